@@ -66,7 +66,7 @@ impl<'window, T: TimeTrait> App<'window, T> {
         self.core.update(
             self.timer.fixed_time_step(),
             &mut self.input,
-            &self.wgpu,
+            &mut self.wgpu,
             &self.window,
         );
     }
@@ -190,6 +190,10 @@ impl<'window, T: TimeTrait> ApplicationHandler for App<'window, T> {
         }
     }
 
+    fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
+        self.core.save_world();
+    }
+
     // emitted after one update
     fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
         if self.exit_requested {
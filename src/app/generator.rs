@@ -0,0 +1,804 @@
+use crate::app::structures::Structure;
+use crate::core::block::*;
+use crate::core::types::{Node, Node64, CHUNK_SIZE, FULL_DEPTH};
+use crate::util::random::{fbm, hash, smoothstep, unit, Rng};
+
+// Terrain lives in the chunk layer y = 0, below is stone and above is air.
+//
+// Decorations (trees, rocks, ore veins) are anchored on fixed grid cells.
+// Each cell's anchor and shape come only from hash(seed, cell), and a chunk
+// stamps every anchor whose reach overlaps it, clipped to its own bounds.
+// A structure crossing a chunk border is therefore built the same way by
+// both chunks no matter which is generated first.
+//
+// Chunks far from the camera are built only down to their LOD depth from
+// sparsely sampled columns, decorations are left out of them.
+//
+// Structure templates use the same idea with one cell per `spacing`, the
+// footprint is kept inside its cell so only cells overlapping the chunk count.
+
+// surface block + subsurface layers
+const SURFACE_DEPTH: i32 = 4;
+const SNOW_LINE: i32 = 185;
+
+const TREE_CELL: i32 = 12;
+const TREE_REACH: i32 = 7;
+const ROCK_CELL: i32 = 20;
+const ROCK_REACH: i32 = 4;
+const VEIN_CELL: i32 = 16;
+const VEIN_REACH: i32 = 28;
+
+// salts keep the noise fields and decoration grids independent
+const HEIGHT_SALT: u64 = 0x1000;
+const MOUNTAIN_SALT: u64 = 0x2000;
+const TEMPERATURE_SALT: u64 = 0x3000;
+const MOISTURE_SALT: u64 = 0x4000;
+const TREE_SALT: u64 = 0x5000;
+const ROCK_SALT: u64 = 0x6000;
+const VEIN_SALT: u64 = 0x7000;
+const LEAF_SALT: u64 = 0x8000;
+const STRUCTURE_SALT: u64 = 0x9000;
+
+// leveled ring around a flattened structure footprint
+const FLATTEN_MARGIN: i32 = 3;
+// air cleared above leveled ground, removes trees and rocks standing on it
+const CLEAR_HEIGHT: i32 = 24;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Biome {
+    Plains,
+    Forest,
+    Desert,
+    Tundra,
+    Mountains,
+}
+
+impl Biome {
+    // (top block, blocks under it)
+    fn surface(self, height: i32) -> (BlockId, BlockId) {
+        match self {
+            Biome::Plains | Biome::Forest => (GRASS, DIRT),
+            Biome::Desert => (SAND, SANDSTONE),
+            Biome::Tundra => (SNOW, DIRT),
+            Biome::Mountains if height > SNOW_LINE => (SNOW, STONE),
+            Biome::Mountains => (STONE, STONE),
+        }
+    }
+
+    fn tree_chance(self) -> f32 {
+        match self {
+            Biome::Forest => 0.6,
+            Biome::Plains => 0.08,
+            Biome::Tundra => 0.15,
+            Biome::Desert | Biome::Mountains => 0.0,
+        }
+    }
+
+    fn rock_chance(self) -> f32 {
+        match self {
+            Biome::Mountains => 0.5,
+            Biome::Desert => 0.25,
+            Biome::Tundra => 0.2,
+            Biome::Plains | Biome::Forest => 0.1,
+        }
+    }
+}
+
+pub struct Generator {
+    seed: u64,
+    structures: Vec<Structure>,
+}
+
+// a structure template stamped at one cell
+struct Placement {
+    // lowest corner of the rotated bounding box
+    corner: (i32, i32, i32),
+    footprint: (i32, i32),
+    rotation: i32,
+}
+
+impl Generator {
+    pub fn new(seed: u64) -> Generator {
+        Generator {
+            seed,
+            structures: Vec::new(),
+        }
+    }
+
+    // earlier templates win where two of them would overlap
+    pub fn add_structures(&mut self, structures: Vec<Structure>) {
+        self.structures.extend(structures);
+    }
+
+    // first air voxel of the column
+    pub fn height(&self, x: i32, z: i32) -> i32 {
+        let (x, z) = (x as f32, z as f32);
+
+        let hills = fbm(self.seed ^ HEIGHT_SALT, x / 384.0, z / 384.0, 5);
+        let mountains = fbm(self.seed ^ MOUNTAIN_SALT, x / 1024.0, z / 1024.0, 3);
+        let ridge = smoothstep(0.55, 0.8, mountains);
+
+        (48.0 + hills * 60.0 + ridge * 110.0) as i32
+    }
+
+    pub fn biome(&self, x: i32, z: i32, height: i32) -> Biome {
+        let (x, z) = (x as f32, z as f32);
+
+        if height > 165 {
+            return Biome::Mountains;
+        }
+
+        let temperature = fbm(self.seed ^ TEMPERATURE_SALT, x / 1536.0, z / 1536.0, 3);
+        let moisture = fbm(self.seed ^ MOISTURE_SALT, x / 1024.0, z / 1024.0, 3);
+
+        if temperature < 0.38 {
+            Biome::Tundra
+        } else if temperature > 0.6 && moisture < 0.45 {
+            Biome::Desert
+        } else if moisture > 0.52 {
+            Biome::Forest
+        } else {
+            Biome::Plains
+        }
+    }
+
+    pub fn generate(&self, pos: (i32, i32, i32)) -> Node {
+        if pos.1 < 0 {
+            return Node::Solid(STONE);
+        }
+        if pos.1 > 0 {
+            return Node::Empty;
+        }
+        self.generate_at((pos.0 * CHUNK_SIZE, 0, pos.2 * CHUNK_SIZE))
+    }
+
+    // chunk sized block of the terrain layer from any world `origin`, the
+    // chunk grid itself only picks which of them are kept
+    fn generate_at(&self, origin: (i32, i32, i32)) -> Node {
+        let columns = Columns::new(self, (origin.0, origin.2), 1);
+        let mut chunk = build(&columns, (0, 0, 0), CHUNK_SIZE, 1);
+
+        let mut writer = ChunkWriter {
+            chunk: &mut chunk,
+            origin,
+        };
+        self.place_veins(&mut writer);
+        self.place_rocks(&mut writer);
+        self.place_trees(&mut writer);
+        self.place_structures(&mut writer);
+
+        chunk
+    }
+
+    // chunk cut at `depth` levels, see FULL_DEPTH
+    pub fn generate_lod(&self, pos: (i32, i32, i32), depth: u32) -> Node {
+        // trees and rocks still show at 4 voxel cells
+        if depth + 1 >= FULL_DEPTH || pos.1 != 0 {
+            let mut chunk = self.generate(pos);
+            chunk.truncate(depth);
+            return chunk;
+        }
+
+        let cell = CHUNK_SIZE >> (2 * depth);
+        let columns = Columns::new(self, (pos.0 * CHUNK_SIZE, pos.2 * CHUNK_SIZE), cell / 4);
+        build(&columns, (0, 0, 0), CHUNK_SIZE, cell)
+    }
+
+    // calls `place` for every anchor whose reach overlaps the chunk
+    fn for_each_anchor(
+        &self,
+        writer: &mut ChunkWriter,
+        cell: i32,
+        reach: i32,
+        salt: u64,
+        mut place: impl FnMut(&mut ChunkWriter, &mut Rng, i32, i32),
+    ) {
+        let (x0, _, z0) = writer.origin;
+        let low_x = (x0 - reach).div_euclid(cell);
+        let high_x = (x0 + CHUNK_SIZE + reach).div_euclid(cell);
+        let low_z = (z0 - reach).div_euclid(cell);
+        let high_z = (z0 + CHUNK_SIZE + reach).div_euclid(cell);
+
+        for cz in low_z..=high_z {
+            for cx in low_x..=high_x {
+                let mut rng = Rng::new(hash(self.seed ^ salt, cx, 0, cz));
+                let x = cx * cell + rng.range(0, cell);
+                let z = cz * cell + rng.range(0, cell);
+                place(writer, &mut rng, x, z);
+            }
+        }
+    }
+
+    fn place_trees(&self, writer: &mut ChunkWriter) {
+        self.for_each_anchor(writer, TREE_CELL, TREE_REACH, TREE_SALT, |writer, rng, x, z| {
+            let ground = self.height(x, z);
+            let biome = self.biome(x, z, ground);
+            if rng.next_f32() >= biome.tree_chance() {
+                return;
+            }
+
+            let trunk = 7 + rng.range(0, 6);
+            let radius = 3 + rng.range(0, 3);
+            let top = ground + trunk;
+
+            if biome == Biome::Tundra {
+                // spruce, shrinking rings of leaves along the trunk
+                for y in (ground + 3)..=(top + 1) {
+                    let r = ((top + 1 - y) * radius / trunk).max(1);
+                    self.leaf_disk(writer, (x, y, z), r);
+                }
+            } else {
+                for dy in -radius..=radius {
+                    self.leaf_disk_round(writer, (x, top + dy / 2, z), radius, dy);
+                }
+            }
+
+            for y in ground..top {
+                writer.set((x, y, z), WOOD);
+            }
+        });
+    }
+
+    fn leaf_disk(&self, writer: &mut ChunkWriter, center: (i32, i32, i32), r: i32) {
+        for dz in -r..=r {
+            for dx in -r..=r {
+                if dx * dx + dz * dz <= r * r + 1 {
+                    writer.set_if_air((center.0 + dx, center.1, center.2 + dz), LEAVES);
+                }
+            }
+        }
+    }
+
+    // one slice of a round canopy, some leaves on the rim are left out
+    fn leaf_disk_round(&self, writer: &mut ChunkWriter, center: (i32, i32, i32), r: i32, dy: i32) {
+        let slice = r * r - dy * dy;
+        for dz in -r..=r {
+            for dx in -r..=r {
+                let d = dx * dx + dz * dz;
+                if d > slice {
+                    continue;
+                }
+
+                let pos = (center.0 + dx, center.1, center.2 + dz);
+                let sparse = unit(hash(self.seed ^ LEAF_SALT, pos.0, pos.1, pos.2)) < 0.35;
+                if d + 2 >= slice && sparse {
+                    continue;
+                }
+                writer.set_if_air(pos, LEAVES);
+            }
+        }
+    }
+
+    fn place_rocks(&self, writer: &mut ChunkWriter) {
+        self.for_each_anchor(writer, ROCK_CELL, ROCK_REACH, ROCK_SALT, |writer, rng, x, z| {
+            let ground = self.height(x, z);
+            let biome = self.biome(x, z, ground);
+            if rng.next_f32() >= biome.rock_chance() {
+                return;
+            }
+
+            let rx = 1 + rng.range(0, 3);
+            let ry = 1 + rng.range(0, 2);
+            let rz = 1 + rng.range(0, 3);
+            let block = if rng.next_f32() < 0.3 { GRAVEL } else { STONE };
+
+            // sunk one voxel into the ground
+            let cy = ground - 1;
+            for dy in -ry..=ry {
+                for dz in -rz..=rz {
+                    for dx in -rx..=rx {
+                        let d = (dx * dx) as f32 / (rx * rx) as f32
+                            + (dy * dy) as f32 / (ry * ry) as f32
+                            + (dz * dz) as f32 / (rz * rz) as f32;
+                        if d <= 1.0 {
+                            writer.set((x + dx, cy + dy, z + dz), block);
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    fn place_veins(&self, writer: &mut ChunkWriter) {
+        self.for_each_anchor(writer, VEIN_CELL, VEIN_REACH, VEIN_SALT, |writer, rng, x, z| {
+            let ground = self.height(x, z);
+            if ground < 24 {
+                return;
+            }
+
+            let mut pos = (x, rng.range(8, ground - 12), z);
+            // iron only shows up deep
+            let block = if pos.1 < 40 && rng.next_f32() < 0.5 {
+                IRON_ORE
+            } else {
+                COAL_ORE
+            };
+
+            let steps = 8 + rng.range(0, 12);
+            for _ in 0..steps {
+                for dy in -1..=1 {
+                    for dz in -1..=1 {
+                        for dx in -1..=1 {
+                            if dx * dx + dy * dy + dz * dz <= 1 {
+                                writer.replace((pos.0 + dx, pos.1 + dy, pos.2 + dz), STONE, block);
+                            }
+                        }
+                    }
+                }
+
+                pos.0 += rng.range(-1, 2);
+                pos.1 += rng.range(-1, 2);
+                pos.2 += rng.range(-1, 2);
+            }
+        });
+    }
+}
+
+impl Generator {
+    fn structure_cell(&self, index: usize) -> i32 {
+        let structure = &self.structures[index];
+        let longest = structure.size.0.max(structure.size.2);
+        structure.rule.spacing.max(longest + 2 * FLATTEN_MARGIN + 1)
+    }
+
+    fn structure_at(&self, index: usize, cx: i32, cz: i32) -> Option<Placement> {
+        let structure = &self.structures[index];
+        let rule = structure.rule;
+        let cell = self.structure_cell(index);
+
+        let mut rng = Rng::new(hash(self.seed ^ STRUCTURE_SALT, cx, index as i32, cz));
+        if rng.next_f32() >= rule.chance {
+            return None;
+        }
+
+        let rotation = if rule.rotate { rng.range(0, 4) } else { 0 };
+        let footprint = structure.footprint(rotation);
+
+        // footprint and leveled ring stay inside the cell
+        let x = cx * cell + FLATTEN_MARGIN + rng.range(0, cell - footprint.0 - 2 * FLATTEN_MARGIN);
+        let z = cz * cell + FLATTEN_MARGIN + rng.range(0, cell - footprint.1 - 2 * FLATTEN_MARGIN);
+
+        let (center_x, center_z) = (x + footprint.0 / 2, z + footprint.1 / 2);
+        let ground = self.height(center_x, center_z);
+        if !rule.biomes.contains(&self.biome(center_x, center_z, ground)) {
+            return None;
+        }
+
+        // sunk deeper where the top would leave the chunk layer
+        let y = (ground - rule.depth).min(CHUNK_SIZE - structure.size.1);
+        if y < 1 {
+            return None;
+        }
+
+        let low = (x - FLATTEN_MARGIN, z - FLATTEN_MARGIN);
+        let high = (
+            x + footprint.0 + FLATTEN_MARGIN,
+            z + footprint.1 + FLATTEN_MARGIN,
+        );
+        if (0..index).any(|other| self.structure_overlaps(other, low, high)) {
+            return None;
+        }
+
+        Some(Placement {
+            corner: (x, y, z),
+            footprint,
+            rotation,
+        })
+    }
+
+    // any placement of template `index` touching the area [low, high)
+    fn structure_overlaps(&self, index: usize, low: (i32, i32), high: (i32, i32)) -> bool {
+        let cell = self.structure_cell(index);
+
+        for cz in low.1.div_euclid(cell)..=(high.1 - 1).div_euclid(cell) {
+            for cx in low.0.div_euclid(cell)..=(high.0 - 1).div_euclid(cell) {
+                let Some(placement) = self.structure_at(index, cx, cz) else {
+                    continue;
+                };
+
+                let (x, _, z) = placement.corner;
+                let (fx, fz) = placement.footprint;
+                let m = FLATTEN_MARGIN;
+                if x - m < high.0 && x + fx + m > low.0 && z - m < high.1 && z + fz + m > low.1 {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    fn place_structures(&self, writer: &mut ChunkWriter) {
+        let (x0, _, z0) = writer.origin;
+
+        for index in 0..self.structures.len() {
+            let cell = self.structure_cell(index);
+
+            for cz in z0.div_euclid(cell)..=(z0 + CHUNK_SIZE - 1).div_euclid(cell) {
+                for cx in x0.div_euclid(cell)..=(x0 + CHUNK_SIZE - 1).div_euclid(cell) {
+                    if let Some(placement) = self.structure_at(index, cx, cz) {
+                        self.stamp(writer, index, &placement);
+                    }
+                }
+            }
+        }
+    }
+
+    fn stamp(&self, writer: &mut ChunkWriter, index: usize, placement: &Placement) {
+        let structure = &self.structures[index];
+        let rule = structure.rule;
+        let (x, y, z) = placement.corner;
+        let (fx, fz) = placement.footprint;
+
+        if rule.flatten {
+            let ground = y + rule.depth;
+            let m = FLATTEN_MARGIN;
+
+            // only the columns inside this chunk
+            let (x0, _, z0) = writer.origin;
+            let xs = (x - m).max(x0)..(x + fx + m).min(x0 + CHUNK_SIZE);
+            let zs = (z - m).max(z0)..(z + fz + m).min(z0 + CHUNK_SIZE);
+
+            for wz in zs {
+                for wx in xs.clone() {
+                    let h = self.height(wx, wz);
+                    let (top, under) = self.biome(wx, wz, h).surface(ground);
+
+                    for wy in h.min(ground - SURFACE_DEPTH)..ground {
+                        let block = if wy == ground - 1 {
+                            top
+                        } else if wy >= ground - SURFACE_DEPTH {
+                            under
+                        } else {
+                            STONE
+                        };
+                        writer.set((wx, wy, wz), block);
+                    }
+                    for wy in ground..h.max(ground) + CLEAR_HEIGHT {
+                        writer.set((wx, wy, wz), AIR);
+                    }
+                }
+            }
+        }
+
+        if rule.carve {
+            for dy in 0..structure.size.1 {
+                for dz in 0..fz {
+                    for dx in 0..fx {
+                        writer.set((x + dx, y + dy, z + dz), AIR);
+                    }
+                }
+            }
+        }
+
+        for &(voxel, block) in &structure.voxels {
+            let (dx, dy, dz) = structure.rotate(voxel, placement.rotation);
+            writer.set((x + dx, y + dy, z + dz), block);
+        }
+    }
+}
+
+// per column data of one chunk from the world `origin`, sampled every `step`
+// columns
+struct Columns {
+    heights: Vec<i32>,
+    surface: Vec<(BlockId, BlockId)>,
+    step: i32,
+}
+
+impl Columns {
+    fn new(generator: &Generator, origin: (i32, i32), step: i32) -> Columns {
+        let samples = CHUNK_SIZE / step;
+        let size = (samples * samples) as usize;
+        let mut heights = Vec::with_capacity(size);
+        let mut surface = Vec::with_capacity(size);
+
+        for z in 0..samples {
+            for x in 0..samples {
+                let wx = origin.0 + x * step;
+                let wz = origin.1 + z * step;
+
+                let height = generator.height(wx, wz);
+                let biome = generator.biome(wx, wz, height);
+
+                heights.push(height);
+                surface.push(biome.surface(height));
+            }
+        }
+
+        Columns {
+            heights,
+            surface,
+            step,
+        }
+    }
+
+    fn index(&self, x: i32, z: i32) -> usize {
+        (x / self.step + z / self.step * (CHUNK_SIZE / self.step)) as usize
+    }
+
+    fn height(&self, x: i32, z: i32) -> i32 {
+        self.heights[self.index(x, z)]
+    }
+
+    // lowest and highest column over a square footprint
+    fn range(&self, x0: i32, z0: i32, size: i32) -> (i32, i32) {
+        let mut low = i32::MAX;
+        let mut high = i32::MIN;
+        for z in (z0..z0 + size).step_by(self.step as usize) {
+            for x in (x0..x0 + size).step_by(self.step as usize) {
+                let h = self.height(x, z);
+                low = low.min(h);
+                high = high.max(h);
+            }
+        }
+        (low, high)
+    }
+
+    fn block(&self, x: i32, y: i32, z: i32) -> BlockId {
+        let i = self.index(x, z);
+        let h = self.heights[i];
+        let (top, under) = self.surface[i];
+
+        if y >= h {
+            AIR
+        } else if y == h - 1 {
+            top
+        } else if y >= h - SURFACE_DEPTH {
+            under
+        } else {
+            STONE
+        }
+    }
+}
+
+// builds the terrain top down, whole air or stone regions stay unsplit,
+// nodes of `cell` size that still hold terrain become Solid
+fn build(columns: &Columns, origin: (i32, i32, i32), size: i32, cell: i32) -> Node {
+    let (low, high) = columns.range(origin.0, origin.2, size);
+    if origin.1 >= high {
+        return Node::Empty;
+    }
+    if origin.1 + size <= low - SURFACE_DEPTH {
+        return Node::Solid(STONE);
+    }
+
+    if size <= cell {
+        // block of the center column at the top of the cell
+        let (cx, cz) = (origin.0 + size / 2, origin.2 + size / 2);
+        let h = columns.height(cx, cz);
+        let y = (h - 1).clamp(origin.1, origin.1 + size - 1).min(h - 1);
+        return Node::Solid(columns.block(cx, y, cz));
+    }
+
+    let mut node = if size == 4 {
+        let mut mask = 0u64;
+        let mut blocks = Box::new([AIR; 64]);
+        for i in 0..64 {
+            let (x, y, z) = (i as i32 % 4, i as i32 / 4 % 4, i as i32 / 16);
+            let block = columns.block(origin.0 + x, origin.1 + y, origin.2 + z);
+            if block != AIR {
+                mask |= 1 << i;
+                blocks[i] = block;
+            }
+        }
+        Node::Leaf(mask, blocks)
+    } else {
+        let child = size / 4;
+        let mut branch = Box::new(Node64::new());
+        for (i, node) in branch.children.iter_mut().enumerate() {
+            let (x, y, z) = (i as i32 % 4, i as i32 / 4 % 4, i as i32 / 16);
+            let child_origin = (
+                origin.0 + x * child,
+                origin.1 + y * child,
+                origin.2 + z * child,
+            );
+            *node = build(columns, child_origin, child, cell);
+        }
+        Node::Branch(branch)
+    };
+
+    node.collapse();
+    node
+}
+
+// world space writes clipped to one chunk
+struct ChunkWriter<'a> {
+    chunk: &'a mut Node,
+    origin: (i32, i32, i32),
+}
+
+impl ChunkWriter<'_> {
+    fn local(&self, pos: (i32, i32, i32)) -> Option<(i32, i32, i32)> {
+        let local = (
+            pos.0 - self.origin.0,
+            pos.1 - self.origin.1,
+            pos.2 - self.origin.2,
+        );
+        let inside = |v: i32| (0..CHUNK_SIZE).contains(&v);
+
+        if inside(local.0) && inside(local.1) && inside(local.2) {
+            Some(local)
+        } else {
+            None
+        }
+    }
+
+    fn set(&mut self, pos: (i32, i32, i32), block: BlockId) {
+        if let Some(local) = self.local(pos) {
+            self.chunk.set(local, block);
+        }
+    }
+
+    fn set_if_air(&mut self, pos: (i32, i32, i32), block: BlockId) {
+        self.replace(pos, AIR, block);
+    }
+
+    fn replace(&mut self, pos: (i32, i32, i32), from: BlockId, to: BlockId) {
+        if let Some(local) = self.local(pos) {
+            if self.chunk.get(local) == from {
+                self.chunk.set(local, to);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED: u64 = 1;
+    // two forest chunks side by side, trees stand on the border x = -768
+    const WEST: (i32, i32, i32) = (-4, 0, -4);
+    const EAST: (i32, i32, i32) = (-3, 0, -4);
+
+    fn bytes(chunk: &Node) -> Vec<u8> {
+        let mut out = Vec::new();
+        chunk.write(&mut out);
+        out
+    }
+
+    #[test]
+    fn chunks_match_in_either_order() {
+        let generator = Generator::new(SEED);
+
+        let west_first = [generator.generate(WEST), generator.generate(EAST)];
+        let east_first = [generator.generate(EAST), generator.generate(WEST)];
+
+        assert_eq!(bytes(&west_first[0]), bytes(&east_first[1]));
+        assert_eq!(bytes(&west_first[1]), bytes(&east_first[0]));
+    }
+
+    // the voxels within reach of the border, as the two chunks built them
+    // and as one chunk centered on the border builds them
+    #[test]
+    fn border_matches_one_piece() {
+        let generator = Generator::new(SEED);
+        let west = generator.generate(WEST);
+        let east = generator.generate(EAST);
+
+        let border = EAST.0 * CHUNK_SIZE;
+        let z0 = EAST.2 * CHUNK_SIZE;
+        let shift = border - CHUNK_SIZE / 2;
+        let whole = generator.generate_at((shift, 0, z0));
+
+        let reach = TREE_REACH.max(ROCK_REACH).max(VEIN_REACH);
+        for x in border - reach..border + reach {
+            for y in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    let split = if x < border {
+                        west.get((x - border + CHUNK_SIZE, y, z))
+                    } else {
+                        east.get((x - border, y, z))
+                    };
+                    let expected = whole.get((x - shift, y, z));
+                    assert_eq!(split, expected, "at {:?}", (x, y, z0 + z));
+                }
+            }
+        }
+
+        // canopies reach over the border from both sides
+        let crossing = |chunk: &Node, x: i32| {
+            (0..CHUNK_SIZE)
+                .flat_map(|y| (0..CHUNK_SIZE).map(move |z| (x, y, z)))
+                .any(|pos| chunk.get(pos) == LEAVES)
+        };
+        assert!(crossing(&west, CHUNK_SIZE - 1));
+        assert!(crossing(&east, 0));
+    }
+
+    #[test]
+    fn structures_stay_inside_the_layer() {
+        use crate::app::structures::StructureRule;
+
+        // a tower taller than any slope leaves room for
+        let height = 120;
+        let mut generator = Generator::new(SEED);
+        generator.add_structures(vec![Structure {
+            size: (3, height, 3),
+            voxels: (0..height).map(|y| ((1, y, 1), STONE)).collect(),
+            rule: StructureRule {
+                spacing: 64,
+                chance: 1.0,
+                biomes: &[
+                    Biome::Plains,
+                    Biome::Forest,
+                    Biome::Desert,
+                    Biome::Tundra,
+                    Biome::Mountains,
+                ],
+                flatten: true,
+                rotate: false,
+                depth: 0,
+                carve: false,
+            },
+        }]);
+
+        let mut sunk = 0;
+        for cz in -16..16 {
+            for cx in -16..16 {
+                if let Some(placement) = generator.structure_at(0, cx, cz) {
+                    let (x, y, z) = placement.corner;
+                    let ground = generator.height(x + 1, z + 1);
+                    assert!(y + height <= CHUNK_SIZE);
+                    if y < ground {
+                        sunk += 1;
+                    }
+                }
+            }
+        }
+        assert!(sunk > 0);
+    }
+
+    #[test]
+    fn mountains_above_the_tree_line() {
+        let generator = Generator::new(SEED);
+        for (x, z) in [(0, 0), (-5000, 700), (12_345, -9876)] {
+            assert_eq!(generator.biome(x, z, 166), Biome::Mountains);
+        }
+        assert_eq!(Biome::Mountains.surface(SNOW_LINE + 1), (SNOW, STONE));
+        assert_eq!(Biome::Mountains.surface(SNOW_LINE), (STONE, STONE));
+    }
+
+    // sparse columns over a wide area, every biome shows up and the column
+    // is topped with that biome's blocks
+    #[test]
+    fn surface_follows_biome() {
+        let generator = Generator::new(SEED);
+        let mut seen = Vec::new();
+
+        for cz in -32..32 {
+            for cx in -32..32 {
+                let origin = (cx * CHUNK_SIZE, cz * CHUNK_SIZE);
+                let columns = Columns::new(&generator, origin, CHUNK_SIZE / 4);
+
+                for z in (0..CHUNK_SIZE).step_by(CHUNK_SIZE as usize / 4) {
+                    for x in (0..CHUNK_SIZE).step_by(CHUNK_SIZE as usize / 4) {
+                        let (wx, wz) = (origin.0 + x, origin.1 + z);
+                        let h = generator.height(wx, wz);
+                        let biome = generator.biome(wx, wz, h);
+                        let (top, under) = biome.surface(h);
+
+                        assert_eq!(columns.block(x, h, z), AIR);
+                        assert_eq!(columns.block(x, h - 1, z), top);
+                        assert_eq!(columns.block(x, h - 2, z), under);
+                        assert_eq!(columns.block(x, h - SURFACE_DEPTH - 1, z), STONE);
+
+                        if !seen.contains(&biome) {
+                            seen.push(biome);
+                        }
+                    }
+                }
+            }
+        }
+
+        for biome in [
+            Biome::Plains,
+            Biome::Forest,
+            Biome::Desert,
+            Biome::Tundra,
+            Biome::Mountains,
+        ] {
+            assert!(seen.contains(&biome), "no {:?} found", biome);
+        }
+    }
+}
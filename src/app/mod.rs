@@ -2,6 +2,10 @@ pub mod app;
 
 pub mod egui;
 pub mod frame_timer;
+pub mod generator;
 pub mod input;
+pub mod region;
+pub mod structures;
+pub mod world;
 
 pub use app::App;
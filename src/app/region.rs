@@ -0,0 +1,485 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::core::types::Node;
+use crate::util::{checksum::crc32, compression};
+
+// Region file: REGION_SIZE^3 chunks in one file.
+//
+// [magic u32][version u32][table: CHUNKS x (first sector u32, sector count u32)]
+// chunk record, starting at a sector boundary:
+// [payload len u32][raw len u32][crc32 of payload u32][compressed payload]
+pub const REGION_SIZE: i32 = 32;
+const CHUNKS: usize = (REGION_SIZE * REGION_SIZE * REGION_SIZE) as usize;
+
+const MAGIC: u32 = u32::from_le_bytes(*b"VXRG");
+// 2: leaves store a block id per voxel
+const VERSION: u32 = 2;
+
+const SECTOR_SIZE: u64 = 512;
+const TABLE_OFFSET: u64 = 8;
+const HEADER_BYTES: u64 = TABLE_OFFSET + CHUNKS as u64 * 8;
+const HEADER_SECTORS: u32 = HEADER_BYTES.div_ceil(SECTOR_SIZE) as u32;
+const RECORD_HEADER: usize = 12;
+
+pub struct RegionFile {
+    file: File,
+    // (first sector, sector count), count 0 = chunk not stored
+    table: Vec<(u32, u32)>,
+    // sector allocation map, used for free space reuse
+    used: Vec<bool>,
+    writable: bool,
+}
+
+impl RegionFile {
+    // created when missing
+    pub fn open(path: &Path) -> io::Result<RegionFile> {
+        Self::open_with(path, true)
+    }
+
+    // for lookups only, nothing on disk changes
+    pub fn open_read_only(path: &Path) -> io::Result<RegionFile> {
+        Self::open_with(path, false)
+    }
+
+    pub fn writable(&self) -> bool {
+        self.writable
+    }
+
+    fn open_with(path: &Path, writable: bool) -> io::Result<RegionFile> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(writable)
+            .create(writable)
+            .truncate(false)
+            .open(path)?;
+
+        let mut table = vec![(0, 0); CHUNKS];
+
+        if file.metadata()?.len() == 0 && writable {
+            let mut header = vec![0u8; (HEADER_SECTORS as u64 * SECTOR_SIZE) as usize];
+            header[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+            header[4..8].copy_from_slice(&VERSION.to_le_bytes());
+            file.write_all(&header)?;
+        } else {
+            let mut header = vec![0u8; HEADER_BYTES as usize];
+            file.seek(SeekFrom::Start(0))?;
+            file.read_exact(&mut header)?;
+
+            if read_u32(&header, 0) != MAGIC || read_u32(&header, 4) != VERSION {
+                return Err(invalid_data("not a region file"));
+            }
+
+            for (i, entry) in table.iter_mut().enumerate() {
+                let at = TABLE_OFFSET as usize + i * 8;
+                *entry = (read_u32(&header, at), read_u32(&header, at + 4));
+            }
+        }
+
+        // whole sectors only, a record cut short by a crash is outside
+        let sectors = (file.metadata()?.len() / SECTOR_SIZE).max(HEADER_SECTORS as u64);
+        let mut used = vec![false; sectors as usize];
+        used[..HEADER_SECTORS as usize].fill(true);
+
+        let mut region = RegionFile {
+            file,
+            table,
+            used,
+            writable,
+        };
+        for i in 0..CHUNKS {
+            let (first, count) = region.table[i];
+            if region.in_file((first, count)) {
+                region.mark(first, count, true);
+            }
+        }
+
+        Ok(region)
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        self.table[index].1 != 0
+    }
+
+    // decompressed chunk bytes, checksum is verified
+    pub fn read(&mut self, index: usize) -> io::Result<Option<Vec<u8>>> {
+        let (first, count) = self.table[index];
+        if count == 0 {
+            return Ok(None);
+        }
+        if !self.in_file((first, count)) {
+            return Err(invalid_data("chunk record points outside the file"));
+        }
+
+        let mut record = vec![0u8; count as usize * SECTOR_SIZE as usize];
+        self.file
+            .seek(SeekFrom::Start(first as u64 * SECTOR_SIZE))?;
+        self.file.read_exact(&mut record)?;
+
+        let payload_len = read_u32(&record, 0) as usize;
+        let raw_len = read_u32(&record, 4) as usize;
+        let checksum = read_u32(&record, 8);
+
+        let payload = record
+            .get(RECORD_HEADER..RECORD_HEADER + payload_len)
+            .ok_or_else(|| invalid_data("chunk record is truncated"))?;
+        if crc32(payload) != checksum {
+            return Err(invalid_data("chunk checksum mismatch"));
+        }
+
+        match compression::decompress(payload, raw_len) {
+            Some(raw) => Ok(Some(raw)),
+            None => Err(invalid_data("chunk failed to decompress")),
+        }
+    }
+
+    pub fn write(&mut self, index: usize, raw: &[u8]) -> io::Result<()> {
+        let payload = compression::compress(raw);
+
+        let mut record = Vec::with_capacity(RECORD_HEADER + payload.len());
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&(raw.len() as u32).to_le_bytes());
+        record.extend_from_slice(&crc32(&payload).to_le_bytes());
+        record.extend_from_slice(&payload);
+
+        let count = (record.len() as u64).div_ceil(SECTOR_SIZE) as u32;
+        record.resize(count as usize * SECTOR_SIZE as usize, 0);
+
+        // never over the old record, it stays whole until the table points at
+        // the new one, a crash in between keeps the chunk as it was saved before
+        let first = self.allocate(count);
+        self.mark(first, count, true);
+
+        self.file.seek(SeekFrom::Start(first as u64 * SECTOR_SIZE))?;
+        self.file.write_all(&record)?;
+        self.file.sync_data()?;
+
+        let old = self.table[index];
+        self.set_entry(index, (first, count))?;
+        if self.in_file(old) {
+            self.mark(old.0, old.1, false);
+        }
+        Ok(())
+    }
+
+    fn set_entry(&mut self, index: usize, entry: (u32, u32)) -> io::Result<()> {
+        self.table[index] = entry;
+
+        let mut bytes = [0u8; 8];
+        bytes[0..4].copy_from_slice(&entry.0.to_le_bytes());
+        bytes[4..8].copy_from_slice(&entry.1.to_le_bytes());
+
+        self.file
+            .seek(SeekFrom::Start(TABLE_OFFSET + index as u64 * 8))?;
+        self.file.write_all(&bytes)
+    }
+
+    // a stored entry whose sectors lie past the header and inside the file,
+    // table entries are not trusted
+    fn in_file(&self, (first, count): (u32, u32)) -> bool {
+        count > 0
+            && first >= HEADER_SECTORS
+            && first
+                .checked_add(count)
+                .is_some_and(|end| end as usize <= self.used.len())
+    }
+
+    fn allocate(&self, count: u32) -> u32 {
+        let mut run = 0;
+        for (i, used) in self.used.iter().enumerate() {
+            if *used {
+                run = 0;
+                continue;
+            }
+            run += 1;
+            if run == count {
+                return i as u32 + 1 - count;
+            }
+        }
+        // append, a trailing free run is extended
+        self.used.len() as u32 - run
+    }
+
+    fn mark(&mut self, first: u32, count: u32, used: bool) {
+        let end = (first + count) as usize;
+        if self.used.len() < end {
+            self.used.resize(end, false);
+        }
+        for sector in first as usize..end {
+            self.used[sector] = used;
+        }
+        // keep the header reserved whatever the table says
+        for sector in self.used.iter_mut().take(HEADER_SECTORS as usize) {
+            *sector = true;
+        }
+    }
+}
+
+pub fn region_coords(chunk: (i32, i32, i32)) -> ((i32, i32, i32), usize) {
+    let region = (
+        chunk.0.div_euclid(REGION_SIZE),
+        chunk.1.div_euclid(REGION_SIZE),
+        chunk.2.div_euclid(REGION_SIZE),
+    );
+    let x = chunk.0.rem_euclid(REGION_SIZE);
+    let y = chunk.1.rem_euclid(REGION_SIZE);
+    let z = chunk.2.rem_euclid(REGION_SIZE);
+
+    (region, (x + y * REGION_SIZE + z * REGION_SIZE * REGION_SIZE) as usize)
+}
+
+pub fn region_path(dir: &Path, region: (i32, i32, i32)) -> PathBuf {
+    dir.join(format!("r.{}.{}.{}.region", region.0, region.1, region.2))
+}
+
+fn region_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "region") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+// Region maintenance, run from the command line:
+//   --verify-regions <world dir>   checks every stored chunk
+//   --compact-regions <world dir>  rewrites region files without gaps
+pub fn verify_regions(dir: &Path) -> io::Result<bool> {
+    let mut all_ok = true;
+
+    for path in region_files(dir)? {
+        let mut region = RegionFile::open_read_only(&path)?;
+        let file_sectors = region.file.metadata()?.len().div_ceil(SECTOR_SIZE) as u32;
+
+        let mut owner: Vec<Option<usize>> = vec![None; region.used.len()];
+        let mut chunks = 0;
+        let mut errors = Vec::new();
+
+        for index in 0..CHUNKS {
+            let (first, count) = region.table[index];
+            if count == 0 {
+                continue;
+            }
+            chunks += 1;
+
+            if !region.in_file((first, count)) {
+                errors.push(format!("chunk {} points outside the file", index));
+                continue;
+            }
+
+            let overlap = (first..first + count).find_map(|s| owner[s as usize]);
+            if let Some(other) = overlap {
+                errors.push(format!("chunk {} overlaps chunk {}", index, other));
+            }
+            for s in first..first + count {
+                owner[s as usize] = Some(index);
+            }
+
+            match region.read(index) {
+                Ok(Some(raw)) => {
+                    let mut cursor = 0;
+                    if Node::read(&raw, &mut cursor).is_err() || cursor != raw.len() {
+                        errors.push(format!("chunk {} has an invalid tree", index));
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => errors.push(format!("chunk {}: {}", index, e)),
+            }
+        }
+
+        let free = region.used.iter().filter(|used| !**used).count();
+        println!(
+            "{}: {} chunks, {} sectors, {} free",
+            path.display(),
+            chunks,
+            file_sectors,
+            free
+        );
+        for error in &errors {
+            println!("    {}", error);
+        }
+        all_ok &= errors.is_empty();
+    }
+
+    Ok(all_ok)
+}
+
+// corrupted chunks are dropped, they regenerate on next load
+pub fn compact_regions(dir: &Path) -> io::Result<()> {
+    for path in region_files(dir)? {
+        let mut region = RegionFile::open_read_only(&path)?;
+        let before = region.file.metadata()?.len();
+
+        let tmp = path.with_extension("compact");
+        let _ = fs::remove_file(&tmp);
+        let mut compacted = RegionFile::open(&tmp)?;
+
+        for index in 0..CHUNKS {
+            if !region.contains(index) {
+                continue;
+            }
+            match region.read(index) {
+                Ok(Some(raw)) => compacted.write(index, &raw)?,
+                Ok(None) => {}
+                Err(e) => println!("{}: dropping chunk {}: {}", path.display(), index, e),
+            }
+        }
+
+        compacted.file.sync_all()?;
+        let after = compacted.file.metadata()?.len();
+        drop(compacted);
+        fs::rename(&tmp, &path)?;
+
+        println!("{}: {} -> {} bytes", path.display(), before, after);
+    }
+
+    Ok(())
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("region_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // a serialized chunk with `voxels` scattered voxels
+    fn chunk(index: usize, voxels: usize) -> Vec<u8> {
+        let mut node = Node::Empty;
+        for i in 0..voxels as i32 {
+            let pos = (i * 37 % 256, i * 11 % 256, (i * 5 + index as i32) % 256);
+            node.set(pos, (i as usize % 200 + index + 1) as u8);
+        }
+
+        let mut bytes = Vec::new();
+        node.write(&mut bytes);
+        bytes
+    }
+
+    // table entries running past the end of the file or the u32 range are
+    // reported, not followed
+    #[test]
+    fn verify_reports_bad_entries() {
+        let dir = temp_dir("bad_entries");
+        let path = region_path(&dir, (0, 0, 0));
+
+        let mut region = RegionFile::open(&path).unwrap();
+        region.write(0, &[7; 1000]).unwrap();
+        region.set_entry(1, (u32::MAX - 1, 4)).unwrap();
+        region.set_entry(2, (HEADER_SECTORS, 1_000_000)).unwrap();
+        region.set_entry(3, (1, 1)).unwrap();
+        drop(region);
+
+        let mut region = RegionFile::open(&path).unwrap();
+        assert_eq!(region.read(0).unwrap(), Some(vec![7; 1000]));
+        for index in 1..4 {
+            assert!(region.read(index).is_err());
+        }
+        assert!(!verify_regions(&dir).unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn verify_reports_damaged_and_shared_records() {
+        let dir = temp_dir("damaged");
+        let path = region_path(&dir, (0, 0, 0));
+
+        let mut region = RegionFile::open(&path).unwrap();
+        region.write(0, &chunk(0, 2000)).unwrap();
+        region.write(1, &chunk(1, 2000)).unwrap();
+        drop(region);
+        assert!(verify_regions(&dir).unwrap());
+
+        // one payload byte flipped
+        let mut region = RegionFile::open(&path).unwrap();
+        let (first, _) = region.table[1];
+        let at = first as u64 * SECTOR_SIZE + RECORD_HEADER as u64;
+        let mut byte = [0u8];
+        region.file.seek(SeekFrom::Start(at)).unwrap();
+        region.file.read_exact(&mut byte).unwrap();
+        region.file.seek(SeekFrom::Start(at)).unwrap();
+        region.file.write_all(&[byte[0] ^ 0xFF]).unwrap();
+        drop(region);
+
+        let mut region = RegionFile::open_read_only(&path).unwrap();
+        let error = region.read(1).unwrap_err();
+        assert!(error.to_string().contains("checksum"));
+        assert!(!verify_regions(&dir).unwrap());
+
+        // a second entry pointing at the sectors of chunk 0
+        let mut region = RegionFile::open(&path).unwrap();
+        let shared = region.table[0];
+        region.write(1, &chunk(1, 2000)).unwrap();
+        region.set_entry(2, shared).unwrap();
+        drop(region);
+        assert!(!verify_regions(&dir).unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn verify_changes_nothing() {
+        let dir = temp_dir("read_only");
+        let path = region_path(&dir, (0, 0, 0));
+
+        let mut region = RegionFile::open(&path).unwrap();
+        region.write(5, &chunk(5, 3000)).unwrap();
+        region.set_entry(6, (u32::MAX - 1, 4)).unwrap();
+        drop(region);
+
+        let before = fs::read(&path).unwrap();
+        assert!(!verify_regions(&dir).unwrap());
+        assert_eq!(fs::read(&path).unwrap(), before);
+        assert_eq!(region_files(&dir).unwrap(), vec![path]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compact_keeps_every_chunk() {
+        let dir = temp_dir("compact");
+        let path = region_path(&dir, (0, 0, 0));
+
+        // rewrites of different sizes leave free sectors between records
+        let mut region = RegionFile::open(&path).unwrap();
+        for index in 0..20 {
+            region.write(index, &chunk(index, 1000)).unwrap();
+        }
+        for index in (0..20).step_by(3) {
+            region.write(index, &chunk(index, 5000)).unwrap();
+        }
+        drop(region);
+        let before = fs::metadata(&path).unwrap().len();
+
+        compact_regions(&dir).unwrap();
+        assert!(fs::metadata(&path).unwrap().len() < before);
+        assert!(verify_regions(&dir).unwrap());
+
+        let mut region = RegionFile::open_read_only(&path).unwrap();
+        assert!(region.used.iter().all(|used| *used));
+        for index in 0..20 {
+            let voxels = if index % 3 == 0 { 5000 } else { 1000 };
+            assert_eq!(region.read(index).unwrap(), Some(chunk(index, voxels)));
+        }
+        assert!(!region.contains(20));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,181 @@
+use crate::app::generator::Biome;
+use crate::core::block::{BlockId, STONE};
+use crate::core::cpu_side_svo::Loader;
+use crate::core::materials::Materials;
+
+// Structure templates are .vox models stamped into generated chunks, their
+// palettes become blocks like any other model's, see core/materials.rs.
+// Files that are missing are skipped, the world generates without them.
+//
+// (name, path, rule)
+const TEMPLATES: [(&str, &str, StructureRule); 4] = [
+    (
+        "house",
+        "structures/house.vox",
+        StructureRule {
+            spacing: 192,
+            chance: 0.35,
+            biomes: &[Biome::Plains, Biome::Forest],
+            flatten: true,
+            rotate: true,
+            depth: 0,
+            carve: false,
+        },
+    ),
+    (
+        "ruin",
+        "structures/ruin.vox",
+        StructureRule {
+            spacing: 320,
+            chance: 0.3,
+            biomes: &[Biome::Plains, Biome::Desert, Biome::Tundra],
+            flatten: false,
+            rotate: true,
+            depth: 2,
+            carve: false,
+        },
+    ),
+    (
+        "dungeon",
+        "structures/dungeon.vox",
+        StructureRule {
+            spacing: 256,
+            chance: 0.5,
+            biomes: &[
+                Biome::Plains,
+                Biome::Forest,
+                Biome::Desert,
+                Biome::Tundra,
+                Biome::Mountains,
+            ],
+            flatten: false,
+            rotate: true,
+            depth: 40,
+            carve: true,
+        },
+    ),
+    (
+        "dragon",
+        "dragon.vox",
+        StructureRule {
+            spacing: 1024,
+            chance: 0.5,
+            biomes: &[Biome::Desert],
+            flatten: true,
+            rotate: true,
+            depth: 0,
+            carve: false,
+        },
+    ),
+];
+
+#[derive(Clone, Copy)]
+pub struct StructureRule {
+    // grid cell size, at most one structure of this kind per cell
+    pub spacing: i32,
+    pub chance: f32,
+    // biome at the anchor must be one of these
+    pub biomes: &'static [Biome],
+    // levels the terrain under and around the footprint to the anchor height
+    pub flatten: bool,
+    // picks one of the four rotations around y
+    pub rotate: bool,
+    // how far the base sits below the ground, buried ruins and dungeons
+    pub depth: i32,
+    // clears the bounding box before stamping, for hollow interiors
+    pub carve: bool,
+}
+
+pub struct Structure {
+    // world axes, y up
+    pub size: (i32, i32, i32),
+    pub voxels: Vec<((i32, i32, i32), BlockId)>,
+    pub rule: StructureRule,
+}
+
+impl Structure {
+    pub fn load(
+        path: &str,
+        rule: StructureRule,
+        materials: &mut Materials,
+    ) -> Result<Structure, ()> {
+        let mut loader = Loader::new();
+        loader.load_data(path)?;
+        let (size, voxels) = loader.voxels()?;
+        let palette = materials.import(&loader.materials()?);
+
+        // .vox is z up, turn it into y up without mirroring
+        let voxels = voxels
+            .into_iter()
+            .map(|((x, y, z), i)| {
+                let block = palette.get(&i).copied().unwrap_or(STONE);
+                ((x, z, size.1 - 1 - y), block)
+            })
+            .collect();
+
+        Ok(Structure {
+            size: (size.0, size.2, size.1),
+            voxels,
+            rule,
+        })
+    }
+
+    // footprint (x, z) after `rotation` quarter turns
+    pub fn footprint(&self, rotation: i32) -> (i32, i32) {
+        if rotation % 2 == 0 {
+            (self.size.0, self.size.2)
+        } else {
+            (self.size.2, self.size.0)
+        }
+    }
+
+    // voxel position inside the rotated footprint
+    pub fn rotate(&self, (x, y, z): (i32, i32, i32), rotation: i32) -> (i32, i32, i32) {
+        let (sx, sz) = (self.size.0, self.size.2);
+        match rotation {
+            1 => (sz - 1 - z, y, x),
+            2 => (sx - 1 - x, y, sz - 1 - z),
+            3 => (z, y, sx - 1 - x),
+            _ => (x, y, z),
+        }
+    }
+}
+
+pub fn load_structures(materials: &mut Materials) -> Vec<Structure> {
+    let mut structures = Vec::new();
+
+    for (name, path, rule) in TEMPLATES {
+        match Structure::load(path, rule, materials) {
+            Ok(structure) => structures.push(structure),
+            Err(_) => eprintln!("Structure {} not loaded from {}", name, path),
+        }
+    }
+
+    structures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::block::PALETTE;
+
+    #[test]
+    fn templates_load() {
+        let mut materials = Materials::new();
+        for (name, path, rule) in TEMPLATES {
+            let structure = Structure::load(path, rule, &mut materials).unwrap();
+            assert!(!structure.voxels.is_empty(), "{} is empty", name);
+
+            // one block per palette entry the model uses
+            let mut loader = Loader::new();
+            loader.load_data(path).unwrap();
+            let entries = loader.materials().unwrap().len();
+
+            let mut blocks: Vec<BlockId> = structure.voxels.iter().map(|(_, b)| *b).collect();
+            blocks.sort();
+            blocks.dedup();
+            assert_eq!(blocks.len(), entries, "{}", name);
+            assert!(blocks.iter().all(|block| *block >= PALETTE));
+        }
+    }
+}
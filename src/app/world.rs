@@ -1,187 +1,242 @@
-use nalgebra::{distance_squared, Vector3};
+use nalgebra::Vector3;
 use std::collections::HashMap;
-use wgpu::rwh::RawDisplayHandle;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 
-const CHUNK_WIDTH_IN_BLOCKS: i32 = 16;
-const BLOCK_WIDTH_IN_VOXELS: i32 = 16;
-const CHUNK_WIDTH: i32 = CHUNK_WIDTH_IN_BLOCKS * BLOCK_WIDTH_IN_VOXELS;
-const MAX_DEPTH: u8 = 7; // log2(256) = 8 (but -1 -> last level is voxel not tree)
+use crate::app::generator::Generator;
+use crate::app::region::{region_coords, region_path, RegionFile};
+use crate::app::structures::load_structures;
+use crate::core::materials::Materials;
+use crate::core::types::{Node, Scene};
+
+// <world dir>/level.dat                 seed
+// <world dir>/regions/r.x.y.z.region    32^3 chunks each, see region.rs
+const LEVEL_FILE: &str = "level.dat";
+const REGION_DIR: &str = "regions";
 
 pub struct World {
     seed: u64,
-    chunks: HashMap<(Vector3<i32>), Box<OctreeNode>>,
+    dir: PathBuf,
+    generator: Generator,
+
+    regions: HashMap<(i32, i32, i32), RegionFile>,
 }
 
 impl World {
-    pub fn new(world_seed: Option<u64>) -> World {
-        let seed = match world_seed {
-            Some(ws) => ws,
-            None => 0,
+    // opens an existing world or creates a new one in `dir`, the structure
+    // palettes are added to `materials`
+    pub fn new(dir: &str, world_seed: Option<u64>, materials: &mut Materials) -> World {
+        let dir = PathBuf::from(dir);
+
+        let seed = match read_level(&dir) {
+            Some(seed) => seed,
+            None => world_seed.unwrap_or(0),
         };
-        let chunks: HashMap<Vector3<i32>, Box<OctreeNode>> = HashMap::new();
 
-        Self { seed, chunks }
-    }
-    pub fn load_unload_chunk(&mut self, origin: Vector3<i32>, radius: i32) {
-        for x in origin.x - radius..=origin.x + radius {
-            for y in origin.y - radius..=origin.y + radius {
-                for z in origin.z - radius..=origin.z + radius {
-                    if self.chunks.contains_key(&Vector3::new(x, y, z)) {}
-                }
-            }
+        if let Err(e) = write_level(&dir, seed) {
+            eprintln!("Failed to write world {:?}: {}", dir, e);
+        }
+
+        let mut generator = Generator::new(seed);
+        generator.add_structures(load_structures(materials));
+
+        Self {
+            seed,
+            dir,
+            generator,
+            regions: HashMap::new(),
         }
     }
-    pub fn load_chunks(&mut self, origin: Vector3<i32>, radius: i32) {
+
+    pub fn region_dir(dir: &str) -> PathBuf {
+        Path::new(dir).join(REGION_DIR)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn has_saved_chunk(&mut self, pos: (i32, i32, i32)) -> bool {
+        let (region, index) = region_coords(pos);
+        matches!(self.read_region(region), Ok(Some(file)) if file.contains(index))
+    }
+
+    // `lod` maps a squared chunk distance to the tree depth kept for the chunk
+    pub fn load_chunks(
+        &mut self,
+        scene: &mut Scene,
+        origin: Vector3<i32>,
+        radius: i32,
+        lod: impl Fn(i32) -> u32,
+    ) {
         let radius_squared = radius * radius;
         let mut new_chunks_coords = Vec::new();
-        (-radius..=radius)
-            .flat_map(|dx| (-radius..=radius).flat_map(move |dy| (-radius..=radius)))
-            .filter(|pos| {
-                let dx = pos.x - origin.x;
-                let dy = pos.y - origin.y;
-                let dz = pos.z - origin.z;
-                let distance_squared = dx * dx + dy * dy + dz * dz;
 
-                distance_squared <= radius_squared
-            })
-            .for_each(|pos| {
-                if !self.chunks.contains_key(&pos) {
-                    new_chunks_coords.push(pos);
+        for dz in -radius..=radius {
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    let distance_squared = dx * dx + dy * dy + dz * dz;
+                    if distance_squared > radius_squared {
+                        continue;
+                    }
+
+                    let pos = (origin.x + dx, origin.y + dy, origin.z + dz);
+                    let depth = lod(distance_squared);
+
+                    if !scene.contains_chunk(pos) || scene.chunk_depth(pos) < depth {
+                        new_chunks_coords.push((pos, depth));
+                    } else if scene.chunk_depth(pos) > depth {
+                        self.coarsen_chunk(scene, pos, depth);
+                    }
                 }
-            });
+            }
+        }
+
+        for (pos, depth) in new_chunks_coords {
+            // saved changes take priority over generation
+            let chunk = match self.load_chunk(pos) {
+                Some(mut chunk) => {
+                    chunk.truncate(depth);
+                    chunk
+                }
+                None => self.generator.generate_lod(pos, depth),
+            };
+            scene.add_lod_chunk(chunk, pos, depth);
+        }
+    }
+
+    // edits are saved before the detail is thrown away
+    fn coarsen_chunk(&mut self, scene: &mut Scene, pos: (i32, i32, i32), depth: u32) {
+        let Some((mut chunk, modified)) = scene.remove_chunk(pos) else {
+            return;
+        };
+        if modified {
+            if let Err(e) = self.write_chunk(pos, &chunk) {
+                eprintln!("Failed to save chunk {:?}: {}", pos, e);
+            }
+        }
 
-        new_chunks_coords.into_iter().for_each(|pos| {
-            self.chunks.insert(pos, Box::new(Self::generate_chunk(pos)));
-        });
+        chunk.truncate(depth);
+        scene.add_lod_chunk(chunk, pos, depth);
     }
-    pub fn unload_chunks(&mut self, origin: Vector3<i32>, radius: i32) {
-        // rewrite into extract_if when possible
 
+    pub fn unload_chunks(&mut self, scene: &mut Scene, origin: Vector3<i32>, radius: i32) {
         let radius_squared = radius * radius;
         let mut removed_chunks = Vec::new();
 
-        // Collect positions of chunks to be removed
-        let to_remove: Vec<Vector3<i32>> = self
-            .chunks
-            .iter()
-            .filter(|(pos, _)| {
-                let dx = pos.x - origin.x;
-                let dy = pos.y - origin.y;
-                let dz = pos.z - origin.z;
+        let to_remove: Vec<(i32, i32, i32)> = scene
+            .chunk_coords()
+            .into_iter()
+            .filter(|pos| {
+                let dx = pos.0 - origin.x;
+                let dy = pos.1 - origin.y;
+                let dz = pos.2 - origin.z;
                 let distance_squared = dx * dx + dy * dy + dz * dz;
 
-                distance_squared > radius_squared // Mark for removal
+                distance_squared > radius_squared
             })
-            .map(|(pos, _)| *pos) // Extract positions
             .collect();
 
-        // Remove from HashMap and store removed elements
         for pos in to_remove {
-            if let Some(chunk) = self.chunks.remove(&pos) {
-                removed_chunks.push((pos, chunk));
+            if let Some((chunk, modified)) = scene.remove_chunk(pos) {
+                // untouched chunks can be regenerated or reloaded
+                if modified {
+                    removed_chunks.push((pos, chunk));
+                }
             }
         }
 
-        // add different structure to record changes
-        // Self::save_changes(removed_chunks);
+        self.save_changes(removed_chunks);
     }
-    fn save_changes(chunks: Vec<(Vector3<i32>, OctreeNode)>) {}
-    fn generate_chunk(pos: Vector3<i32>) -> OctreeNode {
-        let sphere_center = pos.cast::<f32>() + Vector3::new(7.5, 7.5, 7.5);
-        let radius = 4f32;
-
-        let mut chunk = OctreeNode::new(pos, 0);
-        let mut voxel_coordinates = vec![];
-        for x in 0..32 {
-            for y in 0..32 {
-                for z in 0..32 {
-                    let voxel_pos = Vector3::new(x, y, z);
-                    if ((pos + voxel_pos).cast::<f32>() - sphere_center).norm_squared() > radius {
-                        continue;
-                    }
-                    // is inside the sphere:
-                    voxel_coordinates.push(voxel_pos);
+
+    // writes every edited chunk that is still loaded, used on exit
+    pub fn save_all(&mut self, scene: &mut Scene) {
+        let changed: Vec<(i32, i32, i32)> = scene.take_modified();
+
+        for pos in changed {
+            if let Some(chunk) = scene.get_chunk(pos) {
+                if let Err(e) = self.write_chunk(pos, chunk) {
+                    eprintln!("Failed to save chunk {:?}: {}", pos, e);
                 }
             }
         }
-        chunk.fill_tree(&voxel_coordinates);
-        return chunk;
     }
-}
-
-struct OctreeNode {
-    pos: Vector3<i32>,
-    depth: u8,
-    sub_tree: [Node; 8],
-}
 
-impl OctreeNode {
-    pub fn new(pos: Vector3<i32>, depth: u8) -> OctreeNode {
-        Self {
-            pos: pos,
-            depth: 0,
-            sub_tree: [
-                Node::Empty,
-                Node::Empty,
-                Node::Empty,
-                Node::Empty,
-                Node::Empty,
-                Node::Empty,
-                Node::Empty,
-                Node::Empty,
-            ],
+    fn save_changes(&mut self, chunks: Vec<((i32, i32, i32), Node)>) {
+        for (pos, chunk) in chunks {
+            if let Err(e) = self.write_chunk(pos, &chunk) {
+                eprintln!("Failed to save chunk {:?}: {}", pos, e);
+            }
         }
     }
-    pub fn fill_tree(&mut self, v_coord: &Vec<Vector3<i32>>) {
-        for v in v_coord {
-            self.rec_fill(&v);
-        }
+
+    fn write_chunk(&mut self, pos: (i32, i32, i32), chunk: &Node) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        chunk.write(&mut bytes);
+
+        let (region, index) = region_coords(pos);
+        self.write_region(region)?.write(index, &bytes)
     }
-    fn rec_fill(&mut self, v: &Vector3<i32>) {
-        let mut index = 0;
-        if v.x >= CHUNK_WIDTH / 2i32.pow(self.depth as u32 + 1) {
-            index |= 1
-        }
-        if v.y >= CHUNK_WIDTH / 2i32.pow(self.depth as u32 + 1) {
-            index |= 2
-        }
-        if v.z >= CHUNK_WIDTH / 2i32.pow(self.depth as u32 + 1) {
-            index |= 4
-        }
 
-        self.sub_tree[index] = match &mut self.sub_tree[index] {
-            Node::Empty if self.depth < MAX_DEPTH => {
-                let mut octant = Self::create_octant(self.pos, self.depth, index);
-                octant.rec_fill(v);
-                Node::SubTree(Box::new(octant))
+    fn load_chunk(&mut self, pos: (i32, i32, i32)) -> Option<Node> {
+        let (region, index) = region_coords(pos);
+
+        let read = self
+            .read_region(region)
+            .and_then(|file| file.map_or(Ok(None), |file| file.read(index)));
+        let bytes = match read {
+            Ok(bytes) => bytes?,
+            Err(e) => {
+                eprintln!("Failed to read chunk {:?}, regenerating: {}", pos, e);
+                return None;
             }
-            Node::Empty => Node::Full(Voxel {
-                color: Vector3::new(0u8, 0u8, 0u8),
-            }),
-            Node::SubTree(octant) => {
-                octant.rec_fill(v);
-                return;
-            }
-            Node::Full(_) => return,
         };
+
+        let mut cursor = 0;
+        match Node::read(&bytes, &mut cursor) {
+            Ok(chunk) => Some(chunk),
+            Err(e) => {
+                eprintln!("Corrupted chunk {:?}, regenerating: {}", pos, e);
+                None
+            }
+        }
     }
-    fn create_octant(parent_pos: Vector3<i32>, parent_depth: u8, octant_i: usize) -> OctreeNode {
-        let offset = CHUNK_WIDTH / 2i32.pow(parent_depth as u32 + 1);
-        let x = parent_pos.x + if octant_i & 1 != 0 { offset } else { 0 };
-        let y = parent_pos.z + if octant_i & 2 != 0 { offset } else { 0 };
-        let z = parent_pos.y + if octant_i & 4 != 0 { offset } else { 0 };
-        let new_pos = Vector3::new(x, y, z);
-
-        return OctreeNode::new(new_pos, parent_depth - 1);
+
+    // None when the region was never written, nothing is created
+    fn read_region(&mut self, region: (i32, i32, i32)) -> io::Result<Option<&mut RegionFile>> {
+        if !self.regions.contains_key(&region) {
+            let path = region_path(&self.dir.join(REGION_DIR), region);
+            if !path.exists() {
+                return Ok(None);
+            }
+            self.regions
+                .insert(region, RegionFile::open_read_only(&path)?);
+        }
+        Ok(self.regions.get_mut(&region))
+    }
+
+    // opened again for writing if it was only read so far
+    fn write_region(&mut self, region: (i32, i32, i32)) -> io::Result<&mut RegionFile> {
+        if !self.regions.get(&region).is_some_and(RegionFile::writable) {
+            let dir = self.dir.join(REGION_DIR);
+            fs::create_dir_all(&dir)?;
+
+            let file = RegionFile::open(&region_path(&dir, region))?;
+            self.regions.insert(region, file);
+        }
+        Ok(self.regions.get_mut(&region).unwrap())
     }
 }
 
-enum Node {
-    Empty,
-    SubTree(Box<OctreeNode>),
-    Full(Voxel),
+fn read_level(dir: &Path) -> Option<u64> {
+    let text = fs::read_to_string(dir.join(LEVEL_FILE)).ok()?;
+    text.lines()
+        .find_map(|line| line.strip_prefix("seed "))
+        .and_then(|seed| seed.trim().parse().ok())
 }
 
-struct Voxel {
-    color: Vector3<u8>,
+fn write_level(dir: &Path, seed: u64) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join(LEVEL_FILE), format!("seed {}\n", seed))
 }
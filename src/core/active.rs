@@ -0,0 +1,108 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::core::types::{split_voxel, Scene, FULL_DEPTH};
+
+type Coords = (i32, i32, i32);
+
+pub const NEIGHBORS: [Coords; 6] = [
+    (1, 0, 0),
+    (-1, 0, 0),
+    (0, 1, 0),
+    (0, -1, 0),
+    (0, 0, 1),
+    (0, 0, -1),
+];
+
+// Voxels a simulation has to look at next step, grouped by chunk. Chunks
+// without awake voxels are never visited.
+pub struct ActiveCells {
+    cells: HashMap<Coords, HashSet<Coords>>,
+}
+
+impl ActiveCells {
+    pub fn new() -> Self {
+        Self {
+            cells: HashMap::new(),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.cells.values().map(|cells| cells.len()).sum()
+    }
+
+    pub fn wake(&mut self, pos: Coords) {
+        let (chunk, _) = split_voxel(pos);
+        self.cells.entry(chunk).or_default().insert(pos);
+    }
+
+    // the voxel and its six neighbors
+    pub fn wake_around(&mut self, pos: Coords) {
+        self.wake(pos);
+        for n in NEIGHBORS {
+            self.wake(add(pos, n));
+        }
+    }
+
+    // removes up to `max` awake voxels, chunks that are gone or only kept
+    // at lower detail are dropped
+    pub fn take(&mut self, scene: &Scene, max: usize) -> Vec<Coords> {
+        self.cells.retain(|chunk, cells| {
+            !cells.is_empty()
+                && scene.contains_chunk(*chunk)
+                && scene.chunk_depth(*chunk) == FULL_DEPTH
+        });
+
+        let mut taken = Vec::new();
+        for chunk_cells in self.cells.values_mut() {
+            let take = (max - taken.len()).min(chunk_cells.len());
+            let cells: Vec<_> = chunk_cells.iter().copied().take(take).collect();
+            for pos in &cells {
+                chunk_cells.remove(pos);
+            }
+            taken.extend(cells);
+            if taken.len() >= max {
+                break;
+            }
+        }
+        taken
+    }
+}
+
+pub fn add(a: Coords, b: Coords) -> Coords {
+    (a.0 + b.0, a.1 + b.1, a.2 + b.2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::types::{Node, CHUNK_SIZE};
+
+    #[test]
+    fn take_is_bounded_and_skips_unloaded_chunks() {
+        let mut scene = Scene::new();
+        scene.add_chunk(Node::Empty, (0, 0, 0));
+
+        let mut active = ActiveCells::new();
+        for x in 0..10 {
+            active.wake((x, 0, 0));
+        }
+        // chunk not in the scene
+        active.wake((CHUNK_SIZE * 3, 0, 0));
+        assert_eq!(active.count(), 11);
+
+        assert_eq!(active.take(&scene, 4).len(), 4);
+        assert_eq!(active.count(), 6);
+        assert_eq!(active.take(&scene, 100).len(), 6);
+        assert_eq!(active.count(), 0);
+        assert!(active.take(&scene, 100).is_empty());
+    }
+
+    #[test]
+    fn waking_twice_counts_once() {
+        let mut active = ActiveCells::new();
+        active.wake_around((0, 0, 0));
+        active.wake_around((1, 0, 0));
+        // both centers plus 5 + 5 neighbors not shared
+        assert_eq!(active.count(), 12);
+    }
+}
@@ -0,0 +1,431 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::core::block::{AIR, CELL, DYING_CELL};
+use crate::core::leaf_bits::{leaf_cells, leaf_origin, shift_x, shift_y, shift_z, split_leaf};
+use crate::core::types::Scene;
+use crate::util::random::Rng;
+
+// 3D cellular automata over the 26 voxels around each cell. Alive cells are
+// CELL voxels, with more than two states a dying cell stays as DYING_CELL
+// for a few steps, blocks births and does not count as a neighbor.
+//
+// Cells are kept as one u64 per 4x4x4 leaf, same bit order as Node::Leaf, and
+// neighbor counts are added up for all 64 cells at once with bit-sliced
+// counters. A step reads the front buffer, fills the back one and swaps.
+
+type Coords = (i32, i32, i32);
+
+// 26 neighbors need 5 bit counters
+const COUNT_BITS: usize = 5;
+// Bays' 3D life
+pub const LIFE: &str = "4-5/5/2/M";
+
+// seeded soup is this dense
+const SEED_DENSITY: f32 = 0.35;
+
+#[derive(Clone, Copy)]
+pub struct Rule {
+    // bit n set: n alive neighbors keep a cell / make one
+    survive: u32,
+    birth: u32,
+    // 2 is plain alive / dead
+    states: u8,
+}
+
+impl Rule {
+    // "survive/birth/states/M" with counts as lists and ranges, "4/4/5/M" or
+    // "4-5/5/2/M". Only the Moore (26 neighbor) neighborhood is supported.
+    pub fn parse(text: &str) -> Result<Rule, ()> {
+        let parts: Vec<&str> = text.trim().split('/').collect();
+        let [survive, birth, states, neighborhood] = parts[..] else {
+            return Err(());
+        };
+        if neighborhood != "M" {
+            return Err(());
+        }
+
+        let states: u8 = states.parse().map_err(|_| ())?;
+        if states < 2 {
+            return Err(());
+        }
+
+        Ok(Rule {
+            survive: parse_counts(survive)?,
+            birth: parse_counts(birth)?,
+            states,
+        })
+    }
+}
+
+fn parse_counts(text: &str) -> Result<u32, ()> {
+    let mut counts = 0;
+    for part in text.split(',').filter(|p| !p.is_empty()) {
+        let (low, high) = match part.split_once('-') {
+            Some((low, high)) => (low, high),
+            None => (part, part),
+        };
+        let low: u32 = low.parse().map_err(|_| ())?;
+        let high: u32 = high.parse().map_err(|_| ())?;
+        if low > high || high > 26 {
+            return Err(());
+        }
+
+        for n in low..=high {
+            counts |= 1 << n;
+        }
+    }
+    Ok(counts)
+}
+
+pub struct Automaton {
+    rule: Rule,
+    // ticks between steps
+    rate: u64,
+    tick: u64,
+
+    // alive cells per leaf, leaf coords are voxel coords >> 2
+    front: HashMap<Coords, u64>,
+    back: HashMap<Coords, u64>,
+    // steps left for every dying cell
+    dying: HashMap<Coords, u8>,
+}
+
+impl Automaton {
+    pub fn new(rule: Rule, rate: u64) -> Self {
+        Self {
+            rule,
+            rate: rate.max(1),
+            tick: 0,
+            front: HashMap::new(),
+            back: HashMap::new(),
+            dying: HashMap::new(),
+        }
+    }
+
+    pub fn alive_count(&self) -> u32 {
+        self.front.values().map(|bits| bits.count_ones()).sum()
+    }
+
+    // fills air in a cube around `center` with random alive cells,
+    // returns the voxels that changed
+    pub fn seed(
+        &mut self,
+        scene: &mut Scene,
+        center: Coords,
+        radius: i32,
+        seed: u64,
+    ) -> Vec<Coords> {
+        let mut rng = Rng::new(seed);
+        let mut changed = Vec::new();
+
+        for z in -radius..=radius {
+            for y in -radius..=radius {
+                for x in -radius..=radius {
+                    let pos = (center.0 + x, center.1 + y, center.2 + z);
+                    if rng.next_f32() > SEED_DENSITY || scene.get_voxel(pos) != AIR {
+                        continue;
+                    }
+                    if scene.set_voxel(pos, CELL) {
+                        let (leaf, bit) = split_leaf(pos);
+                        *self.front.entry(leaf).or_default() |= 1 << bit;
+                        changed.push(pos);
+                    }
+                }
+            }
+        }
+        changed
+    }
+
+    // returns the voxels that changed
+    pub fn update(&mut self, scene: &mut Scene) -> Vec<Coords> {
+        self.tick += 1;
+        if !self.tick.is_multiple_of(self.rate) || (self.front.is_empty() && self.dying.is_empty())
+        {
+            return Vec::new();
+        }
+
+        let mut changed = Vec::new();
+        self.sync(scene);
+        self.age(scene, &mut changed);
+        self.step(scene, &mut changed);
+        changed
+    }
+
+    // cells edited away since the last step are dead
+    fn sync(&mut self, scene: &Scene) {
+        self.front.retain(|leaf, bits| {
+            let cells = scene.leaf_mask(leaf_origin(*leaf), |b| b == CELL);
+            *bits &= cells;
+            *bits != 0
+        });
+    }
+
+    fn age(&mut self, scene: &mut Scene, changed: &mut Vec<Coords>) {
+        let mut gone = Vec::new();
+        for (pos, steps) in self.dying.iter_mut() {
+            *steps -= 1;
+            if *steps == 0 {
+                gone.push(*pos);
+            }
+        }
+
+        for pos in gone {
+            self.dying.remove(&pos);
+            if scene.get_voxel(pos) == DYING_CELL && scene.set_voxel(pos, AIR) {
+                changed.push(pos);
+            }
+        }
+    }
+
+    fn step(&mut self, scene: &mut Scene, changed: &mut Vec<Coords>) {
+        // leaves with cells and the ones next to them
+        let mut candidates = HashSet::new();
+        for leaf in self.front.keys() {
+            for z in -1..=1 {
+                for y in -1..=1 {
+                    for x in -1..=1 {
+                        candidates.insert((leaf.0 + x, leaf.1 + y, leaf.2 + z));
+                    }
+                }
+            }
+        }
+
+        self.back.clear();
+        let mut born = Vec::new();
+        let mut died = Vec::new();
+
+        for leaf in candidates {
+            let origin = leaf_origin(leaf);
+            if !scene.is_editable(origin) {
+                continue;
+            }
+
+            let counts = self.neighbor_counts(leaf);
+            let alive = self.front.get(&leaf).copied().unwrap_or(0);
+            let occupied = scene.leaf_mask(origin, |b| b != AIR);
+
+            let survive = alive & matches(&counts, self.rule.survive);
+            let birth = !occupied & matches(&counts, self.rule.birth);
+
+            let next = survive | birth;
+            if next != 0 {
+                self.back.insert(leaf, next);
+            }
+            if birth != 0 {
+                born.push((leaf, birth));
+            }
+            if alive & !survive != 0 {
+                died.push((leaf, alive & !survive));
+            }
+        }
+        std::mem::swap(&mut self.front, &mut self.back);
+
+        for (leaf, bits) in born {
+            for pos in leaf_cells(leaf, bits) {
+                if scene.set_voxel(pos, CELL) {
+                    changed.push(pos);
+                }
+            }
+        }
+
+        let dying_steps = self.rule.states - 2;
+        let block = if dying_steps > 0 { DYING_CELL } else { AIR };
+        for (leaf, bits) in died {
+            for pos in leaf_cells(leaf, bits) {
+                if scene.set_voxel(pos, block) {
+                    changed.push(pos);
+                }
+                if dying_steps > 0 {
+                    self.dying.insert(pos, dying_steps);
+                }
+            }
+        }
+    }
+
+    // bit-sliced alive neighbor counts, counts[i] holds bit i of every cell's count
+    fn neighbor_counts(&self, leaf: Coords) -> [u64; COUNT_BITS] {
+        let bits = |x, y, z| {
+            self.front
+                .get(&(leaf.0 + x, leaf.1 + y, leaf.2 + z))
+                .copied()
+                .unwrap_or(0)
+        };
+
+        let mut counts = [0u64; COUNT_BITS];
+        for dz in -1..=1 {
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    if (dx, dy, dz) == (0, 0, 0) {
+                        continue;
+                    }
+
+                    // cells shifted by (dx, dy, dz), one axis at a time pulling
+                    // in the faces of the leaves next to this one
+                    let mut shifted = [[0u64; 3]; 3];
+                    for (z, row) in shifted.iter_mut().enumerate() {
+                        for (y, value) in row.iter_mut().enumerate() {
+                            let (y, z) = (y as i32 - 1, z as i32 - 1);
+                            *value = shift_x(bits(0, y, z), bits(dx, y, z), dx);
+                        }
+                    }
+                    let mut planes = [0u64; 3];
+                    for (z, plane) in planes.iter_mut().enumerate() {
+                        let next = (dy + 1) as usize;
+                        *plane = shift_y(shifted[z][1], shifted[z][next], dy);
+                    }
+                    let neighbor = shift_z(planes[1], planes[(dz + 1) as usize], dz);
+
+                    add_bits(&mut counts, neighbor);
+                }
+            }
+        }
+        counts
+    }
+}
+
+// cells whose neighbor count is in `set`
+fn matches(counts: &[u64; COUNT_BITS], set: u32) -> u64 {
+    let mut result = 0;
+    for n in 0..27 {
+        if set & (1 << n) == 0 {
+            continue;
+        }
+        let mut equal = !0u64;
+        for (bit, plane) in counts.iter().enumerate() {
+            equal &= if n & (1 << bit) != 0 { *plane } else { !*plane };
+        }
+        result |= equal;
+    }
+    result
+}
+
+// ripple carry add of one bit per cell
+fn add_bits(counts: &mut [u64; COUNT_BITS], mut carry: u64) {
+    for plane in counts.iter_mut() {
+        let next = *plane & carry;
+        *plane ^= carry;
+        carry = next;
+        if carry == 0 {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::block::STONE;
+    use crate::core::types::Node;
+
+    // the soup covers 4..=12 on every axis, leaf borders at 4, 8 and 12
+    const CENTER: Coords = (8, 8, 8);
+    const RADIUS: i32 = 4;
+    // three steps grow the soup by up to three voxels
+    const STEPS: i32 = 3;
+    const REACH: i32 = RADIUS + STEPS + 1;
+
+    #[test]
+    fn parses_rules() {
+        let rule = Rule::parse("4/4/5/M").unwrap();
+        assert_eq!((rule.survive, rule.birth, rule.states), (1 << 4, 1 << 4, 5));
+
+        let rule = Rule::parse(LIFE).unwrap();
+        assert_eq!(
+            (rule.survive, rule.birth, rule.states),
+            (0b110000, 1 << 5, 2)
+        );
+
+        let rule = Rule::parse(" 2,6-7/1,3/10/M ").unwrap();
+        assert_eq!(rule.survive, (1 << 2) | (1 << 6) | (1 << 7));
+        assert_eq!(rule.birth, (1 << 1) | (1 << 3));
+
+        for bad in [
+            "",
+            "4/4/5",
+            "4/4/5/N",
+            "4/4/1/M",
+            "4/4/x/M",
+            "5-4/4/5/M",
+            "4/27/5/M",
+            "a/4/5/M",
+            "4/4/5/M/M",
+        ] {
+            assert!(Rule::parse(bad).is_err(), "{:?} parsed", bad);
+        }
+    }
+
+    // next alive cells counted one voxel at a time
+    fn reference(scene: &Scene, rule: Rule) -> HashSet<Coords> {
+        let (low, high) = (CENTER.0 - REACH, CENTER.0 + REACH);
+        let alive = |pos: Coords| scene.get_voxel(pos) == CELL;
+
+        let mut next = HashSet::new();
+        for z in low..=high {
+            for y in low..=high {
+                for x in low..=high {
+                    let mut count = 0;
+                    for (dx, dy, dz) in neighbors() {
+                        count += alive((x + dx, y + dy, z + dz)) as u32;
+                    }
+                    let pos = (x, y, z);
+                    let survives = alive(pos) && rule.survive & (1 << count) != 0;
+                    let born = scene.get_voxel(pos) == AIR && rule.birth & (1 << count) != 0;
+                    if survives || born {
+                        next.insert(pos);
+                    }
+                }
+            }
+        }
+        next
+    }
+
+    fn neighbors() -> impl Iterator<Item = Coords> {
+        (-1..=1)
+            .flat_map(|z| (-1..=1).flat_map(move |y| (-1..=1).map(move |x| (x, y, z))))
+            .filter(|d| *d != (0, 0, 0))
+    }
+
+    fn cells(scene: &Scene) -> HashSet<Coords> {
+        let (low, high) = (CENTER.0 - REACH, CENTER.0 + REACH);
+        let mut cells = HashSet::new();
+        for z in low..=high {
+            for y in low..=high {
+                for x in low..=high {
+                    if scene.get_voxel((x, y, z)) == CELL {
+                        cells.insert((x, y, z));
+                    }
+                }
+            }
+        }
+        cells
+    }
+
+    fn matches_reference(text: &str) {
+        let rule = Rule::parse(text).unwrap();
+        let mut scene = Scene::new();
+        scene.add_chunk(Node::Empty, (0, 0, 0));
+        // a wall through the soup, births are blocked there
+        for z in 2..14 {
+            for y in 2..14 {
+                scene.set_voxel((7, y, z), STONE);
+            }
+        }
+
+        let mut automaton = Automaton::new(rule, 1);
+        assert!(!automaton.seed(&mut scene, CENTER, RADIUS, 7).is_empty());
+
+        for step in 0..STEPS {
+            let expected = reference(&scene, rule);
+            automaton.update(&mut scene);
+            assert_eq!(cells(&scene), expected, "{} step {}", text, step);
+            assert_eq!(automaton.alive_count() as usize, expected.len());
+        }
+    }
+
+    #[test]
+    fn step_matches_per_voxel_counts() {
+        matches_reference(LIFE);
+        matches_reference("4/4/5/M");
+        matches_reference("2-6/3-5/2/M");
+    }
+}
@@ -0,0 +1,71 @@
+use crate::core::registry::registry;
+
+// Block ids stored per voxel in chunk leaves, 0 is always air. What each id
+// does comes from the registry, see core/registry.rs and blocks.txt.
+pub type BlockId = u8;
+
+pub const AIR: BlockId = 0;
+pub const STONE: BlockId = 1;
+pub const DIRT: BlockId = 2;
+pub const GRASS: BlockId = 3;
+pub const SAND: BlockId = 4;
+pub const SANDSTONE: BlockId = 5;
+pub const SNOW: BlockId = 6;
+pub const GRAVEL: BlockId = 7;
+pub const WOOD: BlockId = 8;
+pub const LEAVES: BlockId = 9;
+pub const COAL_ORE: BlockId = 10;
+pub const IRON_ORE: BlockId = 11;
+// cellular automaton cells, see core/automata.rs
+pub const CELL: BlockId = 12;
+pub const DYING_CELL: BlockId = 13;
+// .vox palette entries are given ids from here up, see core/materials.rs
+pub const PALETTE: BlockId = 64;
+
+pub fn name(block: BlockId) -> &'static str {
+    &registry().get(block).name
+}
+
+// falls when nothing solid is below
+pub fn is_loose(block: BlockId) -> bool {
+    registry().get(block).loose
+}
+
+// collides and holds up what rests on it
+pub fn is_solid(block: BlockId) -> bool {
+    registry().get(block).solid
+}
+
+// resistance to explosions, 0 breaks anywhere in the blast, 1 only close
+// to its center
+pub fn hardness(block: BlockId) -> f32 {
+    registry().get(block).hardness
+}
+
+// Fluids use one id per level, base + level - 1. Levels 1..=FULL hold that
+// much fluid, SOURCE never runs dry. Water and lava meeting turn into stone.
+pub const WATER: BlockId = 16;
+pub const LAVA: BlockId = 32;
+
+pub const FULL: u8 = 8;
+pub const SOURCE: u8 = 9;
+
+// (base id, level)
+pub fn fluid(block: BlockId) -> Option<(BlockId, u8)> {
+    let base = registry().get(block).fluid?;
+    Some((base, block - base + 1))
+}
+
+pub fn fluid_block(base: BlockId, level: u8) -> BlockId {
+    base + level - 1
+}
+
+// light passes through
+pub fn is_transparent(block: BlockId) -> bool {
+    registry().get(block).transparent
+}
+
+// block light level given off, 0..=15
+pub fn emission(block: BlockId) -> u8 {
+    registry().get(block).emission
+}
@@ -0,0 +1,226 @@
+use crate::gpu::types::{GpuBvhNode, GpuObject};
+
+// Bounding volume hierarchy over everything the shader traces: chunks,
+// voxel objects and model instances. Built top down by splitting the longest
+// axis of the centers at the median, the objects are reordered so every leaf
+// owns a contiguous run of them.
+
+// objects per leaf
+const LEAF_SIZE: usize = 4;
+
+type Bounds = ([f32; 3], [f32; 3]);
+
+// returns the nodes, root first, the objects in leaf order and the position
+// every object ended up at
+pub fn build(objects: &[GpuObject]) -> (Vec<GpuBvhNode>, Vec<GpuObject>, Vec<usize>) {
+    let bounds: Vec<Bounds> = objects.iter().map(|object| object.bounds()).collect();
+    let mut order: Vec<usize> = (0..objects.len()).collect();
+
+    let mut nodes = vec![GpuBvhNode::default()];
+    if !order.is_empty() {
+        split(&bounds, &mut order, 0, 0, &mut nodes);
+    }
+
+    let sorted = order.iter().map(|i| objects[*i]).collect();
+    let mut slots = vec![0; objects.len()];
+    for (slot, i) in order.iter().enumerate() {
+        slots[*i] = slot;
+    }
+    (nodes, sorted, slots)
+}
+
+// new boxes after objects moved in place, the tree keeps its shape. Children
+// come after their parent, going backwards sees them first.
+pub fn refit(nodes: &mut [GpuBvhNode], objects: &[GpuObject]) {
+    if objects.is_empty() {
+        return;
+    }
+
+    for node in (0..nodes.len()).rev() {
+        let (first, count) = (nodes[node].first as usize, nodes[node].count as usize);
+        let (low, high) = if count > 0 {
+            enclose(objects[first..first + count].iter().map(|o| o.bounds()))
+        } else {
+            enclose(nodes[first..first + 2].iter().map(|c| (c.low, c.high)))
+        };
+        nodes[node].low = low;
+        nodes[node].high = high;
+    }
+}
+
+// fills nodes[node] with the objects in `order`, which start at `first` in
+// the final order
+fn split(
+    bounds: &[Bounds],
+    order: &mut [usize],
+    first: usize,
+    node: usize,
+    nodes: &mut Vec<GpuBvhNode>,
+) {
+    let (low, high) = enclose(order.iter().map(|i| bounds[*i]));
+    nodes[node].low = low;
+    nodes[node].high = high;
+
+    if order.len() <= LEAF_SIZE {
+        nodes[node].first = first as u32;
+        nodes[node].count = order.len() as u32;
+        return;
+    }
+
+    let center = |i: usize, axis: usize| bounds[i].0[axis] + bounds[i].1[axis];
+    let (center_low, center_high) = enclose(order.iter().map(|i| {
+        let c = [0, 1, 2].map(|axis| center(*i, axis));
+        (c, c)
+    }));
+    let extent = [0, 1, 2].map(|axis| center_high[axis] - center_low[axis]);
+    let axis = if extent[0] >= extent[1] && extent[0] >= extent[2] {
+        0
+    } else if extent[1] >= extent[2] {
+        1
+    } else {
+        2
+    };
+
+    let mid = order.len() / 2;
+    order.select_nth_unstable_by(mid, |a, b| center(*a, axis).total_cmp(&center(*b, axis)));
+
+    // children next to each other
+    let left = nodes.len();
+    nodes.push(GpuBvhNode::default());
+    nodes.push(GpuBvhNode::default());
+    nodes[node].first = left as u32;
+
+    let (a, b) = order.split_at_mut(mid);
+    split(bounds, a, first, left, nodes);
+    split(bounds, b, first + mid, left + 1, nodes);
+}
+
+fn enclose(boxes: impl Iterator<Item = Bounds>) -> Bounds {
+    let mut low = [f32::MAX; 3];
+    let mut high = [f32::MIN; 3];
+    for (l, h) in boxes {
+        for axis in 0..3 {
+            low[axis] = low[axis].min(l[axis]);
+            high[axis] = high[axis].max(h[axis]);
+        }
+    }
+    (low, high)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // chunks scattered over a few hundred chunks, root is the input index
+    fn chunks(count: u32) -> Vec<GpuObject> {
+        (0..count)
+            .map(|i| {
+                let i32 = i as i32;
+                let coords = ((i32 * 7) % 13, (i32 * 3) % 5, (i32 * 11) % 17 - 8);
+                GpuObject::chunk(coords, i)
+            })
+            .collect()
+    }
+
+    fn contains(outer: Bounds, inner: Bounds) -> bool {
+        (0..3).all(|axis| outer.0[axis] <= inner.0[axis] && inner.1[axis] <= outer.1[axis])
+    }
+
+    // walks the tree from the root, checks every box and returns the leaf
+    // runs in the order they were reached
+    fn check(nodes: &[GpuBvhNode], objects: &[GpuObject]) -> Vec<(usize, usize)> {
+        let mut runs = vec![];
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let bounds = (nodes[node].low, nodes[node].high);
+            let (first, count) = (nodes[node].first as usize, nodes[node].count as usize);
+            if count > 0 {
+                for object in &objects[first..first + count] {
+                    assert!(
+                        contains(bounds, object.bounds()),
+                        "leaf {node} misses an object"
+                    );
+                }
+                runs.push((first, count));
+            } else {
+                for child in [first, first + 1] {
+                    assert!(child > node, "child {child} before its parent {node}");
+                    let child_bounds = (nodes[child].low, nodes[child].high);
+                    assert!(
+                        contains(bounds, child_bounds),
+                        "node {node} misses child {child}"
+                    );
+                    stack.push(child);
+                }
+            }
+        }
+        runs
+    }
+
+    #[test]
+    fn every_object_once() {
+        let objects = chunks(37);
+        let (nodes, sorted, slots) = build(&objects);
+
+        let mut runs = check(&nodes, &sorted);
+        runs.sort();
+        let mut next = 0;
+        for (first, count) in runs {
+            assert_eq!(first, next, "leaf runs overlap or leave a gap");
+            assert!(count <= LEAF_SIZE);
+            next += count;
+        }
+        assert_eq!(next, objects.len());
+
+        let mut roots: Vec<u32> = sorted.iter().map(|o| o.root[0]).collect();
+        roots.sort();
+        assert_eq!(roots, (0..37).collect::<Vec<_>>());
+
+        // slots[i] is where object i ended up
+        for (i, slot) in slots.iter().enumerate() {
+            assert_eq!(sorted[*slot].root[0], i as u32);
+        }
+    }
+
+    #[test]
+    fn refit_follows_moved_objects() {
+        let objects = chunks(37);
+        let (mut nodes, sorted, _) = build(&objects);
+
+        // every object jumps somewhere else, the tree keeps its shape
+        let moved: Vec<GpuObject> = sorted
+            .iter()
+            .map(|object| {
+                let i = object.root[0] as i32;
+                GpuObject::chunk((-(i * 5) % 19, i % 7 - 3, (i * 13) % 11), object.root[0])
+            })
+            .collect();
+        let old = (nodes[0].low, nodes[0].high);
+        assert!(moved.iter().any(|o| !contains(old, o.bounds())));
+
+        refit(&mut nodes, &moved);
+        check(&nodes, &moved);
+    }
+
+    #[test]
+    fn empty_and_single() {
+        let (mut nodes, sorted, slots) = build(&[]);
+        assert_eq!(nodes.len(), 1);
+        assert_eq!((nodes[0].first, nodes[0].count), (0, 0));
+        assert!(sorted.is_empty() && slots.is_empty());
+        refit(&mut nodes, &[]);
+        assert_eq!((nodes[0].first, nodes[0].count), (0, 0));
+
+        let objects = chunks(1);
+        let (mut nodes, sorted, slots) = build(&objects);
+        assert_eq!(nodes.len(), 1);
+        assert_eq!((nodes[0].first, nodes[0].count), (0, 1));
+        assert_eq!(slots, vec![0]);
+        assert_eq!((nodes[0].low, nodes[0].high), objects[0].bounds());
+
+        let moved = [GpuObject::chunk((3, -1, 2), 0)];
+        refit(&mut nodes, &moved);
+        assert_eq!((nodes[0].low, nodes[0].high), moved[0].bounds());
+        assert_eq!(sorted.len(), 1);
+    }
+}
@@ -0,0 +1,107 @@
+use std::f32::consts::TAU;
+
+// World time of day, advanced by fixed updates. Time is a fraction of a day,
+// 0.0 is midnight, 0.25 sunrise in the east (+x), 0.5 noon, 0.75 sunset.
+
+// sun path leans south so noon is not straight up
+const TILT: f32 = 0.35;
+
+const DAY_SKY: [f32; 3] = [0.45, 0.65, 0.95];
+const NIGHT_SKY: [f32; 3] = [0.01, 0.015, 0.04];
+const DUSK_SKY: [f32; 3] = [0.9, 0.45, 0.2];
+
+const NOON_SUN: [f32; 3] = [1.0, 0.97, 0.9];
+const DUSK_SUN: [f32; 3] = [1.0, 0.55, 0.3];
+// moonlight compared to noon
+const MOON_STRENGTH: f32 = 0.15;
+
+pub struct Clock {
+    time: f64,
+    // seconds in one day
+    day_length: f64,
+}
+
+impl Clock {
+    pub fn new(day_length: f64) -> Self {
+        Self {
+            // start in the morning
+            time: 0.3,
+            day_length,
+        }
+    }
+
+    pub fn tick(&mut self, delta_time: f64) {
+        self.time = (self.time + delta_time / self.day_length).rem_euclid(1.0);
+    }
+
+    pub fn time_of_day(&self) -> f64 {
+        self.time
+    }
+
+    pub fn set_time_of_day(&mut self, time: f64) {
+        self.time = time.rem_euclid(1.0);
+    }
+
+    // unit vector towards the sun
+    pub fn sun_direction(&self) -> [f32; 3] {
+        let angle = (self.time as f32 - 0.25) * TAU;
+        normalize([angle.cos(), angle.sin(), TILT])
+    }
+
+    // the moon is opposite the sun
+    pub fn moon_direction(&self) -> [f32; 3] {
+        let [x, y, z] = self.sun_direction();
+        [-x, -y, -z]
+    }
+
+    // 0 at night, 1 once the sun is well above the horizon
+    pub fn daylight(&self) -> f32 {
+        smoothstep(-0.1, 0.25, self.sun_direction()[1])
+    }
+
+    pub fn moonlight(&self) -> f32 {
+        smoothstep(-0.1, 0.25, self.moon_direction()[1]) * MOON_STRENGTH
+    }
+
+    // surfaces are lit from whichever is brighter
+    pub fn light_direction(&self) -> [f32; 3] {
+        if self.daylight() >= self.moonlight() {
+            self.sun_direction()
+        } else {
+            self.moon_direction()
+        }
+    }
+
+    pub fn sky_color(&self) -> [f32; 3] {
+        let sky = mix(NIGHT_SKY, DAY_SKY, self.daylight());
+        mix(sky, DUSK_SKY, self.dusk() * 0.6)
+    }
+
+    pub fn sun_color(&self) -> [f32; 3] {
+        mix(NOON_SUN, DUSK_SUN, self.dusk())
+    }
+
+    // 1 while the sun is at the horizon
+    fn dusk(&self) -> f32 {
+        let height = self.sun_direction()[1].abs();
+        1.0 - smoothstep(0.0, 0.3, height)
+    }
+}
+
+fn normalize([x, y, z]: [f32; 3]) -> [f32; 3] {
+    let length = (x * x + y * y + z * z).sqrt();
+    [x / length, y / length, z / length]
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn mix(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+    [
+        a[0] + (b[0] - a[0]) * t,
+        a[1] + (b[1] - a[1]) * t,
+        a[2] + (b[2] - a[2]) * t,
+    ]
+}
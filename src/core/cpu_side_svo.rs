@@ -1,30 +1,79 @@
-use std::fs::File;
-use std::io::Write;
-
-
 use dot_vox::{load, DotVoxData};
+use std::collections::HashMap;
 
 use crate::{
     core::types::Scene,
-    gpu::types::{GpuNode, GpuRoot, GpuSceneHeader},
+    gpu::types::{GpuNode, GpuRoot, GpuSceneHeader, SOLID_FLAG},
 };
 
-use crate::core::types::{Node, Node64};
+use crate::core::instances::Instances;
+use crate::core::light::{LightField, FULL_SUN};
+use crate::core::materials::Material;
+use crate::core::objects::Objects;
+
+use crate::core::block::{BlockId, AIR};
+use crate::core::types::{Node, Node64, CHUNK_SIZE};
 const BIT_MASK: u8 = 0b0000_0011;
 pub struct Loader {
     data: Option<DotVoxData>,
 }
 
+// size, voxels as (position, palette index)
+pub type VoxModel = ((i32, i32, i32), Vec<((i32, i32, i32), u8)>);
+
 impl Loader {
     pub fn new() -> Self {
         Loader { data: None }
     }
 
-    pub fn load_data(&mut self, path: &str) {
-        self.data = Some(load(path).unwrap());
+    pub fn load_data(&mut self, path: &str) -> Result<(), ()> {
+        self.data = Some(load(path).map_err(|_| ())?);
+        Ok(())
+    }
+
+    // size and voxels of the first model, in .vox axes (z up)
+    pub fn voxels(&self) -> Result<VoxModel, ()> {
+        let Some(data) = &self.data else {
+            return Err(());
+        };
+        let model = data.models.first().ok_or(())?;
+
+        let size = (model.size.x as i32, model.size.y as i32, model.size.z as i32);
+        let voxels = model
+            .voxels
+            .iter()
+            .map(|v| ((v.x as i32, v.y as i32, v.z as i32), v.i))
+            .collect();
+
+        Ok((size, voxels))
+    }
+
+    // palette entries the first model uses, with their MATL properties
+    pub fn materials(&self) -> Result<Vec<(u8, Material)>, ()> {
+        let Some(data) = &self.data else {
+            return Err(());
+        };
+        let model = data.models.first().ok_or(())?;
+
+        let mut used = [false; 256];
+        for v in &model.voxels {
+            used[v.i as usize] = true;
+        }
+
+        // voxel i is palette entry i, its MATL chunk has the id i + 1
+        let materials = (0..=255u8)
+            .filter(|i| used[*i as usize])
+            .map(|i| {
+                let color = data.palette.get(i as usize).map(|c| (*c).into());
+                let matl = data.materials.iter().find(|m| m.id == i as u32 + 1);
+                (i, Material::from_vox(color.unwrap_or([255; 4]), matl))
+            })
+            .collect();
+        Ok(materials)
     }
 
-    pub fn make_chunk(&mut self) -> Result<Node, ()> {
+    // `block` maps the palette index of every voxel to its block
+    pub fn make_chunk(&mut self, block: impl Fn(u8) -> BlockId) -> Result<Node, ()> {
         if let None = &self.data {
             return Err(());
         }
@@ -45,22 +94,23 @@ impl Loader {
                 let offset = (x + 4 * y + 16 * z) as usize;
 
                 if let Node::Empty = node {
-                    *node = Node::Branch(Node64::new());
+                    *node = Node::Branch(Box::new(Node64::new()));
                 }
 
                 if let Node::Branch(ref mut branch) = node {
-                    node = &mut *branch.children[offset];
+                    node = &mut branch.children[offset];
                 }
             }
 
-            let bit: u64 = 1 << (v.x & BIT_MASK + 4 * (v.y & BIT_MASK) + 16 * (v.z & BIT_MASK));
+            let index = ((v.x & BIT_MASK) + 4 * (v.y & BIT_MASK) + 16 * (v.z & BIT_MASK)) as usize;
 
             if let Node::Empty = node {
-                *node = Node::Leaf(0);
+                *node = Node::Leaf(0, Box::new([AIR; 64]));
             }
 
-            if let Node::Leaf(vox) = node {
-                *vox |= bit;
+            if let Node::Leaf(vox, blocks) = node {
+                *vox |= 1 << index;
+                blocks[index] = block(v.i);
             }
         }
 
@@ -71,7 +121,34 @@ impl Loader {
 pub struct Stager {
     pub header: GpuSceneHeader,
     pub gpu_nodes: Vec<GpuNode>,
-    colors: Vec<u32>, // change
+    // one brick per leaf, see BRICK
+    pub gpu_bricks: Vec<u32>,
+
+    // node index of every chunk's root, empty chunks are left out
+    pub chunk_roots: Vec<((i32, i32, i32), u32)>,
+    // node index of every object's root, in Objects::iter order
+    pub object_roots: Vec<u32>,
+    // node index of every model's root, by model id
+    pub model_roots: Vec<u32>,
+
+    // flattened chunks, objects and models, [0] is the root and bases count
+    // from it
+    chunks: HashMap<(i32, i32, i32), FlatChunk>,
+    objects: HashMap<u32, FlatChunk>,
+    models: Vec<FlatChunk>,
+}
+
+// Leaves point at their brick with `base`: one light byte per voxel in the
+// first 16 u32, full sunlight in unlit chunks, then one block id byte per
+// voxel. Solid nodes keep one light value in `base` flagged with LIGHT_SET.
+pub const LIGHT_SET: u32 = 1 << 8;
+const BRICK: usize = 32;
+
+struct FlatChunk {
+    nodes: Vec<GpuNode>,
+    // leaf node indices, their base is a brick and not a node
+    leaves: Vec<usize>,
+    bricks: Vec<u32>,
 }
 
 impl Stager {
@@ -79,137 +156,232 @@ impl Stager {
         Self {
             header: GpuSceneHeader::default(),
             gpu_nodes: Vec::new(),
-            colors: Vec::new(),
+            gpu_bricks: Vec::new(),
+            chunk_roots: Vec::new(),
+            object_roots: Vec::new(),
+            model_roots: Vec::new(),
+            chunks: HashMap::new(),
+            objects: HashMap::new(),
+            models: Vec::new(),
         }
     }
 
-    pub fn stage(&mut self, chunks: &Scene, start: (i32, i32, i32), end: (i32, i32, i32)) {
+    // only `dirty` chunks and new objects or models are flattened, the rest
+    // is reused. `bounds` is the chunk range from Scene::bounds
+    pub fn stage(
+        &mut self,
+        scene: &Scene,
+        light: &LightField,
+        objects: &Objects,
+        instances: &Instances,
+        bounds: ((i32, i32, i32), (i32, i32, i32)),
+        dirty: &[(i32, i32, i32)],
+    ) {
+        let (start, end) = bounds;
+        self.chunks.retain(|coords, _| scene.contains_chunk(*coords));
+        for coords in dirty {
+            self.chunks.remove(coords);
+        }
+
         let mut nodes = Vec::new();
         nodes.push(GpuNode::default()); // push NULL value
+        let mut bricks = Vec::new();
 
-        reserve_roots(&mut nodes, start, end);
-
+        self.chunk_roots.clear();
         for z in start.2..end.2 {
             for y in start.1..end.1 {
                 for x in start.0..end.0 {
-                    let offset = root_offset((x, y, z), start, end);
-                    self.flatten(chunks.get_chunk((x, y, z)), &mut nodes, offset);
+                    let Some(chunk) = scene.get_chunk((x, y, z)) else {
+                        continue;
+                    };
+                    if let Node::Empty = chunk {
+                        continue;
+                    }
+                    let flat = self
+                        .chunks
+                        .entry((x, y, z))
+                        .or_insert_with(|| {
+                            let origin = (x * CHUNK_SIZE, y * CHUNK_SIZE, z * CHUNK_SIZE);
+                            let lighting = light.is_lit((x, y, z)).then_some((scene, light));
+                            flatten(chunk, origin, lighting)
+                        });
+
+                    self.chunk_roots.push(((x, y, z), nodes.len() as u32));
+                    append_tree(flat, &mut nodes, &mut bricks);
                 }
             }
         }
 
-        let mut file = File::create("output_voxel.txt").unwrap();
-        for item in &nodes {
-            let _ = writeln!(file, "{:?}", item);
+        // objects follow the chunks, their trees never change once made
+        let ids: Vec<u32> = objects.iter().map(|object| object.id).collect();
+        self.objects.retain(|id, _| ids.contains(id));
+
+        self.object_roots.clear();
+        for object in objects.iter() {
+            let flat = self
+                .objects
+                .entry(object.id)
+                .or_insert_with(|| flatten(&object.root, (0, 0, 0), None));
+
+            self.object_roots.push(nodes.len() as u32);
+            append_tree(flat, &mut nodes, &mut bricks);
+        }
+
+        // each model once, however many instances point at it
+        for model in &instances.models()[self.models.len()..] {
+            self.models.push(flatten(&model.root, (0, 0, 0), None));
+        }
+
+        self.model_roots.clear();
+        for flat in &self.models {
+            self.model_roots.push(nodes.len() as u32);
+            append_tree(flat, &mut nodes, &mut bricks);
         }
 
         let header = GpuSceneHeader {
-            start: [start.0, start.1, start.2, 0],
-            end: [end.0, end.1, end.2, 0],
             size: nodes.len() as u32,
+            ..Default::default()
         };
         self.header = header;
         self.gpu_nodes = nodes;
-
+        self.gpu_bricks = bricks;
     }
+}
 
-    fn flatten(&self, chunk: Option<&Node>, nodes: &mut Vec<GpuNode>, root_offset: usize) {
-        use std::collections::VecDeque;
+// the whole tree appended, root first, with bases moved along
+fn append_tree(flat: &FlatChunk, nodes: &mut Vec<GpuNode>, bricks: &mut Vec<u32>) {
+    let relocated = relocate(flat, nodes.len() as u32, bricks.len());
 
-        if chunk.is_none() {
-            return;
-        }
+    nodes.extend_from_slice(&relocated);
+    bricks.extend_from_slice(&flat.bricks);
+}
 
-        let mut queue = VecDeque::new();
+// node bases moved by `shift`, bricks to follow the `bricks_len` u32s already
+// staged
+fn relocate(flat: &FlatChunk, shift: u32, bricks_len: usize) -> Vec<GpuNode> {
+    let brick_shift = (bricks_len / BRICK) as u32;
 
-        match chunk.unwrap() {
+    let mut relocated: Vec<GpuNode> = flat.nodes.clone();
+    for node in relocated.iter_mut() {
+        // branches point at children, their base is never 0
+        if node.color_index & SOLID_FLAG == 0 && node.base != 0 {
+            node.base += shift;
+        }
+    }
+    for leaf in &flat.leaves {
+        relocated[*leaf].base = flat.nodes[*leaf].base + brick_shift;
+    }
+    relocated
+}
+
+// `origin` is the world position of the root, without lighting every leaf is
+// in full sunlight
+fn flatten(
+    root: &Node,
+    origin: (i32, i32, i32),
+    lighting: Option<(&Scene, &LightField)>,
+) -> FlatChunk {
+    use std::collections::VecDeque;
+
+    let mut nodes = vec![GpuNode::default()];
+    let mut leaves = Vec::new();
+    let mut bricks = Vec::new();
+
+    // children are appended breadth first
+    let mut queue = VecDeque::new();
+    queue.push_back((root, 0, origin, CHUNK_SIZE));
+
+    while let Some((node, index, origin, size)) = queue.pop_front() {
+        match node {
             Node::Empty => {}
             Node::Branch(branch) => {
                 let mut mask: u64 = 0;
-                let mut children: Vec<&Node> = Vec::new();
-    
+                let mut children = Vec::new();
+
+                let child_size = size / 4;
                 for (i, child) in branch.children.iter().enumerate() {
-                    if let Node::Empty = child.as_ref() {
+                    if let Node::Empty = child {
                         continue;
                     }
                     mask |= 1 << i;
-                    children.push(child.as_ref());
+
+                    let i = i as i32;
+                    let child_origin = (
+                        origin.0 + (i % 4) * child_size,
+                        origin.1 + (i / 4 % 4) * child_size,
+                        origin.2 + (i / 16) * child_size,
+                    );
+                    children.push((child, child_origin));
                 }
-    
-                nodes[root_offset].mask_l = mask as u32;
-                nodes[root_offset].mask_h = (mask >> 32) as u32;
-                nodes[root_offset].base = nodes.len() as u32;
-    
-                // reserve space for children
+
                 let base = nodes.len();
+
+                nodes[index].mask_l = mask as u32;
+                nodes[index].mask_h = (mask >> 32) as u32;
+                nodes[index].base = base as u32;
+                // color of the whole subtree when seen from far away
+                nodes[index].color_index = node.representative() as u32;
+
+                // reserve space for children
                 for _ in 0..children.len() {
                     nodes.push(GpuNode::default());
                 }
-    
-                for (i, child) in children.into_iter().enumerate() {
-                    queue.push_back((child, i + base));
+
+                for (i, (child, child_origin)) in children.into_iter().enumerate() {
+                    queue.push_back((child, i + base, child_origin, child_size));
                 }
             }
-            Node::Leaf(mask) => {
-                let mask = *mask; 
-                nodes[root_offset].mask_l = mask as u32;
-                nodes[root_offset].mask_h = (mask >> 32) as u32;
+            Node::Leaf(mask, blocks) => {
+                nodes[index] = GpuNode::set_leaf(*mask, node.representative() as u32);
+                nodes[index].base = (bricks.len() / BRICK) as u32;
+                leaves.push(index);
+
+                let values = match lighting {
+                    Some((scene, light)) => light.leaf_light(scene, origin, *mask, blocks),
+                    None => [FULL_SUN; 64],
+                };
+                let words = values.chunks(4).chain(blocks.chunks(4));
+                bricks.extend(words.map(|v| u32::from_le_bytes([v[0], v[1], v[2], v[3]])));
             }
-        }
-    
-        // BFS traversal
-        while let Some((node, index)) = queue.pop_front() {
-            match node {
-                Node::Empty => {}
-                Node::Branch(branch) => {
-                    let mut mask: u64 = 0;
-                    let mut children: Vec<&Node> = Vec::new();
-    
-                    for (i, child) in branch.children.iter().enumerate() {
-                        if let Node::Empty = child.as_ref() {
-                            continue;
-                        }
-                        mask |= 1 << i;
-                        children.push(child.as_ref());
-                    }
-    
-                    let base = nodes.len();
-    
-                    nodes[index].mask_l = mask as u32;
-                    nodes[index].mask_h = (mask >> 32) as u32;
-                    nodes[index].base = base as u32;
-    
-                    // reserve space for children
-                    for _ in 0..children.len() {
-                        nodes.push(GpuNode::default());
-                    }
-    
-                    for (i, child) in children.into_iter().enumerate() {
-                        queue.push_back((child, i + base));
-                    }
-                }
-                Node::Leaf(mask) => {
-                    nodes[index] = GpuNode::set_leaf(*mask, 10);
+            Node::Solid(block) => {
+                nodes[index] = GpuNode::set_solid(*block as u32);
+                if let Some((scene, light)) = lighting {
+                    nodes[index].base = LIGHT_SET | light.node_light(scene, origin, size) as u32;
                 }
             }
         }
     }
-}
 
-fn reserve_roots(nodes: &mut Vec<GpuNode>, start: (i32, i32, i32), end: (i32, i32, i32)) {
-    for z in start.2..end.2 {
-        for y in start.1..end.1 {
-            for x in start.0..end.0 {
-                nodes.push(GpuNode::default());
-            }
-        }
+    FlatChunk {
+        nodes,
+        leaves,
+        bricks,
     }
 }
 
-fn root_offset(coord: (i32, i32, i32), start: (i32, i32, i32), end: (i32, i32, i32)) -> usize {
-    let x_size = end.0 - start.0;
-    let y_size = end.1 - start.1;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::block::{IRON_ORE, STONE};
 
-    let offset = coord.0 + coord.1 * x_size + coord.2 * x_size * y_size;
-    return offset as usize
+    #[test]
+    fn leaf_bricks_keep_every_block() {
+        let mut chunk = Node::Empty;
+        for x in 0..4 {
+            chunk.set((x, 0, 0), STONE);
+        }
+        chunk.set((2, 0, 0), IRON_ORE);
+
+        let flat = flatten(&chunk, (0, 0, 0), None);
+        assert_eq!(flat.leaves.len(), 1);
+        let brick = flat.nodes[flat.leaves[0]].base as usize * BRICK;
+
+        let bytes: Vec<u8> = flat.bricks[brick..brick + BRICK]
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect();
+        assert!(bytes[..64].iter().all(|light| *light == FULL_SUN));
+        assert_eq!(bytes[64..68], [STONE, STONE, IRON_ORE, STONE]);
+        assert!(bytes[68..].iter().all(|block| *block == AIR));
+    }
 }
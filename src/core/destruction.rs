@@ -0,0 +1,87 @@
+use nalgebra::Vector3;
+
+use crate::core::active::add;
+use crate::core::block::{hardness, is_solid, AIR};
+use crate::core::objects::{Objects, VoxelObject};
+use crate::core::regions::detached;
+use crate::core::types::Scene;
+use crate::util::random::Rng;
+
+// Explosions carve a rough sphere, harder blocks only break closer to the
+// center. Pieces the blast cut loose from the ground become voxel objects
+// that tumble down and settle back into the grid, tiny ones are removed.
+
+// how much the blast radius varies per voxel, fraction of the radius
+const JITTER: f32 = 0.2;
+// the hardest block breaks only this far out, fraction of the radius
+const HARD_REACH: f32 = 0.4;
+// searched around the blast for pieces it cut loose
+const DEBRIS_MARGIN: i32 = 24;
+// smaller pieces vanish instead of falling
+const MIN_DEBRIS: u32 = 8;
+// radians per second debris may start spinning with
+const DEBRIS_SPIN: f32 = 1.5;
+
+type Coords = (i32, i32, i32);
+
+// carves the blast and frees what it cut loose, every change is made before
+// returning so the caller restages once, returns the voxels that changed
+pub fn explode(
+    scene: &mut Scene,
+    objects: &mut Objects,
+    center: Coords,
+    radius: i32,
+    seed: u64,
+) -> Vec<Coords> {
+    let mut rng = Rng::new(seed);
+    let mut carved = Vec::new();
+
+    for z in -radius..=radius {
+        for y in -radius..=radius {
+            for x in -radius..=radius {
+                let pos = add(center, (x, y, z));
+                let block = scene.get_voxel(pos);
+                if !is_solid(block) {
+                    continue;
+                }
+
+                let distance = ((x * x + y * y + z * z) as f32).sqrt();
+                let jitter = 1.0 + (rng.next_f32() * 2.0 - 1.0) * JITTER;
+                let reach = radius as f32 * jitter * (1.0 - hardness(block) * (1.0 - HARD_REACH));
+                if distance < reach {
+                    carved.push((pos, AIR));
+                }
+            }
+        }
+    }
+    let mut changed = scene.set_voxels(&carved);
+
+    let margin = radius + DEBRIS_MARGIN;
+    let low = add(center, (-margin, -margin, -margin));
+    let high = add(center, (margin + 1, margin + 1, margin + 1));
+
+    let mut removed = Vec::new();
+    for piece in detached(scene, low, high) {
+        let voxels: Vec<_> = piece
+            .voxels()
+            .map(|pos| (pos, scene.get_voxel(pos)))
+            .collect();
+        removed.extend(voxels.iter().map(|(pos, _)| (*pos, AIR)));
+
+        if piece.count() < MIN_DEBRIS {
+            continue;
+        }
+        // too big to move stays where it is
+        let Some(mut object) = VoxelObject::from_voxels(&voxels) else {
+            removed.truncate(removed.len() - voxels.len());
+            continue;
+        };
+
+        let mut spin = || (rng.next_f32() * 2.0 - 1.0) * DEBRIS_SPIN;
+        object.angular_velocity = Vector3::new(spin(), spin(), spin());
+        object.settle = true;
+        objects.add(object);
+    }
+    changed.extend(scene.set_voxels(&removed));
+    changed
+}
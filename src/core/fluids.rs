@@ -0,0 +1,323 @@
+use crate::core::active::{add, ActiveCells};
+use crate::core::block::{fluid, fluid_block, BlockId, AIR, FULL, LAVA, SOURCE, STONE};
+use crate::core::types::Scene;
+
+// Cellular fluid flow. Every fixed update counts as a tick, a fluid voxel
+// first pours into the voxel below and then levels out with its horizontal
+// neighbors one unit at a time. Voxels that did not change go to sleep until
+// something next to them changes, so settled water costs nothing.
+
+// ticks between steps
+const WATER_RATE: u64 = 4;
+const LAVA_RATE: u64 = 16;
+// bounds the work done in one step, the rest waits for the next one
+const MAX_UPDATES: usize = 8192;
+
+const SIDES: [(i32, i32, i32); 4] = [(1, 0, 0), (0, 0, 1), (-1, 0, 0), (0, 0, -1)];
+
+pub struct Fluids {
+    active: ActiveCells,
+    tick: u64,
+}
+
+impl Fluids {
+    pub fn new() -> Self {
+        Self {
+            active: ActiveCells::new(),
+            tick: 0,
+        }
+    }
+
+    pub fn active_count(&self) -> usize {
+        self.active.count()
+    }
+
+    // call after editing a voxel, wakes it and the fluid around it
+    pub fn wake_around(&mut self, pos: (i32, i32, i32)) {
+        self.active.wake_around(pos);
+    }
+
+    // returns the voxels that changed
+    pub fn update(&mut self, scene: &mut Scene) -> Vec<(i32, i32, i32)> {
+        self.tick += 1;
+        let water = self.tick.is_multiple_of(WATER_RATE);
+        let lava = self.tick.is_multiple_of(LAVA_RATE);
+        if !water && !lava {
+            return Vec::new();
+        }
+
+        let cells = self.active.take(scene, MAX_UPDATES);
+
+        let mut changed = Vec::new();
+        for pos in cells {
+            let Some((kind, _)) = fluid(scene.get_voxel(pos)) else {
+                continue;
+            };
+            let due = if kind == LAVA { lava } else { water };
+            if !due {
+                // not its turn, stays awake
+                self.active.wake(pos);
+                continue;
+            }
+
+            self.step(scene, pos, &mut changed);
+        }
+
+        for pos in &changed {
+            self.active.wake_around(*pos);
+        }
+        changed
+    }
+
+    fn step(&self, scene: &mut Scene, pos: (i32, i32, i32), changed: &mut Vec<(i32, i32, i32)>) {
+        let Some((kind, level)) = fluid(scene.get_voxel(pos)) else {
+            return;
+        };
+        let source = level == SOURCE;
+        let mut amount = level.min(FULL);
+
+        let mut set = |scene: &mut Scene, at: (i32, i32, i32), block: BlockId| {
+            if scene.set_voxel(at, block) {
+                changed.push(at);
+            }
+        };
+
+        // falling first
+        let below = add(pos, (0, -1, 0));
+        match blocking(scene, below) {
+            AIR => {
+                set(scene, below, fluid_block(kind, amount));
+                if !source {
+                    set(scene, pos, AIR);
+                }
+                return;
+            }
+            other => match fluid(other) {
+                Some((k, b)) if k == kind && b < FULL => {
+                    let give = amount.min(FULL - b);
+                    set(scene, below, fluid_block(kind, b + give));
+                    if !source {
+                        amount -= give;
+                    }
+                }
+                Some((k, _)) if k != kind => {
+                    react(scene, pos, kind, below, &mut set);
+                    return;
+                }
+                _ => {}
+            },
+        }
+
+        // rotate the start so flow does not lean to one side
+        let first = (self.tick / WATER_RATE) as usize;
+        for i in 0..SIDES.len() {
+            if amount <= 1 {
+                break;
+            }
+
+            let side = add(pos, SIDES[(first + i) % SIDES.len()]);
+            let target = match blocking(scene, side) {
+                AIR => 0,
+                other => match fluid(other) {
+                    Some((k, b)) if k == kind && b < FULL => b,
+                    Some((k, _)) if k != kind => {
+                        react(scene, pos, kind, side, &mut set);
+                        return;
+                    }
+                    _ => continue,
+                },
+            };
+
+            if target + 1 < amount {
+                set(scene, side, fluid_block(kind, target + 1));
+                if !source {
+                    amount -= 1;
+                }
+            }
+        }
+
+        if amount == 0 {
+            set(scene, pos, AIR);
+        } else if !source && amount != level {
+            set(scene, pos, fluid_block(kind, amount));
+        }
+    }
+}
+
+// fluid stops at the edge of what can be edited
+fn blocking(scene: &Scene, pos: (i32, i32, i32)) -> BlockId {
+    if scene.is_editable(pos) {
+        scene.get_voxel(pos)
+    } else {
+        STONE
+    }
+}
+
+// lava touching water hardens, whichever of the two is lava
+fn react(
+    scene: &mut Scene,
+    pos: (i32, i32, i32),
+    kind: BlockId,
+    other: (i32, i32, i32),
+    set: &mut impl FnMut(&mut Scene, (i32, i32, i32), BlockId),
+) {
+    let lava = if kind == LAVA { pos } else { other };
+    set(scene, lava, STONE);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::block::WATER;
+    use crate::core::types::Node;
+
+    // stone floor at y = 0 under 32x32 voxels
+    fn floor() -> Scene {
+        let mut scene = Scene::new();
+        scene.add_chunk(Node::Empty, (0, 0, 0));
+        for x in 0..32 {
+            for z in 0..32 {
+                scene.set_voxel((x, 0, z), STONE);
+            }
+        }
+        scene
+    }
+
+    fn place(scene: &mut Scene, fluids: &mut Fluids, pos: (i32, i32, i32), block: BlockId) {
+        scene.set_voxel(pos, block);
+        fluids.wake_around(pos);
+    }
+
+    // ticks until nothing is awake, returns how many it took
+    fn settle(scene: &mut Scene, fluids: &mut Fluids) -> u64 {
+        for tick in 1..=20_000 {
+            fluids.update(scene);
+            if fluids.active_count() == 0 {
+                return tick;
+            }
+        }
+        panic!("fluid never settled");
+    }
+
+    fn level(scene: &Scene, pos: (i32, i32, i32)) -> u8 {
+        fluid(scene.get_voxel(pos)).map_or(0, |(_, level)| level)
+    }
+
+    #[test]
+    fn source_levels_drop_with_distance() {
+        let mut scene = floor();
+        let mut fluids = Fluids::new();
+        place(
+            &mut scene,
+            &mut fluids,
+            (16, 1, 16),
+            fluid_block(WATER, SOURCE),
+        );
+        settle(&mut scene, &mut fluids);
+
+        for dx in -8i32..=8 {
+            for dz in -8i32..=8 {
+                let distance = (dx.abs() + dz.abs()) as u8;
+                let expected = FULL.saturating_sub(distance);
+                let expected = if distance == 0 { SOURCE } else { expected };
+                let pos = (16 + dx, 1, 16 + dz);
+                assert_eq!(level(&scene, pos), expected, "at {:?}", pos);
+                assert_eq!(scene.get_voxel((pos.0, 2, pos.2)), AIR);
+            }
+        }
+    }
+
+    #[test]
+    fn falling_water_keeps_its_amount() {
+        let mut scene = floor();
+        let mut fluids = Fluids::new();
+        place(
+            &mut scene,
+            &mut fluids,
+            (16, 4, 16),
+            fluid_block(WATER, FULL),
+        );
+        settle(&mut scene, &mut fluids);
+
+        let mut total = 0;
+        for x in 0..32 {
+            for z in 0..32 {
+                for y in 2..=4 {
+                    assert_eq!(scene.get_voxel((x, y, z)), AIR);
+                }
+                let here = level(&scene, (x, 1, z));
+                total += here as u32;
+                // settled neighbors differ by at most one level
+                for (dx, _, dz) in SIDES {
+                    let there = level(&scene, (x + dx, 1, z + dz));
+                    if here > 0 && there > 0 {
+                        assert!(here.abs_diff(there) <= 1, "at {:?}", (x, z));
+                    }
+                }
+            }
+        }
+        assert_eq!(total, FULL as u32);
+        assert!(level(&scene, (16, 1, 16)) < FULL);
+    }
+
+    #[test]
+    fn lava_meeting_water_hardens() {
+        let mut scene = floor();
+        let mut fluids = Fluids::new();
+        // side by side
+        place(&mut scene, &mut fluids, (5, 1, 5), fluid_block(LAVA, FULL));
+        place(
+            &mut scene,
+            &mut fluids,
+            (6, 1, 5),
+            fluid_block(WATER, SOURCE),
+        );
+        // lava poured onto water
+        place(
+            &mut scene,
+            &mut fluids,
+            (20, 1, 20),
+            fluid_block(WATER, SOURCE),
+        );
+        place(
+            &mut scene,
+            &mut fluids,
+            (20, 2, 20),
+            fluid_block(LAVA, FULL),
+        );
+
+        for _ in 0..LAVA_RATE * 2 {
+            fluids.update(&mut scene);
+        }
+        assert_eq!(scene.get_voxel((5, 1, 5)), STONE);
+        assert_eq!(scene.get_voxel((20, 2, 20)), STONE);
+        assert_eq!(level(&scene, (6, 1, 5)), SOURCE);
+        assert_eq!(level(&scene, (20, 1, 20)), SOURCE);
+    }
+
+    #[test]
+    fn settled_pool_sleeps() {
+        let mut scene = floor();
+        let mut fluids = Fluids::new();
+        // two drops into a 4x4 pit dug into the floor
+        for x in 8..12 {
+            for z in 8..12 {
+                scene.set_voxel((x, 0, z), AIR);
+            }
+        }
+        place(&mut scene, &mut fluids, (9, 3, 9), fluid_block(WATER, FULL));
+        place(
+            &mut scene,
+            &mut fluids,
+            (10, 3, 10),
+            fluid_block(WATER, FULL),
+        );
+        settle(&mut scene, &mut fluids);
+
+        // nothing wakes up again on its own
+        for _ in 0..WATER_RATE * LAVA_RATE {
+            assert!(fluids.update(&mut scene).is_empty());
+            assert_eq!(fluids.active_count(), 0);
+        }
+    }
+}
@@ -1,56 +1,161 @@
-use nalgebra::Vector3;
+use nalgebra::{UnitQuaternion, Vector3};
 use std::sync::Arc;
 use winit::window::{Window};
 
 use crate::{
     app::input::{CursorState},
     core::{
+        automata::{self, Automaton, Rule},
+        block, bvh,
+        clock::Clock,
         cpu_side_svo::{Loader, Stager},
-        settings::{Action, Settings},
+        destruction,
+        fluids::Fluids,
+        gravity::Gravity,
+        instances::{Instances, Model},
+        light::LightField,
+        materials::Materials,
+        objects::{Objects, VoxelObject},
+        regions, registry,
+        settings::{Action, ColorBlindness, Occlusion, Settings, Tonemapper, UpscaleFilter},
         types::{self},
     },
-    gpu::{types::ViewPort, wgpu_ctx::WgpuCtx},
+    gpu::{
+        types::{Environment, Fog, GpuBvhNode, GpuMaterial, GpuObject, Post, Tonemap, ViewPort},
+        wgpu_ctx::WgpuCtx,
+    },
     UPDATE_PER_SECOND,
 };
 
-use crate::app::{input::InputState};
-use crate::core::types::Camera;
+use crate::app::{input::InputState, world::World};
+use crate::core::types::{Camera, CHUNK_SIZE};
+use crate::util::random::Rng;
 
 const CAMERA_SPEED: f32 = 1.0 / UPDATE_PER_SECOND as f32;
 
+pub const WORLD_DIR: &str = "world";
+const DRAGON_CHUNK: (i32, i32, i32) = (3, 3, 3);
+// how far voxels can be edited from the camera
+const REACH: f32 = 64.0;
+// half size of the cube of random cells seeded at once
+const SEED_RADIUS: i32 = 6;
+const EXPLOSION_RADIUS: i32 = 8;
+// largest enclosed area filled with water at once
+const FILL_LIMIT: u32 = 65_536;
+// half size of the box searched for detached pieces after breaking a voxel
+const DETACH_REACH: i32 = 16;
+// thrown objects: edge length, distance in front of the camera, speed
+const OBJECT_SIZE: i32 = 4;
+const THROW_DISTANCE: f32 = 8.0;
+const THROW_SPEED: f32 = 20.0;
+// trunk height and canopy radius of the instanced tree
+const TREE_TRUNK: i32 = 9;
+const TREE_CANOPY: i32 = 4;
+
 pub struct Core {
     scene: types::Scene,
+    world: World,
     camera: Camera,
+    clock: Clock,
+    fluids: Fluids,
+    gravity: Gravity,
+    automaton: Automaton,
+    objects: Objects,
+    instances: Instances,
+    // model placed by PlaceModel
+    tree: u32,
+    light: LightField,
+    materials: Materials,
+    stager: Stager,
+
+    // (pieces, voxels) left floating by the last break
+    detached: (usize, u32),
+    // share of the window rendered last frame, see Settings::render_scale
+    render_scale: f32,
+
+    // hierarchy last uploaded and where each voxel object sits in it, refit
+    // while only objects move
+    bvh_nodes: Vec<GpuBvhNode>,
+    bvh_objects: Vec<GpuObject>,
+    object_slots: Vec<usize>,
+
+    // chunk the loaded area is centered on
+    stream_center: Option<Vector3<i32>>,
 
     settings: Settings,
 }
 
 impl Core {
     pub fn new() -> Self {
+        // before anything looks at a block
+        registry::load(registry::BLOCKS_FILE);
+
         let mut scene = types::Scene::new();
 
+        // the palettes are imported every run in the same order, saved chunks
+        // keep the block ids
+        let mut materials = Materials::new();
         let mut loader = Loader::new();
-        loader.load_data("dragon.vox");
+        let _ = loader.load_data("dragon.vox");
+        let palette = materials.import(&loader.materials().unwrap_or_default());
+        let mut world = World::new(WORLD_DIR, None, &mut materials);
 
-        if let Ok(data) = loader.make_chunk() {
-            scene.add_chunk(data, (3, 3, 3));
+        // placed as an edit, once saved the world keeps its own copy
+        if !world.has_saved_chunk(DRAGON_CHUNK) {
+            let block = |i| palette.get(&i).copied().unwrap_or(block::STONE);
+            if let Ok(data) = loader.make_chunk(block) {
+                scene.add_chunk(data, DRAGON_CHUNK);
+                scene.mark_modified(DRAGON_CHUNK);
+            }
         }
 
         let camera = Camera::new();
         let settings = Settings::default();
 
+        let rule = Rule::parse(settings.automaton_rule()).unwrap_or_else(|_| {
+            eprintln!(
+                "Automaton rule {} not understood, using 3D life",
+                settings.automaton_rule()
+            );
+            Rule::parse(automata::LIFE).unwrap()
+        });
+
+        let mut instances = Instances::new();
+        let tree = instances.add_model(tree_model());
+
         Core {
             scene,
+            world,
             camera,
+            clock: Clock::new(settings.day_length()),
+            fluids: Fluids::new(),
+            gravity: Gravity::new(),
+            automaton: Automaton::new(rule, settings.automaton_rate()),
+            objects: Objects::new(),
+            instances,
+            tree,
+            light: LightField::new(),
+            materials,
+            stager: Stager::new(),
+            detached: (0, 0),
+            render_scale: 1.0,
+            bvh_nodes: Vec::new(),
+            bvh_objects: Vec::new(),
+            object_slots: Vec::new(),
+            stream_center: None,
             settings,
         }
     }
 
+    pub fn save_world(&mut self) {
+        self.world.save_all(&mut self.scene);
+    }
+
     pub fn update(
         &mut self,
         delta_time: f64,
         input: &mut InputState,
-        wgpu: &Option<WgpuCtx>,
+        wgpu: &mut Option<WgpuCtx>,
         window: &Option<Arc<Window>>,
     ) -> bool {
         let changed = self.rotate_camera(delta_time, input);
@@ -61,11 +166,96 @@ impl Core {
 
         self.grab(window, input);
 
-        if !self.scene.world_changed() {
+        // the sun holds still while samples add up
+        if !self.settings.path_tracing() {
+            self.clock.tick(delta_time);
+        }
+        if let Some(wgpu) = wgpu {
+            let light_dir = self.light_direction();
+            let shadow_distance = if self.settings.shadows() {
+                self.settings.shadow_distance()
+            } else {
+                0.0
+            };
+            wgpu.update_environment(&Environment::new(
+                &self.clock,
+                light_dir,
+                self.settings.ambient(),
+                shadow_distance,
+                self.settings.occlusion() as u32,
+                self.settings.reflection_bounces(),
+            ));
+            wgpu.update_tonemap(&Tonemap::new(
+                self.settings.tonemapper() as u32,
+                self.settings.exposure(),
+                self.settings.auto_exposure(),
+            ));
+            let chain: Vec<u32> = self
+                .settings
+                .post_chain()
+                .into_iter()
+                .map(|effect| effect as u32)
+                .collect();
+            wgpu.set_post_effects(&chain);
+            wgpu.update_post(&Post::new(self.settings.color_blindness() as u32));
+            wgpu.set_resolution(
+                self.settings.render_scale(),
+                self.settings
+                    .auto_resolution()
+                    .then_some(self.settings.target_frame_time()),
+                self.settings.upscale_filter() as u32,
+            );
+            self.render_scale = wgpu.render_scale();
+            let far = (self.settings.view_distance() * CHUNK_SIZE) as f32;
+            wgpu.update_fog(&Fog::new(
+                far,
+                self.settings.fog().then_some(self.settings.fog_start()),
+                self.settings.fog_density(),
+            ));
+            wgpu.set_path_tracing(
+                self.settings
+                    .path_tracing()
+                    .then_some(self.settings.path_bounces()),
+            );
+        }
+
+        self.stream_chunks();
+        let mut changed = self.edit_voxels(input);
+        changed.extend(self.seed_cells(input));
+        changed.extend(self.fill_water(input));
+        changed.extend(self.explode(input));
+        self.spawn_object(input);
+        self.place_model(input);
+        changed.extend(self.simulate(delta_time));
+        if !changed.is_empty() {
+            self.light.voxels_changed(&self.scene, &changed);
+        }
+        let relit = self.light.update(&self.scene);
+
+        let objects_changed = self.objects.take_changed() | self.instances.take_changed();
+        let mut staged = false;
+        if !self.scene.world_changed() && !objects_changed && !relit {
         } else if let Some(wgpu) = wgpu {
             self.scene.reset_changed();
-            let data = self.stage_svo();
-            wgpu.replace_world_buffer(&data);
+            self.stage_svo();
+            wgpu.replace_world_buffer(&self.stager);
+            staged = true;
+        }
+
+        if let Some(wgpu) = wgpu {
+            if self.materials.take_changed() {
+                let materials: Vec<GpuMaterial> =
+                    self.materials.iter().map(GpuMaterial::new).collect();
+                wgpu.update_materials(&materials);
+            }
+        }
+
+        // transforms of moving objects every update, resting ones stay put
+        let moved = self.objects.take_moved();
+        if staged {
+            self.upload_objects(wgpu);
+        } else if moved {
+            self.refit_objects(wgpu);
         }
 
         true
@@ -74,7 +264,7 @@ impl Core {
     pub fn render(&mut self) {}
 
     pub fn draw_gui(&self) {}
-    pub fn draw_debug_info(&self, ctx: &egui::Context) {
+    pub fn draw_debug_info(&mut self, ctx: &egui::Context) {
         let raw = self.camera.get_raw();
         let pos = raw.0;
         let dir = raw.1;
@@ -93,14 +283,566 @@ impl Core {
                     "[{:.2}, {:.2}, {:.2}, {:.2}]",
                     dir[0], dir[1], dir[2], dir[3]
                 ));
-            })
+            });
+            ui.horizontal(|ui| {
+                ui.label("World seed: ");
+                ui.label(format!("{}", self.world.seed()));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Active fluid voxels: ");
+                ui.label(format!("{}", self.fluids.active_count()));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Active loose voxels: ");
+                ui.label(format!("{}", self.gravity.active_count()));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Detached by last break: ");
+                ui.label(format!(
+                    "{} pieces, {} voxels",
+                    self.detached.0, self.detached.1
+                ));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Voxel objects: ");
+                ui.label(format!("{}", self.objects.count()));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Model instances: ");
+                ui.label(format!(
+                    "{} of {} models",
+                    self.instances.count(),
+                    self.instances.model_count()
+                ));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Automaton cells: ");
+                ui.label(format!("{}", self.automaton.alive_count()));
+            });
+            ui.horizontal(|ui| {
+                // scrubbing sets the clock, it keeps running from there
+                let mut hours = self.clock.time_of_day() * 24.0;
+                let slider = egui::Slider::new(&mut hours, 0.0..=24.0).text("Time of day");
+                if ui.add(slider).changed() {
+                    self.clock.set_time_of_day(hours / 24.0);
+                }
+            });
+            self.draw_light_settings(ui);
+            self.draw_post_settings(ui);
+            self.draw_resolution_settings(ui);
         });
     }
 
-    fn stage_svo(&self) -> Stager {
-        let mut stager = Stager::new();
-        stager.stage(&self.scene, (0, 0 , 0), (8, 8, 8));
-        stager
+    fn light_direction(&self) -> [f32; 3] {
+        self.settings
+            .light_direction()
+            .unwrap_or_else(|| self.clock.light_direction())
+    }
+
+    // fixed light direction as azimuth and elevation in degrees, ambient share
+    fn draw_light_settings(&mut self, ui: &mut egui::Ui) {
+        let mut fixed = self.settings.light_direction();
+        let mut follow = fixed.is_none();
+        if ui.checkbox(&mut follow, "Light follows sun").changed() {
+            fixed = (!follow).then(|| self.clock.light_direction());
+            self.settings.set_light_direction(fixed);
+        }
+
+        if let Some([x, y, z]) = fixed {
+            let mut azimuth = z.atan2(x).to_degrees();
+            let mut elevation = y.clamp(-1.0, 1.0).asin().to_degrees();
+            let changed = ui
+                .add(egui::Slider::new(&mut azimuth, -180.0..=180.0).text("Light azimuth"))
+                .changed()
+                | ui.add(egui::Slider::new(&mut elevation, -90.0..=90.0).text("Light elevation"))
+                    .changed();
+            if changed {
+                let (a, e) = (azimuth.to_radians(), elevation.to_radians());
+                let direction = [a.cos() * e.cos(), e.sin(), a.sin() * e.cos()];
+                self.settings.set_light_direction(Some(direction));
+            }
+        }
+
+        let mut ambient = self.settings.ambient();
+        if ui
+            .add(egui::Slider::new(&mut ambient, 0.0..=1.0).text("Ambient"))
+            .changed()
+        {
+            self.settings.set_ambient(ambient);
+        }
+
+        let mut shadows = self.settings.shadows();
+        if ui.checkbox(&mut shadows, "Shadows").changed() {
+            self.settings.set_shadows(shadows);
+        }
+        let mut distance = self.settings.shadow_distance();
+        let slider = egui::Slider::new(&mut distance, 16.0..=1024.0).text("Shadow distance");
+        if ui.add_enabled(shadows, slider).changed() {
+            self.settings.set_shadow_distance(distance);
+        }
+
+        let mut occlusion = self.settings.occlusion();
+        egui::ComboBox::from_label("Ambient occlusion")
+            .selected_text(format!("{:?}", occlusion))
+            .show_ui(ui, |ui| {
+                for mode in [Occlusion::Off, Occlusion::Corners, Occlusion::Rays] {
+                    ui.selectable_value(&mut occlusion, mode, format!("{:?}", mode));
+                }
+            });
+        self.settings.set_occlusion(occlusion);
+
+        let mut bounces = self.settings.reflection_bounces();
+        if ui
+            .add(egui::Slider::new(&mut bounces, 0..=8).text("Reflection bounces"))
+            .changed()
+        {
+            self.settings.set_reflection_bounces(bounces);
+        }
+
+        let mut fog = self.settings.fog();
+        if ui.checkbox(&mut fog, "Fog").changed() {
+            self.settings.set_fog(fog);
+        }
+        let mut start = self.settings.fog_start();
+        let slider = egui::Slider::new(&mut start, 0.0..=0.99).text("Fog start");
+        if ui.add_enabled(fog, slider).changed() {
+            self.settings.set_fog_start(start);
+        }
+        let mut density = self.settings.fog_density();
+        let slider = egui::Slider::new(&mut density, 0.0..=0.005).text("Fog density");
+        if ui.add_enabled(fog, slider).changed() {
+            self.settings.set_fog_density(density);
+        }
+
+        let mut tonemapper = self.settings.tonemapper();
+        egui::ComboBox::from_label("Tonemapping")
+            .selected_text(format!("{:?}", tonemapper))
+            .show_ui(ui, |ui| {
+                for curve in [Tonemapper::Reinhard, Tonemapper::Aces] {
+                    ui.selectable_value(&mut tonemapper, curve, format!("{:?}", curve));
+                }
+            });
+        self.settings.set_tonemapper(tonemapper);
+        let mut exposure = self.settings.exposure();
+        if ui
+            .add(egui::Slider::new(&mut exposure, -4.0..=4.0).text("Exposure"))
+            .changed()
+        {
+            self.settings.set_exposure(exposure);
+        }
+        let mut auto_exposure = self.settings.auto_exposure();
+        if ui.checkbox(&mut auto_exposure, "Auto exposure").changed() {
+            self.settings.set_auto_exposure(auto_exposure);
+        }
+
+        // replaces the shaded picture, samples add up while the camera rests
+        let mut path_tracing = self.settings.path_tracing();
+        if ui.checkbox(&mut path_tracing, "Path tracing").changed() {
+            self.settings.set_path_tracing(path_tracing);
+        }
+        let mut bounces = self.settings.path_bounces();
+        let slider = egui::Slider::new(&mut bounces, 1..=8).text("Bounces");
+        if ui.add_enabled(path_tracing, slider).changed() {
+            self.settings.set_path_bounces(bounces);
+        }
+
+        // the face looked at, traced on the CPU to compare with the picture
+        let (pos, dir, _, _) = self.camera.get_raw();
+        if let Some(hit) = self.scene.raycast(pos, dir, REACH) {
+            let block = self.scene.get_voxel(hit.voxel);
+            ui.label(format!("Looked at block: {}", block::name(block)));
+            if shadows {
+                let light = self.light_direction();
+                let shadowed = self.scene.in_shadow(pos, dir, &hit, light, distance);
+                ui.label(format!("Looked at face in shadow: {}", shadowed));
+            }
+        }
+    }
+
+    // post effects in the order they are drawn, each can move before the one
+    // above it
+    fn draw_post_settings(&mut self, ui: &mut egui::Ui) {
+        ui.label("Post effects");
+        let effects = self.settings.post_effects().to_vec();
+        for (index, (effect, on)) in effects.into_iter().enumerate() {
+            ui.horizontal(|ui| {
+                let mut on = on;
+                if ui.checkbox(&mut on, format!("{:?}", effect)).changed() {
+                    self.settings.set_post_effect(index, on);
+                }
+                if ui.add_enabled(index > 0, egui::Button::new("Up")).clicked() {
+                    self.settings.move_post_effect_up(index);
+                }
+            });
+        }
+
+        let mut color_blindness = self.settings.color_blindness();
+        egui::ComboBox::from_label("Color blindness")
+            .selected_text(format!("{:?}", color_blindness))
+            .show_ui(ui, |ui| {
+                for kind in [
+                    ColorBlindness::Protanopia,
+                    ColorBlindness::Deuteranopia,
+                    ColorBlindness::Tritanopia,
+                ] {
+                    ui.selectable_value(&mut color_blindness, kind, format!("{:?}", kind));
+                }
+            });
+        self.settings.set_color_blindness(color_blindness);
+    }
+
+    // the scale slider is the fixed scale, auto resolution shows the one
+    // it settled on
+    fn draw_resolution_settings(&mut self, ui: &mut egui::Ui) {
+        let mut auto_resolution = self.settings.auto_resolution();
+        if ui
+            .checkbox(&mut auto_resolution, "Auto resolution")
+            .changed()
+        {
+            self.settings.set_auto_resolution(auto_resolution);
+        }
+        let mut scale = self.settings.render_scale();
+        let slider = egui::Slider::new(&mut scale, 0.25..=1.0).text("Render scale");
+        if ui.add_enabled(!auto_resolution, slider).changed() {
+            self.settings.set_render_scale(scale);
+        }
+        let mut target = self.settings.target_frame_time();
+        let slider = egui::Slider::new(&mut target, 4.0..=50.0).text("Target frame time (ms)");
+        if ui.add_enabled(auto_resolution, slider).changed() {
+            self.settings.set_target_frame_time(target);
+        }
+        ui.label(format!("Rendering at {:.0}%", self.render_scale * 100.0));
+
+        let mut filter = self.settings.upscale_filter();
+        egui::ComboBox::from_label("Upscaling")
+            .selected_text(format!("{:?}", filter))
+            .show_ui(ui, |ui| {
+                for kind in [UpscaleFilter::Bilinear, UpscaleFilter::EdgeAware] {
+                    ui.selectable_value(&mut filter, kind, format!("{:?}", kind));
+                }
+            });
+        self.settings.set_upscale_filter(filter);
+    }
+
+    fn stream_chunks(&mut self) {
+        let pos = self.camera.get_raw().0;
+        let center = Vector3::new(
+            (pos[0] / CHUNK_SIZE as f32).floor() as i32,
+            (pos[1] / CHUNK_SIZE as f32).floor() as i32,
+            (pos[2] / CHUNK_SIZE as f32).floor() as i32,
+        );
+
+        if self.stream_center == Some(center) {
+            return;
+        }
+        self.stream_center = Some(center);
+
+        let radius = self.settings.view_distance();
+        let settings = &self.settings;
+        self.world.unload_chunks(&mut self.scene, center, radius);
+        self.world
+            .load_chunks(&mut self.scene, center, radius, |d| settings.lod_depth(d));
+        self.light.sync(&self.scene);
+    }
+
+    // returns the voxel that changed
+    fn edit_voxels(&mut self, input: &mut InputState) -> Vec<(i32, i32, i32)> {
+        let breaking = input.consume_key(self.settings.binding(Action::Break));
+        let placing = [
+            (Action::Place, block::STONE),
+            (Action::PlaceWater, block::fluid_block(block::WATER, block::SOURCE)),
+            (Action::PlaceLava, block::fluid_block(block::LAVA, block::SOURCE)),
+        ]
+        .into_iter()
+        .filter(|(action, _)| input.consume_key(self.settings.binding(*action)))
+        .map(|(_, block)| block)
+        .last();
+        if !breaking && placing.is_none() {
+            return Vec::new();
+        }
+
+        let (pos, dir, _, _) = self.camera.get_raw();
+        let Some(hit) = self.scene.raycast(pos, dir, REACH) else {
+            return Vec::new();
+        };
+
+        let (target, edited) = if breaking {
+            (hit.voxel, self.scene.set_voxel(hit.voxel, block::AIR))
+        } else {
+            let target = (
+                hit.voxel.0 + hit.normal.0,
+                hit.voxel.1 + hit.normal.1,
+                hit.voxel.2 + hit.normal.2,
+            );
+            let block = placing.unwrap_or(block::STONE);
+            (target, self.scene.set_voxel(target, block))
+        };
+        self.fluids.wake_around(target);
+        self.gravity.wake_around(target);
+
+        if edited && breaking {
+            let reach = (DETACH_REACH, DETACH_REACH, DETACH_REACH);
+            let low = (target.0 - reach.0, target.1 - reach.1, target.2 - reach.2);
+            let high = (target.0 + reach.0, target.1 + reach.1, target.2 + reach.2);
+
+            let pieces = regions::detached(&self.scene, low, high);
+            self.detached = (pieces.len(), pieces.iter().map(|p| p.count()).sum());
+        }
+
+        if edited {
+            vec![target]
+        } else {
+            Vec::new()
+        }
+    }
+
+    // blast at the voxel looked at
+    fn explode(&mut self, input: &mut InputState) -> Vec<(i32, i32, i32)> {
+        if !input.consume_key(self.settings.binding(Action::Explode)) {
+            return Vec::new();
+        }
+
+        let (pos, dir, _, _) = self.camera.get_raw();
+        let Some(hit) = self.scene.raycast(pos, dir, REACH) else {
+            return Vec::new();
+        };
+
+        let seed = self.world.seed() ^ self.objects.count() as u64;
+        let changed = destruction::explode(
+            &mut self.scene,
+            &mut self.objects,
+            hit.voxel,
+            EXPLOSION_RADIUS,
+            seed,
+        );
+        for pos in &changed {
+            self.fluids.wake_around(*pos);
+            self.gravity.wake_around(*pos);
+        }
+        changed
+    }
+
+    // a wooden cube thrown from the camera, it stays an object
+    fn spawn_object(&mut self, input: &mut InputState) {
+        if !input.consume_key(self.settings.binding(Action::SpawnObject)) {
+            return;
+        }
+
+        let (pos, dir, _, _) = self.camera.get_raw();
+        let dir = Vector3::new(dir[0], dir[1], dir[2]);
+        let center = Vector3::new(pos[0], pos[1], pos[2]) + dir * THROW_DISTANCE;
+        let corner = center.map(|v| v.floor() as i32 - OBJECT_SIZE / 2);
+
+        let mut voxels = Vec::new();
+        for z in 0..OBJECT_SIZE {
+            for y in 0..OBJECT_SIZE {
+                for x in 0..OBJECT_SIZE {
+                    let voxel = (corner.x + x, corner.y + y, corner.z + z);
+                    voxels.push((voxel, block::WOOD));
+                }
+            }
+        }
+
+        let Some(mut object) = VoxelObject::from_voxels(&voxels) else {
+            return;
+        };
+        // moved back towards the camera out of whatever it was thrown into,
+        // nothing is thrown from inside terrain
+        for _ in 0..THROW_DISTANCE as i32 {
+            if object.fits(&self.scene) {
+                break;
+            }
+            object.position -= dir;
+        }
+        if !object.fits(&self.scene) {
+            return;
+        }
+        object.velocity = dir * THROW_SPEED;
+        object.angular_velocity = dir.cross(&Vector3::y()) * 2.0;
+        self.objects.add(object);
+    }
+
+    // a tree instance standing on the voxel looked at, turned at random
+    fn place_model(&mut self, input: &mut InputState) {
+        if !input.consume_key(self.settings.binding(Action::PlaceModel)) {
+            return;
+        }
+
+        let (pos, dir, _, _) = self.camera.get_raw();
+        let Some(hit) = self.scene.raycast(pos, dir, REACH) else {
+            return;
+        };
+
+        let model = self.instances.model(self.tree);
+        let position = Vector3::new(
+            hit.voxel.0 as f32 + 0.5,
+            hit.voxel.1 as f32 + 1.0 + model.size.1 as f32 / 2.0,
+            hit.voxel.2 as f32 + 0.5,
+        );
+        let mut rng = Rng::new(self.world.seed() ^ self.instances.count() as u64);
+        let yaw = rng.next_f32() * std::f32::consts::TAU;
+        let rotation = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), yaw);
+
+        self.instances.place(self.tree, position, rotation);
+    }
+
+    // fills the closed off air in front of the voxel looked at with water that
+    // then settles, a room if there is one, else a basin up to that height
+    fn fill_water(&mut self, input: &mut InputState) -> Vec<(i32, i32, i32)> {
+        if !input.consume_key(self.settings.binding(Action::FillWater)) {
+            return Vec::new();
+        }
+
+        let (pos, dir, _, _) = self.camera.get_raw();
+        let Some(hit) = self.scene.raycast(pos, dir, REACH) else {
+            return Vec::new();
+        };
+
+        let start = (
+            hit.voxel.0 + hit.normal.0,
+            hit.voxel.1 + hit.normal.1,
+            hit.voxel.2 + hit.normal.2,
+        );
+        let water = block::fluid_block(block::WATER, block::FULL);
+        let mut changed = regions::fill_enclosed(&mut self.scene, start, None, water, FILL_LIMIT);
+        if changed.is_empty() {
+            // open to the sky, only what lies below the start holds water
+            changed =
+                regions::fill_enclosed(&mut self.scene, start, Some(start.1), water, FILL_LIMIT);
+        }
+        if changed.is_empty() {
+            eprintln!("Area at {:?} is not closed off, nothing filled", start);
+        }
+
+        for pos in &changed {
+            self.fluids.wake_around(*pos);
+        }
+        changed
+    }
+
+    // random cells in front of the voxel looked at
+    fn seed_cells(&mut self, input: &mut InputState) -> Vec<(i32, i32, i32)> {
+        if !input.consume_key(self.settings.binding(Action::SeedCells)) {
+            return Vec::new();
+        }
+
+        let (pos, dir, _, _) = self.camera.get_raw();
+        let Some(hit) = self.scene.raycast(pos, dir, REACH) else {
+            return Vec::new();
+        };
+
+        let center = (
+            hit.voxel.0 + hit.normal.0 * (SEED_RADIUS + 1),
+            hit.voxel.1 + hit.normal.1 * (SEED_RADIUS + 1),
+            hit.voxel.2 + hit.normal.2 * (SEED_RADIUS + 1),
+        );
+        let seed = self.world.seed() ^ self.automaton.alive_count() as u64;
+        let changed = self
+            .automaton
+            .seed(&mut self.scene, center, SEED_RADIUS, seed);
+        for pos in &changed {
+            self.fluids.wake_around(*pos);
+            self.gravity.wake_around(*pos);
+        }
+        changed
+    }
+
+    // each simulation wakes the others where it changed something,
+    // returns the voxels that changed
+    fn simulate(&mut self, delta_time: f64) -> Vec<(i32, i32, i32)> {
+        let mut changed = self.fluids.update(&mut self.scene);
+        for pos in &changed {
+            self.gravity.wake_around(*pos);
+        }
+
+        let fallen = self.gravity.update(&mut self.scene);
+        for pos in &fallen {
+            self.fluids.wake_around(*pos);
+        }
+        changed.extend(fallen);
+
+        let debris = self.objects.update(&mut self.scene, delta_time as f32);
+        for pos in &debris {
+            self.fluids.wake_around(*pos);
+            self.gravity.wake_around(*pos);
+        }
+        changed.extend(debris);
+
+        let cells = self.automaton.update(&mut self.scene);
+        for pos in &cells {
+            self.fluids.wake_around(*pos);
+            self.gravity.wake_around(*pos);
+        }
+        changed.extend(cells);
+        changed
+    }
+
+    // chunks with new voxels or new light are flattened again
+    fn stage_svo(&mut self) {
+        let mut dirty = self.scene.take_dirty();
+        dirty.extend(self.light.take_dirty());
+        self.stager.stage(
+            &self.scene,
+            &self.light,
+            &self.objects,
+            &self.instances,
+            self.scene.bounds(),
+            &dirty,
+        );
+    }
+
+    // transforms of everything traced and the hierarchy over them
+    fn upload_objects(&mut self, wgpu: &mut Option<WgpuCtx>) {
+        let Some(wgpu) = wgpu else {
+            return;
+        };
+
+        let mut objects: Vec<GpuObject> = self
+            .stager
+            .chunk_roots
+            .iter()
+            .map(|(coords, root)| GpuObject::chunk(*coords, *root))
+            .collect();
+        let first_object = objects.len();
+        objects.extend(
+            self.objects
+                .iter()
+                .zip(&self.stager.object_roots)
+                .map(|(object, root)| GpuObject::new(object, *root)),
+        );
+        let object_count = objects.len() - first_object;
+        objects.extend(self.instances.iter().map(|instance| {
+            let root = self.stager.model_roots[instance.model as usize];
+            GpuObject::instance(instance, self.instances.model(instance.model), root)
+        }));
+
+        let (nodes, objects, slots) = bvh::build(&objects);
+        wgpu.update_objects(&objects, &nodes);
+
+        self.bvh_nodes = nodes;
+        self.bvh_objects = objects;
+        self.object_slots = slots[first_object..first_object + object_count].to_vec();
+    }
+
+    // the voxel objects moved but none came or went, the tree keeps its shape
+    fn refit_objects(&mut self, wgpu: &mut Option<WgpuCtx>) {
+        let Some(wgpu) = wgpu else {
+            return;
+        };
+
+        let moved: Vec<(usize, GpuObject)> = self
+            .objects
+            .iter()
+            .zip(&self.stager.object_roots)
+            .zip(&self.object_slots)
+            .map(|((object, root), slot)| (*slot, GpuObject::new(object, *root)))
+            .collect();
+        for (slot, object) in &moved {
+            self.bvh_objects[*slot] = *object;
+        }
+
+        bvh::refit(&mut self.bvh_nodes, &self.bvh_objects);
+        wgpu.move_objects(&moved, &self.bvh_nodes);
     }
 
     fn move_camera(&mut self, delta_time: f64, input: &InputState) -> bool {
@@ -154,13 +896,15 @@ impl Core {
         dx != 0.0 || dy != 0.0
     }
 
-    fn update_view_port(&self, wgpu: &Option<WgpuCtx>, window: &Option<Arc<Window>>) {
+    fn update_view_port(&self, wgpu: &mut Option<WgpuCtx>, window: &Option<Arc<Window>>) {
         match (wgpu, window) {
             (Some(wgpu), Some(window)) => {
+                let far = (self.settings.view_distance() * CHUNK_SIZE) as f32;
                 wgpu.update_view_port(&ViewPort::new(
                     &self.camera,
                     window.inner_size(),
                     self.settings.field_of_view(),
+                    far,
                 ));
             }
             _ => {}
@@ -187,3 +931,26 @@ impl Core {
         }
     }
 }
+
+// trunk with a round canopy, trunk base at the bottom center
+fn tree_model() -> Model {
+    let mut voxels = Vec::new();
+    for y in 0..TREE_TRUNK {
+        voxels.push(((0, y, 0), block::WOOD));
+    }
+
+    let r = TREE_CANOPY;
+    for z in -r..=r {
+        for y in -r..=r {
+            for x in -r..=r {
+                let inside = x * x + y * y + z * z <= r * r;
+                if inside && !(x == 0 && z == 0 && y < 0) {
+                    voxels.push(((x, TREE_TRUNK + y, z), block::LEAVES));
+                }
+            }
+        }
+    }
+
+    // only a few voxels, always fits
+    Model::from_voxels(&voxels).unwrap()
+}
@@ -0,0 +1,127 @@
+use crate::core::active::{add, ActiveCells};
+use crate::core::block::{is_loose, is_solid};
+use crate::core::types::Scene;
+
+// Loose blocks (sand, gravel) drop one voxel per step while the voxel below
+// is air or fluid, fluid is pushed up into the voxel they left. Only voxels
+// woken by an edit next to them are checked.
+
+// ticks between steps
+const FALL_RATE: u64 = 2;
+const MAX_UPDATES: usize = 8192;
+
+pub struct Gravity {
+    active: ActiveCells,
+    tick: u64,
+}
+
+impl Gravity {
+    pub fn new() -> Self {
+        Self {
+            active: ActiveCells::new(),
+            tick: 0,
+        }
+    }
+
+    pub fn active_count(&self) -> usize {
+        self.active.count()
+    }
+
+    // call after editing a voxel, the column above it may have lost support
+    pub fn wake_around(&mut self, pos: (i32, i32, i32)) {
+        self.active.wake_around(pos);
+    }
+
+    // returns the voxels that changed
+    pub fn update(&mut self, scene: &mut Scene) -> Vec<(i32, i32, i32)> {
+        self.tick += 1;
+        if !self.tick.is_multiple_of(FALL_RATE) {
+            return Vec::new();
+        }
+
+        // bottom first so a whole column moves down together
+        let mut cells = self.active.take(scene, MAX_UPDATES);
+        cells.sort_unstable_by_key(|pos| pos.1);
+
+        let mut changed = Vec::new();
+        for pos in cells {
+            let block = scene.get_voxel(pos);
+            if !is_loose(block) {
+                continue;
+            }
+
+            let below = add(pos, (0, -1, 0));
+            if !scene.is_editable(below) {
+                continue;
+            }
+            let under = scene.get_voxel(below);
+            if is_solid(under) {
+                continue;
+            }
+
+            scene.set_voxel(below, block);
+            scene.set_voxel(pos, under);
+            changed.push(pos);
+            changed.push(below);
+        }
+
+        for pos in &changed {
+            self.active.wake_around(*pos);
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::block::{fluid_block, AIR, FULL, GRAVEL, SAND, STONE, WATER};
+    use crate::core::types::Node;
+
+    #[test]
+    fn loose_blocks_fall_until_supported() {
+        let mut scene = Scene::new();
+        scene.add_chunk(Node::Empty, (0, 0, 0));
+        for x in 0..24 {
+            for z in 0..24 {
+                scene.set_voxel((x, 0, z), STONE);
+            }
+        }
+
+        let placed = [
+            // floating column
+            ((5, 5, 5), SAND),
+            ((5, 6, 5), GRAVEL),
+            ((5, 7, 5), SAND),
+            // resting on the floor and on a stone block
+            ((10, 1, 10), GRAVEL),
+            ((12, 1, 12), STONE),
+            ((12, 2, 12), SAND),
+            // sinking through water
+            ((20, 1, 20), fluid_block(WATER, FULL)),
+            ((20, 2, 20), SAND),
+        ];
+        let mut gravity = Gravity::new();
+        for (pos, block) in placed {
+            scene.set_voxel(pos, block);
+            gravity.wake_around(pos);
+        }
+
+        let mut ticks = 0;
+        while gravity.active_count() > 0 {
+            gravity.update(&mut scene);
+            ticks += 1;
+            assert!(ticks < 1000, "blocks never came to rest");
+        }
+
+        let column: Vec<_> = (1..=7).map(|y| scene.get_voxel((5, y, 5))).collect();
+        assert_eq!(column, [SAND, GRAVEL, SAND, AIR, AIR, AIR, AIR]);
+
+        assert_eq!(scene.get_voxel((10, 1, 10)), GRAVEL);
+        assert_eq!(scene.get_voxel((12, 1, 12)), STONE);
+        assert_eq!(scene.get_voxel((12, 2, 12)), SAND);
+
+        assert_eq!(scene.get_voxel((20, 1, 20)), SAND);
+        assert_eq!(scene.get_voxel((20, 2, 20)), fluid_block(WATER, FULL));
+    }
+}
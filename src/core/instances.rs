@@ -0,0 +1,117 @@
+use nalgebra::{UnitQuaternion, Vector3};
+
+use crate::core::block::BlockId;
+use crate::core::types::{Node, CHUNK_SIZE};
+
+// Models placed many times over. Every model keeps one tree in model space,
+// instances only hold a reference and a transform, so the staged nodes grow
+// with the number of distinct models and not with the placements.
+// Instances are drawn but not part of the scene grid, nothing collides with
+// them.
+
+type Coords = (i32, i32, i32);
+
+pub struct Model {
+    pub root: Node,
+    // voxels per axis, at most CHUNK_SIZE
+    pub size: (i32, i32, i32),
+}
+
+impl Model {
+    // `voxels` in model space, moved so the lowest corner is (0, 0, 0)
+    pub fn from_voxels(voxels: &[(Coords, BlockId)]) -> Option<Model> {
+        let first = voxels.first()?.0;
+        let mut low = first;
+        let mut high = first;
+        for ((x, y, z), _) in voxels {
+            low = (low.0.min(*x), low.1.min(*y), low.2.min(*z));
+            high = (high.0.max(*x), high.1.max(*y), high.2.max(*z));
+        }
+
+        let size = (high.0 - low.0 + 1, high.1 - low.1 + 1, high.2 - low.2 + 1);
+        if size.0 > CHUNK_SIZE || size.1 > CHUNK_SIZE || size.2 > CHUNK_SIZE {
+            return None;
+        }
+
+        let mut root = Node::Empty;
+        for ((x, y, z), block) in voxels {
+            root.set((x - low.0, y - low.1, z - low.2), *block);
+        }
+
+        Some(Model { root, size })
+    }
+
+    // model space point the instance transform rotates around
+    pub fn center(&self) -> Vector3<f32> {
+        let (x, y, z) = self.size;
+        Vector3::new(x as f32, y as f32, z as f32) / 2.0
+    }
+}
+
+pub struct Instance {
+    pub model: u32,
+    // where the model's center lands in the world
+    pub position: Vector3<f32>,
+    pub rotation: UnitQuaternion<f32>,
+}
+
+pub struct Instances {
+    models: Vec<Model>,
+    instances: Vec<Instance>,
+    // set when instances were placed, they need staging
+    changed: bool,
+}
+
+impl Instances {
+    pub fn new() -> Self {
+        Self {
+            models: Vec::new(),
+            instances: Vec::new(),
+            changed: false,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn model_count(&self) -> usize {
+        self.models.len()
+    }
+
+    // models never change or go away, their id is the index
+    pub fn add_model(&mut self, model: Model) -> u32 {
+        self.models.push(model);
+        self.models.len() as u32 - 1
+    }
+
+    pub fn model(&self, id: u32) -> &Model {
+        &self.models[id as usize]
+    }
+
+    pub fn models(&self) -> &[Model] {
+        &self.models
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Instance> {
+        self.instances.iter()
+    }
+
+    pub fn place(&mut self, model: u32, position: Vector3<f32>, rotation: UnitQuaternion<f32>) {
+        if model as usize >= self.models.len() {
+            eprintln!("No model {} to place", model);
+            return;
+        }
+
+        self.instances.push(Instance {
+            model,
+            position,
+            rotation,
+        });
+        self.changed = true;
+    }
+
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+}
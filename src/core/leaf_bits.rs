@@ -0,0 +1,99 @@
+// Bit operations on the u64 masks of 4x4x4 leaves, index = x + 4y + 16z like
+// Node::Leaf. Leaf coords are voxel coords >> 2.
+
+type Coords = (i32, i32, i32);
+
+// bits of the cells on each face of a leaf
+pub const X0: u64 = 0x1111_1111_1111_1111;
+pub const X3: u64 = X0 << 3;
+pub const Y0: u64 = 0x000F_000F_000F_000F;
+pub const Y3: u64 = Y0 << 12;
+pub const Z0: u64 = 0xFFFF;
+pub const Z3: u64 = Z0 << 48;
+
+// value of the cell at x + d, `next` is the leaf on that side
+pub fn shift_x(center: u64, next: u64, d: i32) -> u64 {
+    match d {
+        1 => ((center >> 1) & !X3) | ((next & X0) << 3),
+        -1 => ((center << 1) & !X0) | ((next & X3) >> 3),
+        _ => center,
+    }
+}
+
+pub fn shift_y(center: u64, next: u64, d: i32) -> u64 {
+    match d {
+        1 => ((center >> 4) & !Y3) | ((next & Y0) << 12),
+        -1 => ((center << 4) & !Y0) | ((next & Y3) >> 12),
+        _ => center,
+    }
+}
+
+pub fn shift_z(center: u64, next: u64, d: i32) -> u64 {
+    match d {
+        1 => (center >> 16) | ((next & Z0) << 48),
+        -1 => (center << 16) | ((next & Z3) >> 48),
+        _ => center,
+    }
+}
+
+// cells of leaves in row `leaf_y` at or below the voxel height `top`
+pub fn at_or_below(leaf_y: i32, top: i32) -> u64 {
+    (0..4)
+        .filter(|y| leaf_y * 4 + y <= top)
+        .fold(0, |bits, y| bits | (Y0 << (4 * y)))
+}
+
+// bits plus their six neighbors inside the leaf
+pub fn dilate(bits: u64) -> u64 {
+    bits | ((bits << 1) & !X0)
+        | ((bits >> 1) & !X3)
+        | ((bits << 4) & !Y0)
+        | ((bits >> 4) & !Y3)
+        | (bits << 16)
+        | (bits >> 16)
+}
+
+// everything in `open` connected to `seed` without leaving the leaf
+pub fn fill_within(seed: u64, open: u64) -> u64 {
+    let mut filled = seed & open;
+    loop {
+        let next = dilate(filled) & open;
+        if next == filled {
+            return filled;
+        }
+        filled = next;
+    }
+}
+
+// bits on the face towards `dir` moved onto the touching face of the next
+// leaf, `dir` is one of active::NEIGHBORS
+pub fn spill(bits: u64, dir: Coords) -> u64 {
+    match dir {
+        (1, 0, 0) => (bits & X3) >> 3,
+        (-1, 0, 0) => (bits & X0) << 3,
+        (0, 1, 0) => (bits & Y3) >> 12,
+        (0, -1, 0) => (bits & Y0) << 12,
+        (0, 0, 1) => (bits & Z3) >> 48,
+        (0, 0, -1) => (bits & Z0) << 48,
+        _ => 0,
+    }
+}
+
+// (leaf, bit) of a voxel
+pub fn split_leaf(pos: Coords) -> (Coords, u32) {
+    let leaf = (pos.0 >> 2, pos.1 >> 2, pos.2 >> 2);
+    let bit = (pos.0 & 3) + 4 * (pos.1 & 3) + 16 * (pos.2 & 3);
+    (leaf, bit as u32)
+}
+
+pub fn leaf_origin(leaf: Coords) -> Coords {
+    (leaf.0 << 2, leaf.1 << 2, leaf.2 << 2)
+}
+
+// voxels of the set bits
+pub fn leaf_cells(leaf: Coords, bits: u64) -> impl Iterator<Item = Coords> {
+    let origin = leaf_origin(leaf);
+    (0..64)
+        .filter(move |i| bits & (1 << i) != 0)
+        .map(move |i| (origin.0 + i % 4, origin.1 + i / 4 % 4, origin.2 + i / 16))
+}
@@ -0,0 +1,664 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::core::active::{add, NEIGHBORS};
+use crate::core::block::{emission, is_transparent, BlockId};
+use crate::core::types::{split_voxel, Node, Scene, CHUNK_SIZE, FULL_DEPTH};
+
+// Light of a voxel packed in one byte, sunlight in the high 4 bits and block
+// light in the low 4 bits, both 0..=15.
+//
+// Only chunks at full detail are lit. Voxels at or above the top opaque voxel
+// of their column get full sunlight and everything else starts dark, then
+// both kinds of light flood fill through transparent voxels losing one level
+// per step. Only voxels that differ from that default are stored.
+//
+// An edit relights the box light from it can reach, voxels outside the box
+// keep their values and seed it from the border. Boxes wait in a queue and
+// update relights a bounded amount of them every tick.
+
+pub const FULL_SUN: u8 = 0xF0;
+// furthest light travels
+const REACH: i32 = 15;
+// voxels relit in one update, a larger box still goes through on its own
+const MAX_RELIGHT: i64 = 1 << 18;
+
+type Coords = (i32, i32, i32);
+
+pub struct LightField {
+    // top opaque voxel + 1 of every column, per chunk column (x, z)
+    heights: HashMap<(i32, i32), Vec<i32>>,
+    // lit chunk ys of every chunk column, highest first
+    columns: HashMap<(i32, i32), Vec<i32>>,
+    values: HashMap<Coords, HashMap<u32, u8>>,
+    emitters: HashMap<Coords, HashSet<Coords>>,
+
+    lit: HashSet<Coords>,
+    // chunks whose staged light is out of date
+    dirty: HashSet<Coords>,
+    // boxes waiting to be relit, oldest first
+    pending: VecDeque<(Coords, Coords)>,
+}
+
+impl LightField {
+    pub fn new() -> Self {
+        Self {
+            heights: HashMap::new(),
+            columns: HashMap::new(),
+            values: HashMap::new(),
+            emitters: HashMap::new(),
+            lit: HashSet::new(),
+            dirty: HashSet::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub fn is_lit(&self, chunk: Coords) -> bool {
+        self.lit.contains(&chunk)
+    }
+
+    pub fn take_dirty(&mut self) -> Vec<Coords> {
+        self.dirty.drain().collect()
+    }
+
+    pub fn get(&self, pos: Coords) -> u8 {
+        let (chunk, local) = split_voxel(pos);
+        let stored = self
+            .values
+            .get(&chunk)
+            .and_then(|values| values.get(&local_index(local)));
+
+        match stored {
+            Some(value) => *value,
+            None => self.default_light(pos),
+        }
+    }
+
+    fn default_light(&self, pos: Coords) -> u8 {
+        if pos.1 >= self.height(pos.0, pos.2) {
+            FULL_SUN
+        } else {
+            0
+        }
+    }
+
+    fn height(&self, x: i32, z: i32) -> i32 {
+        let column = (x.div_euclid(CHUNK_SIZE), z.div_euclid(CHUNK_SIZE));
+        let (lx, lz) = (x.rem_euclid(CHUNK_SIZE), z.rem_euclid(CHUNK_SIZE));
+
+        match self.heights.get(&column) {
+            Some(heights) => heights[(lx + lz * CHUNK_SIZE) as usize],
+            None => i32::MIN,
+        }
+    }
+
+    fn set(&mut self, pos: Coords, value: u8) {
+        let default = self.default_light(pos);
+        let (chunk, local) = split_voxel(pos);
+        let values = self.values.entry(chunk).or_default();
+
+        if value == default {
+            values.remove(&local_index(local));
+        } else {
+            values.insert(local_index(local), value);
+        }
+    }
+
+    // lights chunks that reached full detail and forgets the ones that left
+    pub fn sync(&mut self, scene: &Scene) {
+        let full: HashSet<Coords> = scene
+            .chunk_coords()
+            .into_iter()
+            .filter(|chunk| scene.chunk_depth(*chunk) == FULL_DEPTH)
+            .collect();
+
+        let added: Vec<Coords> = full.difference(&self.lit).copied().collect();
+        let removed: Vec<Coords> = self.lit.difference(&full).copied().collect();
+        if added.is_empty() && removed.is_empty() {
+            return;
+        }
+
+        let mut changed_columns = HashSet::new();
+        for chunk in removed {
+            self.lit.remove(&chunk);
+            self.values.remove(&chunk);
+            self.emitters.remove(&chunk);
+            if let Some(ys) = self.columns.get_mut(&(chunk.0, chunk.2)) {
+                ys.retain(|y| *y != chunk.1);
+            }
+            changed_columns.insert((chunk.0, chunk.2));
+        }
+        for chunk in added {
+            self.lit.insert(chunk);
+            self.columns.entry((chunk.0, chunk.2)).or_default().push(chunk.1);
+            self.scan_emitters(scene, chunk);
+            changed_columns.insert((chunk.0, chunk.2));
+        }
+
+        for column in &changed_columns {
+            self.update_heights(scene, *column);
+        }
+
+        for column in changed_columns {
+            let Some(ys) = self.columns.get(&column) else {
+                continue;
+            };
+            let (top, bottom) = (ys[0], ys[ys.len() - 1]);
+
+            let low = (
+                column.0 * CHUNK_SIZE - REACH,
+                bottom * CHUNK_SIZE - REACH,
+                column.1 * CHUNK_SIZE - REACH,
+            );
+            let high = (
+                (column.0 + 1) * CHUNK_SIZE + REACH,
+                (top + 1) * CHUNK_SIZE + REACH,
+                (column.1 + 1) * CHUNK_SIZE + REACH,
+            );
+            self.pending.push_back((low, high));
+        }
+    }
+
+    // call with every voxel that changed since the last call
+    pub fn voxels_changed(&mut self, scene: &Scene, changed: &[Coords]) {
+        // nearby changes share one box
+        let mut boxes: HashMap<Coords, (Coords, Coords)> = HashMap::new();
+
+        for pos in changed {
+            let (chunk, _) = split_voxel(*pos);
+            if !self.lit.contains(&chunk) {
+                continue;
+            }
+
+            let emitters = self.emitters.entry(chunk).or_default();
+            if emission(scene.get_voxel(*pos)) > 0 {
+                emitters.insert(*pos);
+            } else {
+                emitters.remove(pos);
+            }
+
+            let old_height = self.height(pos.0, pos.2);
+            self.update_column(scene, pos.0, pos.2);
+            let new_height = self.height(pos.0, pos.2);
+
+            // a column that opened up or closed changes all the way down
+            let bottom = self.column_bottom(pos.0, pos.2);
+            let lowest = pos.1.min(old_height).min(new_height).max(bottom);
+
+            let low = (pos.0 - REACH, lowest - REACH, pos.2 - REACH);
+            let high = (pos.0 + REACH + 1, pos.1 + REACH + 1, pos.2 + REACH + 1);
+
+            let key = (pos.0 >> 5, pos.1 >> 5, pos.2 >> 5);
+            let entry = boxes.entry(key).or_insert((low, high));
+            entry.0 = min3(entry.0, low);
+            entry.1 = max3(entry.1, high);
+        }
+
+        self.pending.extend(boxes.into_values());
+    }
+
+    // relights waiting boxes, the oldest one and then more while they fit in
+    // MAX_RELIGHT voxels. Returns whether anything was relit.
+    pub fn update(&mut self, scene: &Scene) -> bool {
+        let mut budget = MAX_RELIGHT;
+        let mut relit = false;
+
+        while let Some((low, high)) = self.pending.front().copied() {
+            let size = (high.0 - low.0, high.1 - low.1, high.2 - low.2);
+            let volume = size.0 as i64 * size.1 as i64 * size.2 as i64;
+            if relit && volume > budget {
+                break;
+            }
+
+            self.pending.pop_front();
+            self.relight(scene, low, high);
+            budget -= volume;
+            relit = true;
+        }
+        relit
+    }
+
+    fn relight(&mut self, scene: &Scene, low: Coords, high: Coords) {
+        let inside = |p: Coords| {
+            (low.0..high.0).contains(&p.0)
+                && (low.1..high.1).contains(&p.1)
+                && (low.2..high.2).contains(&p.2)
+        };
+
+        let chunks: Vec<Coords> = self
+            .lit
+            .iter()
+            .copied()
+            .filter(|c| overlaps(*c, add(low, (-1, -1, -1)), add(high, (1, 1, 1))))
+            .collect();
+
+        for chunk in &chunks {
+            self.dirty.insert(*chunk);
+            if let Some(values) = self.values.get_mut(chunk) {
+                values.retain(|i, _| !inside(add(chunk_origin(*chunk), local_coords(*i))));
+            }
+        }
+
+        let mut queue = VecDeque::new();
+
+        // sunlight spilling sideways from exposed columns under overhangs
+        for z in low.2 - 1..high.2 + 1 {
+            for x in low.0 - 1..high.0 + 1 {
+                let h = self.height(x, z);
+                if h == i32::MIN {
+                    continue;
+                }
+
+                for (dx, _, dz) in NEIGHBORS.iter().filter(|n| n.1 == 0) {
+                    let (nx, nz) = (x + dx, z + dz);
+                    if !(low.0..high.0).contains(&nx) || !(low.2..high.2).contains(&nz) {
+                        continue;
+                    }
+
+                    let top = self.height(nx, nz).min(high.1);
+                    for y in h.max(low.1)..top {
+                        self.seed(scene, &mut queue, (nx, y, nz), FULL_SUN - 0x10, &inside);
+                    }
+                }
+            }
+        }
+
+        // light coming in from outside the box
+        let mut border = Vec::new();
+        for z in low.2..high.2 {
+            for x in low.0..high.0 {
+                border.push(((x, low.1 - 1, z), (x, low.1, z)));
+                border.push(((x, high.1, z), (x, high.1 - 1, z)));
+            }
+        }
+        for y in low.1..high.1 {
+            for x in low.0..high.0 {
+                border.push(((x, y, low.2 - 1), (x, y, low.2)));
+                border.push(((x, y, high.2), (x, y, high.2 - 1)));
+            }
+            for z in low.2..high.2 {
+                border.push(((low.0 - 1, y, z), (low.0, y, z)));
+                border.push(((high.0, y, z), (high.0 - 1, y, z)));
+            }
+        }
+        for (outside, pos) in border {
+            let value = decay(self.get(outside));
+            if value != 0 {
+                self.seed(scene, &mut queue, pos, value, &inside);
+            }
+        }
+
+        // emissive blocks
+        let mut emitters = Vec::new();
+        for chunk in &chunks {
+            if let Some(set) = self.emitters.get(chunk) {
+                emitters.extend(set.iter().copied().filter(|p| inside(*p)));
+            }
+        }
+        for pos in emitters {
+            let value = brightest(self.get(pos), emission(scene.get_voxel(pos)));
+            self.set(pos, value);
+            queue.push_back(pos);
+        }
+
+        while let Some(pos) = queue.pop_front() {
+            let value = decay(self.get(pos));
+            if value == 0 {
+                continue;
+            }
+            for n in NEIGHBORS {
+                self.seed(scene, &mut queue, add(pos, n), value, &inside);
+            }
+        }
+    }
+
+    fn seed(
+        &mut self,
+        scene: &Scene,
+        queue: &mut VecDeque<Coords>,
+        pos: Coords,
+        value: u8,
+        inside: &impl Fn(Coords) -> bool,
+    ) {
+        if !inside(pos) || !self.lit.contains(&split_voxel(pos).0) {
+            return;
+        }
+        if !is_transparent(scene.get_voxel(pos)) {
+            return;
+        }
+
+        let current = self.get(pos);
+        let merged = brightest(current, value);
+        if merged != current {
+            self.set(pos, merged);
+            queue.push_back(pos);
+        }
+    }
+
+    fn scan_emitters(&mut self, scene: &Scene, chunk: Coords) {
+        let mut found = HashSet::new();
+        if let Some(root) = scene.get_chunk(chunk) {
+            find_emitters(root, chunk_origin(chunk), CHUNK_SIZE, &mut found);
+        }
+        self.emitters.insert(chunk, found);
+    }
+
+    fn update_heights(&mut self, scene: &Scene, column: (i32, i32)) {
+        let Some(ys) = self.columns.get_mut(&column) else {
+            return;
+        };
+        if ys.is_empty() {
+            self.columns.remove(&column);
+            self.heights.remove(&column);
+            return;
+        }
+        ys.sort_unstable_by(|a, b| b.cmp(a));
+
+        let mut heights = vec![i32::MIN; (CHUNK_SIZE * CHUNK_SIZE) as usize];
+        for lz in 0..CHUNK_SIZE {
+            for lx in 0..CHUNK_SIZE {
+                heights[(lx + lz * CHUNK_SIZE) as usize] = column_height(scene, column, ys, lx, lz);
+            }
+        }
+        self.heights.insert(column, heights);
+    }
+
+    fn update_column(&mut self, scene: &Scene, x: i32, z: i32) {
+        let column = (x.div_euclid(CHUNK_SIZE), z.div_euclid(CHUNK_SIZE));
+        let (lx, lz) = (x.rem_euclid(CHUNK_SIZE), z.rem_euclid(CHUNK_SIZE));
+        let Some(ys) = self.columns.get(&column) else {
+            return;
+        };
+
+        let height = column_height(scene, column, ys, lx, lz);
+        if let Some(heights) = self.heights.get_mut(&column) {
+            heights[(lx + lz * CHUNK_SIZE) as usize] = height;
+        }
+    }
+
+    fn column_bottom(&self, x: i32, z: i32) -> i32 {
+        let column = (x.div_euclid(CHUNK_SIZE), z.div_euclid(CHUNK_SIZE));
+        match self.columns.get(&column).and_then(|ys| ys.last()) {
+            Some(y) => y * CHUNK_SIZE,
+            None => i32::MIN / 2,
+        }
+    }
+
+    // brightest light on any open face of a leaf voxel, 64 values in leaf order
+    pub fn leaf_light(&self, scene: &Scene, origin: Coords, mask: u64, blocks: &[BlockId; 64]) -> [u8; 64] {
+        let mut light = [0u8; 64];
+
+        for (i, value) in light.iter_mut().enumerate() {
+            if mask & (1 << i) == 0 {
+                continue;
+            }
+            let local = (i as i32 % 4, i as i32 / 4 % 4, i as i32 / 16);
+
+            for n in NEIGHBORS {
+                let (x, y, z) = add(local, n);
+                let open = if (0..4).contains(&x) && (0..4).contains(&y) && (0..4).contains(&z) {
+                    let j = (x + 4 * y + 16 * z) as usize;
+                    mask & (1 << j) == 0 || is_transparent(blocks[j])
+                } else {
+                    is_transparent(scene.get_voxel(add(origin, (x, y, z))))
+                };
+
+                if open {
+                    *value = brightest(*value, self.get(add(origin, (x, y, z))));
+                }
+            }
+        }
+        light
+    }
+
+    // one value for a whole solid node, sampled in front of its face centers
+    pub fn node_light(&self, scene: &Scene, origin: Coords, size: i32) -> u8 {
+        let half = size / 2;
+        let samples = [
+            (-1, half, half),
+            (size, half, half),
+            (half, -1, half),
+            (half, size, half),
+            (half, half, -1),
+            (half, half, size),
+        ];
+
+        let mut value = 0;
+        for sample in samples {
+            let pos = add(origin, sample);
+            if is_transparent(scene.get_voxel(pos)) {
+                value = brightest(value, self.get(pos));
+            }
+        }
+        value
+    }
+}
+
+// top opaque voxel + 1 over the lit chunks of a column
+fn column_height(scene: &Scene, column: (i32, i32), ys: &[i32], lx: i32, lz: i32) -> i32 {
+    for y in ys {
+        let Some(root) = scene.get_chunk((column.0, *y, column.1)) else {
+            continue;
+        };
+        if let Some(top) = node_top(root, lx, lz, 0, CHUNK_SIZE) {
+            return y * CHUNK_SIZE + top + 1;
+        }
+    }
+    i32::MIN
+}
+
+// local y of the highest opaque voxel of column (x, z) inside the node
+fn node_top(node: &Node, x: i32, z: i32, y0: i32, size: i32) -> Option<i32> {
+    match node {
+        Node::Empty => None,
+        Node::Solid(block) if is_transparent(*block) => None,
+        Node::Solid(_) => Some(y0 + size - 1),
+        Node::Leaf(mask, blocks) => (0..4).rev().find_map(|y| {
+            let i = ((x & 3) + 4 * y + 16 * (z & 3)) as usize;
+            let opaque = mask & (1 << i) != 0 && !is_transparent(blocks[i]);
+            opaque.then_some(y0 + y)
+        }),
+        Node::Branch(branch) => {
+            let child = size / 4;
+            let (cx, cz) = ((x / child) % 4, (z / child) % 4);
+            (0..4).rev().find_map(|y| {
+                let i = (cx + 4 * y + 16 * cz) as usize;
+                node_top(&branch.children[i], x, z, y0 + y * child, child)
+            })
+        }
+    }
+}
+
+fn find_emitters(node: &Node, origin: Coords, size: i32, found: &mut HashSet<Coords>) {
+    match node {
+        Node::Empty => {}
+        Node::Solid(block) => {
+            if emission(*block) == 0 {
+                return;
+            }
+            for z in 0..size {
+                for y in 0..size {
+                    for x in 0..size {
+                        found.insert(add(origin, (x, y, z)));
+                    }
+                }
+            }
+        }
+        Node::Leaf(mask, blocks) => {
+            for (i, block) in blocks.iter().enumerate() {
+                if mask & (1 << i) != 0 && emission(*block) > 0 {
+                    found.insert(add(origin, (i as i32 % 4, i as i32 / 4 % 4, i as i32 / 16)));
+                }
+            }
+        }
+        Node::Branch(branch) => {
+            let child = size / 4;
+            for (i, node) in branch.children.iter().enumerate() {
+                let offset = (i as i32 % 4, i as i32 / 4 % 4, i as i32 / 16);
+                let offset = (offset.0 * child, offset.1 * child, offset.2 * child);
+                find_emitters(node, add(origin, offset), child, found);
+            }
+        }
+    }
+}
+
+// one step further from the light
+fn decay(value: u8) -> u8 {
+    let sun = (value >> 4).saturating_sub(1);
+    let block = (value & 0x0F).saturating_sub(1);
+    sun << 4 | block
+}
+
+// per channel maximum
+fn brightest(a: u8, b: u8) -> u8 {
+    (a & 0xF0).max(b & 0xF0) | (a & 0x0F).max(b & 0x0F)
+}
+
+fn local_index(local: Coords) -> u32 {
+    (local.0 + local.1 * CHUNK_SIZE + local.2 * CHUNK_SIZE * CHUNK_SIZE) as u32
+}
+
+fn local_coords(index: u32) -> Coords {
+    let i = index as i32;
+    (i % CHUNK_SIZE, i / CHUNK_SIZE % CHUNK_SIZE, i / (CHUNK_SIZE * CHUNK_SIZE))
+}
+
+fn chunk_origin(chunk: Coords) -> Coords {
+    (chunk.0 * CHUNK_SIZE, chunk.1 * CHUNK_SIZE, chunk.2 * CHUNK_SIZE)
+}
+
+fn overlaps(chunk: Coords, low: Coords, high: Coords) -> bool {
+    let origin = chunk_origin(chunk);
+    let end = add(origin, (CHUNK_SIZE, CHUNK_SIZE, CHUNK_SIZE));
+
+    origin.0 < high.0
+        && end.0 > low.0
+        && origin.1 < high.1
+        && end.1 > low.1
+        && origin.2 < high.2
+        && end.2 > low.2
+}
+
+fn min3(a: Coords, b: Coords) -> Coords {
+    (a.0.min(b.0), a.1.min(b.1), a.2.min(b.2))
+}
+
+fn max3(a: Coords, b: Coords) -> Coords {
+    (a.0.max(b.0), a.1.max(b.1), a.2.max(b.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::block::{fluid_block, AIR, FULL, LAVA, STONE};
+
+    // hollow stone box, the air inside is 101..=109 on every axis
+    const LOW: i32 = 100;
+    const HIGH: i32 = 110;
+
+    // ground at y = 0 around a closed box, lit and settled
+    fn sealed_box() -> (Scene, LightField) {
+        let mut scene = Scene::new();
+        scene.add_chunk(Node::Empty, (0, 0, 0));
+        for x in 60..150 {
+            for z in 60..150 {
+                scene.set_voxel((x, 0, z), STONE);
+            }
+        }
+        for z in LOW..=HIGH {
+            for y in LOW..=HIGH {
+                for x in LOW..=HIGH {
+                    if [x, y, z].iter().any(|v| *v == LOW || *v == HIGH) {
+                        scene.set_voxel((x, y, z), STONE);
+                    }
+                }
+            }
+        }
+
+        let mut light = LightField::new();
+        light.sync(&scene);
+        flush(&mut light, &scene);
+        (scene, light)
+    }
+
+    fn flush(light: &mut LightField, scene: &Scene) {
+        while light.update(scene) {}
+    }
+
+    fn inside_box() -> impl Iterator<Item = Coords> {
+        let span = LOW + 1..HIGH;
+        span.clone().flat_map(move |z| {
+            let span = span.clone();
+            span.clone()
+                .flat_map(move |y| span.clone().map(move |x| (x, y, z)))
+        })
+    }
+
+    #[test]
+    fn sealed_box_stays_dark() {
+        let (_, light) = sealed_box();
+        for pos in inside_box() {
+            assert_eq!(light.get(pos), 0, "light at {:?}", pos);
+        }
+        assert_eq!(light.get((105, HIGH + 1, 105)), FULL_SUN);
+        // under the box sunlight comes in sideways, five steps from the edge
+        assert_eq!(light.get((105, LOW - 1, 105)) >> 4, 9);
+    }
+
+    #[test]
+    fn light_drops_one_per_step() {
+        let (mut scene, mut light) = sealed_box();
+        let lamp = (105, 105, 105);
+        scene.set_voxel(lamp, fluid_block(LAVA, FULL));
+        light.voxels_changed(&scene, &[lamp]);
+        flush(&mut light, &scene);
+
+        for pos in inside_box() {
+            let steps = (pos.0 - lamp.0).abs() + (pos.1 - lamp.1).abs() + (pos.2 - lamp.2).abs();
+            assert_eq!(light.get(pos), 15 - steps as u8, "light at {:?}", pos);
+        }
+    }
+
+    #[test]
+    fn breaking_relights_only_its_box() {
+        let (mut scene, mut light) = sealed_box();
+        let region = |light: &LightField| {
+            let mut values = HashMap::new();
+            for z in 70..140 {
+                for y in 70..140 {
+                    for x in 70..140 {
+                        values.insert((x, y, z), light.get((x, y, z)));
+                    }
+                }
+            }
+            values
+        };
+        let before = region(&light);
+
+        let hole = (HIGH, 105, 105);
+        scene.set_voxel(hole, AIR);
+        light.voxels_changed(&scene, &[hole]);
+        // queued, nothing is relit before the update
+        assert_eq!(region(&light), before);
+        assert!(light.update(&scene));
+        assert!(!light.update(&scene));
+
+        let after = region(&light);
+        let mut changed = 0;
+        for (pos, value) in &after {
+            if before[pos] == *value {
+                continue;
+            }
+            changed += 1;
+            let offset = (pos.0 - hole.0, pos.1 - hole.1, pos.2 - hole.2);
+            assert!(
+                offset.0.abs() <= REACH && offset.1.abs() <= REACH && offset.2.abs() <= REACH,
+                "{:?} changed outside the relight box",
+                pos
+            );
+        }
+        assert!(changed > 0);
+        assert_eq!(after[&hole] >> 4, 14);
+        assert_eq!(after[&(HIGH - 1, 105, 105)] >> 4, 13);
+        assert_eq!(light.take_dirty(), vec![(0, 0, 0)]);
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+
+use crate::core::block::{self, BlockId};
+use crate::core::registry::registry;
+
+// How every block id looks, uploaded to the shader as one table indexed by
+// the id. Registered blocks bring their own, blocks from .vox palettes get
+// ids from block::PALETTE up, with the palette color and the MATL properties
+// MagicaVoxel saved for them.
+
+#[derive(Clone, Copy)]
+pub struct Material {
+    pub color: [f32; 3],
+    // 0 is a mirror, 1 fully diffuse
+    pub roughness: f32,
+    pub metalness: f32,
+    // share of the light passing through, refracted by ior
+    pub transparency: f32,
+    pub ior: f32,
+    // 0..=1, 1 glows with the full color
+    pub emission: f32,
+}
+
+impl Material {
+    pub const fn diffuse(color: [f32; 3]) -> Self {
+        Self {
+            color,
+            roughness: 1.0,
+            metalness: 0.0,
+            transparency: 0.0,
+            ior: 1.0,
+            emission: 0.0,
+        }
+    }
+
+    // palette color with the entry's MATL chunk, if the file has one
+    pub fn from_vox(color: [u8; 4], matl: Option<&dot_vox::Material>) -> Self {
+        let [r, g, b, _] = color.map(|c| c as f32 / 255.0);
+        let mut material = Material::diffuse([r, g, b]);
+        let Some(matl) = matl else {
+            return material;
+        };
+
+        match matl.material_type() {
+            Some("_metal") => {
+                material.metalness = matl.metalness().unwrap_or(1.0);
+                material.roughness = matl.roughness().unwrap_or(0.1);
+            }
+            Some("_glass") => {
+                material.transparency = matl.transparency().unwrap_or(1.0);
+                material.roughness = matl.roughness().unwrap_or(0.0);
+                // MagicaVoxel stores the index minus one
+                material.ior = 1.0 + matl.refractive_index().unwrap_or(0.5);
+            }
+            Some("_emit") => {
+                material.emission = matl.emission().unwrap_or(1.0);
+            }
+            _ => {}
+        }
+        material.roughness = material.roughness.clamp(0.0, 1.0);
+        material.metalness = material.metalness.clamp(0.0, 1.0);
+        material.transparency = material.transparency.clamp(0.0, 1.0);
+        material.emission = material.emission.clamp(0.0, 1.0);
+        material
+    }
+}
+
+pub struct Materials {
+    table: Vec<Material>,
+    // next block id handed to a palette entry
+    next_palette: usize,
+    // set when the table changed, it needs uploading
+    changed: bool,
+}
+
+impl Materials {
+    // the looks from the block registry, see blocks.txt
+    pub fn new() -> Self {
+        let table = (0..=255).map(|id| registry().get(id).material).collect();
+
+        Self {
+            table,
+            next_palette: block::PALETTE as usize,
+            changed: true,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Material> {
+        self.table.iter()
+    }
+
+    // gives every palette entry its own block id, returns the block per
+    // palette index. Entries past the last free id are left out.
+    pub fn import(&mut self, palette: &[(u8, Material)]) -> HashMap<u8, BlockId> {
+        let mut blocks = HashMap::new();
+        for (index, material) in palette {
+            if self.next_palette >= self.table.len() {
+                eprintln!("No block ids left for palette entry {}", index);
+                continue;
+            }
+
+            self.table[self.next_palette] = *material;
+            blocks.insert(*index, self.next_palette as BlockId);
+            self.next_palette += 1;
+        }
+
+        self.changed = true;
+        blocks
+    }
+
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(out: &mut Vec<u8>, text: &str) {
+        out.extend_from_slice(&(text.len() as i32).to_le_bytes());
+        out.extend_from_slice(text.as_bytes());
+    }
+
+    fn chunk(id: &[u8; 4], content: &[u8], children: &[u8]) -> Vec<u8> {
+        let mut out = id.to_vec();
+        out.extend_from_slice(&(content.len() as i32).to_le_bytes());
+        out.extend_from_slice(&(children.len() as i32).to_le_bytes());
+        out.extend_from_slice(content);
+        out.extend_from_slice(children);
+        out
+    }
+
+    fn matl(id: i32, properties: &[(&str, &str)]) -> Vec<u8> {
+        let mut content = id.to_le_bytes().to_vec();
+        content.extend_from_slice(&(properties.len() as i32).to_le_bytes());
+        for (key, value) in properties {
+            string(&mut content, key);
+            string(&mut content, value);
+        }
+        chunk(b"MATL", &content, &[])
+    }
+
+    // one voxel each of palette entries 1, 2 and 3 with a MATL chunk each,
+    // the file counts colors from 1
+    fn vox_file() -> Vec<u8> {
+        let size: Vec<u8> = [3i32, 1, 1].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let mut voxels = 3i32.to_le_bytes().to_vec();
+        voxels.extend_from_slice(&[0, 0, 0, 2, 1, 0, 0, 3, 2, 0, 0, 4]);
+
+        let mut children = chunk(b"SIZE", &size, &[]);
+        children.extend(chunk(b"XYZI", &voxels, &[]));
+        children.extend(matl(
+            2,
+            &[("_type", "_metal"), ("_metal", "0.8"), ("_rough", "0.3")],
+        ));
+        children.extend(matl(
+            3,
+            &[("_type", "_glass"), ("_trans", "0.6"), ("_ior", "0.5")],
+        ));
+        children.extend(matl(4, &[("_type", "_emit"), ("_emit", "2.0")]));
+
+        let mut file = b"VOX ".to_vec();
+        file.extend_from_slice(&150i32.to_le_bytes());
+        file.extend(chunk(b"MAIN", &[], &children));
+        file
+    }
+
+    #[test]
+    fn imports_matl_chunks() {
+        let data = dot_vox::load_bytes(&vox_file()).unwrap();
+        // voxel i is palette entry i, its MATL chunk has the id i + 1
+        let material = |voxel: usize| {
+            let i = data.models[0].voxels[voxel].i as u32;
+            let matl = data.materials.iter().find(|m| m.id == i + 1);
+            assert!(matl.is_some(), "no MATL chunk for entry {}", i);
+            Material::from_vox([255, 128, 0, 255], matl)
+        };
+
+        let metal = material(0);
+        assert_eq!(metal.metalness, 0.8);
+        assert_eq!(metal.roughness, 0.3);
+        assert_eq!(metal.transparency, 0.0);
+
+        let glass = material(1);
+        assert_eq!(glass.transparency, 0.6);
+        assert_eq!(glass.ior, 1.5);
+        assert_eq!(glass.roughness, 0.0);
+        assert_eq!(glass.metalness, 0.0);
+
+        // clamped to full glow
+        let emit = material(2);
+        assert_eq!(emit.emission, 1.0);
+        assert_eq!(emit.color, [1.0, 128.0 / 255.0, 0.0]);
+        assert_eq!(emit.transparency, 0.0);
+
+        let plain = Material::from_vox([255; 4], None);
+        assert_eq!((plain.roughness, plain.emission), (1.0, 0.0));
+    }
+}
@@ -1,6 +1,21 @@
+pub mod block;
 pub mod types;
 
+pub mod active;
+pub mod automata;
+pub mod bvh;
+pub mod clock;
 pub mod cpu_side_svo;
+pub mod destruction;
+pub mod fluids;
+pub mod gravity;
+pub mod instances;
+pub mod leaf_bits;
+pub mod light;
+pub mod materials;
+pub mod objects;
+pub mod regions;
+pub mod registry;
 
 pub mod game;
 
@@ -0,0 +1,291 @@
+use nalgebra::{UnitQuaternion, Vector3};
+
+use crate::core::block::{is_solid, BlockId, AIR};
+use crate::core::types::{Node, Scene, CHUNK_SIZE};
+
+// Movable voxel objects. Each keeps its own tree in object space, voxel
+// (0, 0, 0) at the corner, and a transform placing it in the world. Fixed
+// updates integrate gravity and resolve collisions against the static scene
+// one axis at a time using the object's surface voxels as contact points.
+
+// voxels per second squared
+const GRAVITY: f32 = -30.0;
+const MAX_SPEED: f32 = 40.0;
+// velocity kept after bouncing off something
+const RESTITUTION: f32 = 0.3;
+// horizontal and angular velocity kept per step on the ground
+const FRICTION: f32 = 0.85;
+// below this speed for SLEEP_STEPS steps an object stops simulating
+const SLEEP_SPEED: f32 = 0.5;
+const SLEEP_STEPS: u32 = 30;
+// contact points tested per object, larger objects use every n-th
+const MAX_POINTS: usize = 2048;
+// objects that are not written back when resting, the oldest goes first
+const MAX_UNSETTLED: usize = 16;
+
+type Coords = (i32, i32, i32);
+
+pub struct VoxelObject {
+    pub id: u32,
+    pub root: Node,
+    // voxels per axis, at most CHUNK_SIZE
+    pub size: (i32, i32, i32),
+    pub position: Vector3<f32>,
+    pub rotation: UnitQuaternion<f32>,
+    pub velocity: Vector3<f32>,
+    pub angular_velocity: Vector3<f32>,
+    // object space point the transform rotates around, the center of mass
+    pub center: Vector3<f32>,
+    // written back into the scene once it comes to rest, for debris
+    pub settle: bool,
+
+    voxels: Vec<(Coords, BlockId)>,
+    points: Vec<Vector3<f32>>,
+    still_steps: u32,
+    resting: bool,
+}
+
+impl VoxelObject {
+    // `voxels` in world coords, the object starts exactly where they are
+    pub fn from_voxels(voxels: &[(Coords, BlockId)]) -> Option<VoxelObject> {
+        let first = voxels.first()?.0;
+        let mut low = first;
+        let mut high = first;
+        for ((x, y, z), _) in voxels {
+            low = (low.0.min(*x), low.1.min(*y), low.2.min(*z));
+            high = (high.0.max(*x), high.1.max(*y), high.2.max(*z));
+        }
+
+        let size = (high.0 - low.0 + 1, high.1 - low.1 + 1, high.2 - low.2 + 1);
+        if size.0 > CHUNK_SIZE || size.1 > CHUNK_SIZE || size.2 > CHUNK_SIZE {
+            return None;
+        }
+
+        let mut root = Node::Empty;
+        let mut local = Vec::with_capacity(voxels.len());
+        let mut center = Vector3::zeros();
+        for ((x, y, z), block) in voxels {
+            let pos = (x - low.0, y - low.1, z - low.2);
+            root.set(pos, *block);
+            local.push((pos, *block));
+            center += voxel_center(pos);
+        }
+        center /= voxels.len() as f32;
+
+        let position = Vector3::new(low.0 as f32, low.1 as f32, low.2 as f32) + center;
+        let points = surface_points(&root, &local);
+
+        Some(VoxelObject {
+            id: 0,
+            root,
+            size,
+            position,
+            rotation: UnitQuaternion::identity(),
+            velocity: Vector3::zeros(),
+            angular_velocity: Vector3::zeros(),
+            center,
+            settle: false,
+            voxels: local,
+            points,
+            still_steps: 0,
+            resting: false,
+        })
+    }
+
+    // object space point -> world
+    pub fn to_world(&self, local: Vector3<f32>) -> Vector3<f32> {
+        self.position + self.rotation * (local - self.center)
+    }
+
+    // free of the scene where it is now
+    pub fn fits(&self, scene: &Scene) -> bool {
+        !self.collides(scene)
+    }
+
+    fn step(&mut self, scene: &Scene, dt: f32) {
+        if self.resting {
+            return;
+        }
+
+        self.velocity.y += GRAVITY * dt;
+        self.velocity = self.velocity.cap_magnitude(MAX_SPEED);
+
+        for axis in 0..3 {
+            let delta = self.velocity[axis] * dt;
+            if delta == 0.0 {
+                continue;
+            }
+
+            self.position[axis] += delta;
+            if !self.collides(scene) {
+                continue;
+            }
+            self.position[axis] -= delta;
+
+            self.velocity[axis] *= -RESTITUTION;
+            if axis == 1 {
+                // resting on something
+                self.velocity.x *= FRICTION;
+                self.velocity.z *= FRICTION;
+                self.angular_velocity *= FRICTION;
+            }
+        }
+
+        let previous = self.rotation;
+        self.rotation =
+            UnitQuaternion::from_scaled_axis(self.angular_velocity * dt) * self.rotation;
+        if self.collides(scene) {
+            self.rotation = previous;
+            self.angular_velocity *= RESTITUTION;
+        }
+
+        let speed = self.velocity.norm() + self.angular_velocity.norm() * self.size_radius();
+        if speed < SLEEP_SPEED {
+            self.still_steps += 1;
+        } else {
+            self.still_steps = 0;
+        }
+        self.resting = self.still_steps >= SLEEP_STEPS;
+    }
+
+    fn collides(&self, scene: &Scene) -> bool {
+        self.points.iter().any(|point| {
+            let world = self.to_world(*point);
+            let voxel = (
+                world.x.floor() as i32,
+                world.y.floor() as i32,
+                world.z.floor() as i32,
+            );
+            // the edge of what is loaded stops objects as well
+            !scene.is_editable(voxel) || is_solid(scene.get_voxel(voxel))
+        })
+    }
+
+    fn size_radius(&self) -> f32 {
+        let (x, y, z) = self.size;
+        Vector3::new(x as f32, y as f32, z as f32).norm() / 2.0
+    }
+
+    // writes the voxels into the scene grid at their rounded world positions,
+    // returns the voxels that changed
+    fn stamp(&self, scene: &mut Scene) -> Vec<Coords> {
+        let mut edits = Vec::new();
+        for (pos, block) in &self.voxels {
+            let world = self.to_world(voxel_center(*pos));
+            let voxel = (
+                world.x.floor() as i32,
+                world.y.floor() as i32,
+                world.z.floor() as i32,
+            );
+            if !is_solid(scene.get_voxel(voxel)) {
+                edits.push((voxel, *block));
+            }
+        }
+        scene.set_voxels(&edits)
+    }
+}
+
+pub struct Objects {
+    objects: Vec<VoxelObject>,
+    next_id: u32,
+    // set when objects were added or removed, their trees need staging
+    changed: bool,
+    // set when an object moved, its transform needs uploading
+    moved: bool,
+}
+
+impl Objects {
+    pub fn new() -> Self {
+        Self {
+            objects: Vec::new(),
+            next_id: 1,
+            changed: false,
+            moved: false,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &VoxelObject> {
+        self.objects.iter()
+    }
+
+    pub fn add(&mut self, mut object: VoxelObject) -> u32 {
+        object.id = self.next_id;
+        self.next_id += 1;
+        self.changed = true;
+
+        let unsettled = self.objects.iter().filter(|o| !o.settle).count();
+        if !object.settle && unsettled >= MAX_UNSETTLED {
+            if let Some(oldest) = self.objects.iter().position(|o| !o.settle) {
+                self.objects.remove(oldest);
+            }
+        }
+
+        let id = object.id;
+        self.objects.push(object);
+        id
+    }
+
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+
+    pub fn take_moved(&mut self) -> bool {
+        std::mem::take(&mut self.moved)
+    }
+
+    // moves every object, settling ones are written back into the scene
+    // when they come to rest, returns the voxels that changed
+    pub fn update(&mut self, scene: &mut Scene, dt: f32) -> Vec<Coords> {
+        let mut changed = Vec::new();
+
+        for object in self.objects.iter_mut() {
+            // resting objects stay where they are for good
+            self.moved |= !object.resting;
+            object.step(scene, dt);
+        }
+
+        let before = self.objects.len();
+        self.objects.retain(|object| {
+            if object.settle && object.resting {
+                changed.extend(object.stamp(scene));
+                return false;
+            }
+            true
+        });
+        self.changed |= self.objects.len() != before;
+
+        changed
+    }
+}
+
+fn voxel_center((x, y, z): Coords) -> Vector3<f32> {
+    Vector3::new(x as f32 + 0.5, y as f32 + 0.5, z as f32 + 0.5)
+}
+
+// voxel centers with an empty face, touching anything happens through them
+fn surface_points(root: &Node, voxels: &[(Coords, BlockId)]) -> Vec<Vector3<f32>> {
+    let open = |(x, y, z): Coords| {
+        let outside = |v: i32| !(0..CHUNK_SIZE).contains(&v);
+        outside(x) || outside(y) || outside(z) || root.get((x, y, z)) == AIR
+    };
+
+    let surface: Vec<Vector3<f32>> = voxels
+        .iter()
+        .filter(|((x, y, z), _)| {
+            open((x + 1, *y, *z))
+                || open((x - 1, *y, *z))
+                || open((*x, y + 1, *z))
+                || open((*x, y - 1, *z))
+                || open((*x, *y, z + 1))
+                || open((*x, *y, z - 1))
+        })
+        .map(|(pos, _)| voxel_center(*pos))
+        .collect();
+
+    let stride = surface.len().div_ceil(MAX_POINTS).max(1);
+    surface.into_iter().step_by(stride).collect()
+}
@@ -0,0 +1,251 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::core::active::{add, NEIGHBORS};
+use crate::core::block::{is_solid, BlockId};
+use crate::core::leaf_bits::{at_or_below, X0, X3, Y0, Z0, Z3};
+use crate::core::leaf_bits::{fill_within, leaf_cells, leaf_origin, spill, split_leaf};
+use crate::core::types::Scene;
+
+// Flood fill and connected components over leaf bitmasks. A leaf is filled
+// all at once by growing the mask inside it, only the bits on its faces are
+// handed to the leaves next to it.
+
+type Coords = (i32, i32, i32);
+
+// set of voxels as one mask per leaf
+pub struct Region {
+    pub leaves: HashMap<Coords, u64>,
+}
+
+impl Region {
+    fn new() -> Self {
+        Self {
+            leaves: HashMap::new(),
+        }
+    }
+
+    pub fn count(&self) -> u32 {
+        self.leaves.values().map(|bits| bits.count_ones()).sum()
+    }
+
+    pub fn voxels(&self) -> impl Iterator<Item = Coords> + '_ {
+        self.leaves
+            .iter()
+            .flat_map(|(leaf, bits)| leaf_cells(*leaf, *bits))
+    }
+}
+
+// voxels connected to `start` through blocks that pass `open`, not above
+// `top` if given, None when more than `max` are reached so unbounded areas
+// (open sky) are given up on
+pub fn flood_fill(
+    scene: &Scene,
+    start: Coords,
+    top: Option<i32>,
+    max: u32,
+    open: impl Fn(BlockId) -> bool,
+) -> Option<Region> {
+    let mut region = Region::new();
+    let mut masks: HashMap<Coords, u64> = HashMap::new();
+    let mut count = 0;
+
+    let (leaf, bit) = split_leaf(start);
+    let mut queue = VecDeque::new();
+    queue.push_back((leaf, 1u64 << bit));
+
+    while let Some((leaf, seed)) = queue.pop_front() {
+        let mask = *masks.entry(leaf).or_insert_with(|| {
+            let mask = scene.leaf_mask(leaf_origin(leaf), &open);
+            top.map_or(mask, |top| mask & at_or_below(leaf.1, top))
+        });
+        let filled = region.leaves.entry(leaf).or_default();
+
+        // what is already filled was spread from before
+        let new = fill_within(seed, mask & !*filled);
+        if new == 0 {
+            continue;
+        }
+        *filled |= new;
+
+        count += new.count_ones();
+        if count > max {
+            return None;
+        }
+
+        for dir in NEIGHBORS {
+            let next = spill(new, dir);
+            if next != 0 {
+                queue.push_back((add(leaf, dir), next));
+            }
+        }
+    }
+
+    region.leaves.retain(|_, bits| *bits != 0);
+    Some(region)
+}
+
+// a room, or a lake basin with `top` at the water level: fills the air around
+// `start` with `block` if it is closed off within `max` voxels, returns the
+// voxels that changed
+pub fn fill_enclosed(
+    scene: &mut Scene,
+    start: Coords,
+    top: Option<i32>,
+    block: BlockId,
+    max: u32,
+) -> Vec<Coords> {
+    let Some(region) = flood_fill(scene, start, top, max, |b| !is_solid(b)) else {
+        return Vec::new();
+    };
+
+    region
+        .voxels()
+        .collect::<Vec<_>>()
+        .into_iter()
+        .filter(|pos| scene.set_voxel(*pos, block))
+        .collect()
+}
+
+pub struct Component {
+    pub region: Region,
+    // touches the side or bottom of the searched box, so it may be held up
+    // by something outside
+    pub grounded: bool,
+}
+
+// connected solid voxels inside the box low..high, rounded out to whole leaves
+pub fn components(scene: &Scene, low: Coords, high: Coords) -> Vec<Component> {
+    let (first, _) = split_leaf(low);
+    let (last, _) = split_leaf(add(high, (-1, -1, -1)));
+    let inside = |leaf: Coords| {
+        (first.0..=last.0).contains(&leaf.0)
+            && (first.1..=last.1).contains(&leaf.1)
+            && (first.2..=last.2).contains(&leaf.2)
+    };
+
+    // every leaf split into the pieces connected inside it
+    let mut pieces: Vec<(Coords, u64)> = Vec::new();
+    let mut by_leaf: HashMap<Coords, Vec<usize>> = HashMap::new();
+    for z in first.2..=last.2 {
+        for y in first.1..=last.1 {
+            for x in first.0..=last.0 {
+                let leaf = (x, y, z);
+                let mut solid = scene.leaf_mask(leaf_origin(leaf), is_solid);
+
+                while solid != 0 {
+                    let piece = fill_within(solid & solid.wrapping_neg(), solid);
+                    solid &= !piece;
+
+                    by_leaf.entry(leaf).or_default().push(pieces.len());
+                    pieces.push((leaf, piece));
+                }
+            }
+        }
+    }
+
+    // pieces touching across a leaf face are one component
+    let mut parents: Vec<usize> = (0..pieces.len()).collect();
+    for (i, (leaf, bits)) in pieces.iter().enumerate() {
+        for dir in [(1, 0, 0), (0, 1, 0), (0, 0, 1)] {
+            let next = add(*leaf, dir);
+            let touching = spill(*bits, dir);
+            if touching == 0 || !inside(next) {
+                continue;
+            }
+
+            for j in by_leaf.get(&next).into_iter().flatten() {
+                if pieces[*j].1 & touching != 0 {
+                    union(&mut parents, i, *j);
+                }
+            }
+        }
+    }
+
+    // everything but the top counts as ground
+    let grounded = |(leaf, bits): &(Coords, u64)| {
+        (leaf.0 == first.0 && bits & X0 != 0)
+            || (leaf.0 == last.0 && bits & X3 != 0)
+            || (leaf.1 == first.1 && bits & Y0 != 0)
+            || (leaf.2 == first.2 && bits & Z0 != 0)
+            || (leaf.2 == last.2 && bits & Z3 != 0)
+    };
+
+    let mut groups: HashMap<usize, Component> = HashMap::new();
+    for (i, piece) in pieces.iter().enumerate() {
+        let root = find(&mut parents, i);
+        let component = groups.entry(root).or_insert_with(|| Component {
+            region: Region::new(),
+            grounded: false,
+        });
+
+        *component.region.leaves.entry(piece.0).or_default() |= piece.1;
+        component.grounded |= grounded(piece);
+    }
+    groups.into_values().collect()
+}
+
+// solid pieces inside the box that nothing holds up, floating islands or
+// debris after the ground under them was removed
+pub fn detached(scene: &Scene, low: Coords, high: Coords) -> Vec<Region> {
+    components(scene, low, high)
+        .into_iter()
+        .filter(|component| !component.grounded)
+        .map(|component| component.region)
+        .collect()
+}
+
+fn find(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+fn union(parents: &mut [usize], a: usize, b: usize) {
+    let (a, b) = (find(parents, a), find(parents, b));
+    if a != b {
+        parents[a.max(b)] = a.min(b);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::block::{AIR, STONE, WATER};
+    use crate::core::types::Node;
+
+    // 8x8 basin with walls up to y = 3 on a floor at y = 0
+    fn basin() -> Scene {
+        let mut scene = Scene::new();
+        scene.add_chunk(Node::Empty, (0, 0, 0));
+        for x in 0..10 {
+            for z in 0..10 {
+                scene.set_voxel((x, 0, z), STONE);
+                if x == 0 || x == 9 || z == 0 || z == 9 {
+                    for y in 1..=3 {
+                        scene.set_voxel((x, y, z), STONE);
+                    }
+                }
+            }
+        }
+        scene
+    }
+
+    #[test]
+    fn basin_fills_up_to_the_top() {
+        let mut scene = basin();
+        assert!(fill_enclosed(&mut scene, (4, 2, 4), None, WATER, 10_000).is_empty());
+
+        let filled = fill_enclosed(&mut scene, (4, 2, 4), Some(2), WATER, 10_000);
+        assert_eq!(filled.len(), 8 * 8 * 2);
+        assert!(filled.iter().all(|pos| (1..=2).contains(&pos.1)));
+        assert_eq!(scene.get_voxel((4, 3, 4)), AIR);
+    }
+
+    #[test]
+    fn basin_spills_over_low_walls() {
+        let mut scene = basin();
+        assert!(fill_enclosed(&mut scene, (4, 2, 4), Some(4), WATER, 10_000).is_empty());
+    }
+}
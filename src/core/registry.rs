@@ -0,0 +1,258 @@
+use std::fs;
+use std::ops::RangeInclusive;
+use std::str::SplitWhitespace;
+use std::sync::OnceLock;
+
+use crate::core::block::*;
+use crate::core::materials::Material;
+
+// Every block id and what it does, read from a data file, see blocks.txt.
+// The ids the code places itself are the constants in core/block.rs, the
+// world generator, physics, light, editing and the shader's material table
+// all look the id up here.
+
+pub const BLOCKS_FILE: &str = "blocks.txt";
+// built into the binary, used when no file is next to it
+const BUILT_IN: &str = include_str!("../../blocks.txt");
+
+static REGISTRY: OnceLock<Registry> = OnceLock::new();
+
+#[derive(Clone)]
+pub struct BlockType {
+    pub name: String,
+    pub material: Material,
+    // collides and holds up what rests on it
+    pub solid: bool,
+    // light passes through
+    pub transparent: bool,
+    // block light given off, 0..=15
+    pub emission: u8,
+    // resistance to explosions, 0..=1
+    pub hardness: f32,
+    // falls when nothing solid is below
+    pub loose: bool,
+    // first id of the fluid's levels
+    pub fluid: Option<BlockId>,
+}
+
+impl BlockType {
+    // what ids missing from the file are
+    fn unnamed() -> Self {
+        Self {
+            name: String::new(),
+            material: Material::diffuse([1.0; 3]),
+            solid: true,
+            transparent: false,
+            emission: 0,
+            hardness: 0.5,
+            loose: false,
+            fluid: None,
+        }
+    }
+}
+
+pub struct Registry {
+    types: Vec<BlockType>,
+}
+
+impl Registry {
+    // on error, the number of the line that could not be read
+    pub fn parse(text: &str) -> Result<Registry, usize> {
+        let mut types = vec![BlockType::unnamed(); 256];
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (ids, block) = parse_line(line).ok_or(number + 1)?;
+            for id in ids {
+                types[id as usize] = block.clone();
+            }
+        }
+
+        Ok(Registry { types })
+    }
+
+    pub fn get(&self, block: BlockId) -> &BlockType {
+        &self.types[block as usize]
+    }
+
+    // the ids the code places itself must do what it expects of them, on
+    // error what is wrong
+    pub fn check(&self) -> Result<(), String> {
+        let air = self.get(AIR);
+        if air.solid || !air.transparent {
+            return Err("air must be nonsolid and transparent".to_string());
+        }
+
+        for (name, base) in [("water", WATER), ("lava", LAVA)] {
+            let last = fluid_block(base, SOURCE);
+            if (base..=last).any(|id| self.get(id).fluid != Some(base)) {
+                return Err(format!("{} must be a fluid at {}-{}", name, base, last));
+            }
+        }
+
+        let placed = [
+            STONE, DIRT, GRASS, SAND, SANDSTONE, SNOW, GRAVEL, WOOD, LEAVES, COAL_ORE, IRON_ORE,
+            CELL, DYING_CELL,
+        ];
+        for id in placed {
+            let block = self.get(id);
+            if block.name.is_empty() || block.fluid.is_some() {
+                return Err(format!("block {} must be named and not a fluid", id));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// the file next to the binary, or the built in types if there is none
+pub fn load(path: &str) {
+    let registry = match fs::read_to_string(path) {
+        Ok(text) => match Registry::parse(&text) {
+            Ok(registry) => match registry.check() {
+                Ok(()) => registry,
+                Err(problem) => {
+                    eprintln!("{}: {}, using the built in blocks", path, problem);
+                    built_in()
+                }
+            },
+            Err(line) => {
+                eprintln!(
+                    "{} line {} not understood, using the built in blocks",
+                    path, line
+                );
+                built_in()
+            }
+        },
+        Err(_) => built_in(),
+    };
+
+    if REGISTRY.set(registry).is_err() {
+        eprintln!("Block registry was already loaded");
+    }
+}
+
+pub fn registry() -> &'static Registry {
+    REGISTRY.get_or_init(built_in)
+}
+
+fn built_in() -> Registry {
+    Registry::parse(BUILT_IN).expect("built in blocks.txt is valid")
+}
+
+// `id` or `first-last`, a name, then the properties
+fn parse_line(line: &str) -> Option<(RangeInclusive<BlockId>, BlockType)> {
+    let mut words = line.split_whitespace();
+
+    let ids = words.next()?;
+    let (first, last) = ids.split_once('-').unwrap_or((ids, ids));
+    let (first, last): (BlockId, BlockId) = (first.parse().ok()?, last.parse().ok()?);
+    if first > last {
+        return None;
+    }
+
+    let mut block = BlockType {
+        name: words.next()?.to_string(),
+        ..BlockType::unnamed()
+    };
+
+    while let Some(word) = words.next() {
+        match word {
+            "color" => {
+                block.material.color = [
+                    number(&mut words)?,
+                    number(&mut words)?,
+                    number(&mut words)?,
+                ]
+            }
+            "roughness" => block.material.roughness = number(&mut words)?.clamp(0.0, 1.0),
+            "metalness" => block.material.metalness = number(&mut words)?.clamp(0.0, 1.0),
+            "transparency" => block.material.transparency = number(&mut words)?.clamp(0.0, 1.0),
+            "ior" => block.material.ior = number(&mut words)?.max(1.0),
+            "emission" => block.emission = number(&mut words)?.clamp(0.0, 15.0) as u8,
+            "hardness" => block.hardness = number(&mut words)?.clamp(0.0, 1.0),
+            "transparent" => block.transparent = true,
+            "nonsolid" => block.solid = false,
+            "loose" => block.loose = true,
+            "fluid" => block.fluid = Some(first),
+            _ => return None,
+        }
+    }
+    block.material.emission = block.emission as f32 / 15.0;
+
+    // the fluid code counts on every level having an id
+    if block.fluid.is_some() && last - first + 1 != SOURCE {
+        return None;
+    }
+
+    Some((first..=last, block))
+}
+
+fn number(words: &mut SplitWhitespace) -> Option<f32> {
+    words.next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ids_and_ranges() {
+        let (ids, block) = parse_line("7 gravel loose").unwrap();
+        assert_eq!(ids, 7..=7);
+        assert_eq!(block.name, "gravel");
+        assert!(block.loose && block.solid);
+
+        let (ids, _) = parse_line("20-23 glass transparent").unwrap();
+        assert_eq!(ids, 20..=23);
+
+        assert!(parse_line("23-20 glass").is_none());
+        assert!(parse_line("300 glass").is_none());
+        assert!(parse_line("5").is_none());
+    }
+
+    #[test]
+    fn clamps_values() {
+        let (_, block) = parse_line("1 lamp emission 40 hardness -2 roughness 3 ior 0.5").unwrap();
+        assert_eq!(block.emission, 15);
+        assert_eq!(block.material.emission, 1.0);
+        assert_eq!(block.hardness, 0.0);
+        assert_eq!(block.material.roughness, 1.0);
+        assert_eq!(block.material.ior, 1.0);
+    }
+
+    #[test]
+    fn rejects_unknown_words() {
+        assert!(parse_line("1 stone shiny").is_none());
+        assert!(parse_line("1 stone color 0.5 0.5").is_none());
+        assert!(parse_line("1 stone hardness much").is_none());
+    }
+
+    #[test]
+    fn fluid_needs_one_id_per_level() {
+        let (ids, block) = parse_line("16-24 water fluid nonsolid").unwrap();
+        assert_eq!(ids, 16..=24);
+        assert_eq!(block.fluid, Some(16));
+
+        assert!(parse_line("16-23 water fluid").is_none());
+        assert!(parse_line("16 water fluid").is_none());
+    }
+
+    #[test]
+    fn checks_placed_ids() {
+        assert!(built_in().check().is_ok());
+
+        let solid_air = BUILT_IN.replace("0 air nonsolid transparent", "0 air transparent");
+        assert!(Registry::parse(&solid_air).unwrap().check().is_err());
+
+        let moved_water = BUILT_IN.replace("16-24 water", "48-56 water");
+        assert!(Registry::parse(&moved_water).unwrap().check().is_err());
+
+        let no_stone = BUILT_IN.replace("1 stone", "# 1 stone");
+        assert!(Registry::parse(&no_stone).unwrap().check().is_err());
+    }
+}
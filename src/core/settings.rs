@@ -3,10 +3,54 @@ use std::collections::HashMap;
 use winit::keyboard::KeyCode;
 
 use crate::core::settings;
+use crate::core::types::FULL_DEPTH;
 
 pub struct Settings {
     key_bindings: HashMap<Action, KeyCode>,
     field_of_view: f64,
+    // chunk radius kept loaded around the camera, everything newly inside it
+    // is loaded in the update the camera crosses a chunk border
+    view_distance: i32,
+    // chunk radii where chunks lose one more level of detail
+    lod_rings: [i32; 3],
+    // seconds in one in-game day
+    day_length: f64,
+    // fixed direction surfaces are lit from, None follows the sun and moon
+    light_direction: Option<[f32; 3]>,
+    // share of the light that reaches faces turned away, 0..=1
+    ambient: f32,
+    // hard shadows from rays cast towards the light, up to shadow_distance voxels
+    shadows: bool,
+    shadow_distance: f32,
+    occlusion: Occlusion,
+    // mirror and refraction rays followed past the first hit
+    reflection_bounces: u32,
+    // distance fog from fog_start, a share of the view distance, fully fogged
+    // at the view distance. Density thickens a haze before that.
+    fog: bool,
+    fog_start: f32,
+    fog_density: f32,
+    // exposure in stops, auto exposure adapts on top of it
+    tonemapper: Tonemapper,
+    exposure: f32,
+    auto_exposure: bool,
+    // fullscreen effects after tonemapping, drawn in this order when on
+    post_effects: Vec<(PostEffect, bool)>,
+    color_blindness: ColorBlindness,
+    // share of the window's width and height rendered, or with
+    // auto_resolution adapted so the ray cast takes target_frame_time
+    // milliseconds on the GPU
+    render_scale: f32,
+    auto_resolution: bool,
+    target_frame_time: f32,
+    upscale_filter: UpscaleFilter,
+    // progressive path tracing instead of the shaded picture
+    path_tracing: bool,
+    path_bounces: u32,
+    // cellular automaton rule, see core/automata.rs
+    automaton_rule: String,
+    // fixed updates between automaton steps
+    automaton_rate: u64,
 }
 
 impl Default for Settings {
@@ -25,9 +69,50 @@ impl Default for Settings {
 
         key_bindings.insert(GrabCursor, Escape);
 
+        key_bindings.insert(Break, KeyQ);
+        key_bindings.insert(Place, KeyE);
+        key_bindings.insert(PlaceWater, KeyR);
+        key_bindings.insert(PlaceLava, KeyT);
+        key_bindings.insert(SeedCells, KeyC);
+        key_bindings.insert(FillWater, KeyF);
+        key_bindings.insert(Explode, KeyX);
+        key_bindings.insert(SpawnObject, KeyV);
+        key_bindings.insert(PlaceModel, KeyM);
+
         Settings {
             key_bindings,
             field_of_view: 70.0,
+            view_distance: 6,
+            lod_rings: [2, 3, 4],
+            day_length: 600.0,
+            light_direction: None,
+            ambient: 0.3,
+            shadows: true,
+            shadow_distance: 256.0,
+            occlusion: Occlusion::Corners,
+            reflection_bounces: 2,
+            fog: true,
+            fog_start: 0.6,
+            fog_density: 0.0003,
+            tonemapper: Tonemapper::Aces,
+            exposure: 0.0,
+            auto_exposure: false,
+            post_effects: vec![
+                (PostEffect::Bloom, true),
+                (PostEffect::Grading, false),
+                (PostEffect::Vignette, false),
+                (PostEffect::Fxaa, true),
+                (PostEffect::ColorBlind, false),
+            ],
+            color_blindness: ColorBlindness::Deuteranopia,
+            render_scale: 1.0,
+            auto_resolution: false,
+            target_frame_time: 16.7,
+            upscale_filter: UpscaleFilter::Bilinear,
+            path_tracing: false,
+            path_bounces: 4,
+            automaton_rule: "4/4/5/M".to_string(),
+            automaton_rate: 10,
         }
     }
 }
@@ -40,9 +125,265 @@ impl Settings {
     pub fn field_of_view(&self) -> f64 {
         self.field_of_view
     }
+
+    pub fn view_distance(&self) -> i32 {
+        self.view_distance
+    }
+
+    pub fn day_length(&self) -> f64 {
+        self.day_length
+    }
+
+    pub fn light_direction(&self) -> Option<[f32; 3]> {
+        self.light_direction
+    }
+
+    // normalized, a zero vector goes back to following the sun
+    pub fn set_light_direction(&mut self, direction: Option<[f32; 3]>) {
+        self.light_direction = direction.and_then(|[x, y, z]| {
+            let length = (x * x + y * y + z * z).sqrt();
+            (length > 0.0).then(|| [x / length, y / length, z / length])
+        });
+    }
+
+    pub fn ambient(&self) -> f32 {
+        self.ambient
+    }
+
+    pub fn set_ambient(&mut self, ambient: f32) {
+        self.ambient = ambient.clamp(0.0, 1.0);
+    }
+
+    pub fn shadows(&self) -> bool {
+        self.shadows
+    }
+
+    pub fn set_shadows(&mut self, shadows: bool) {
+        self.shadows = shadows;
+    }
+
+    pub fn shadow_distance(&self) -> f32 {
+        self.shadow_distance
+    }
+
+    pub fn set_shadow_distance(&mut self, distance: f32) {
+        self.shadow_distance = distance.max(1.0);
+    }
+
+    pub fn occlusion(&self) -> Occlusion {
+        self.occlusion
+    }
+
+    pub fn set_occlusion(&mut self, occlusion: Occlusion) {
+        self.occlusion = occlusion;
+    }
+
+    pub fn reflection_bounces(&self) -> u32 {
+        self.reflection_bounces
+    }
+
+    pub fn set_reflection_bounces(&mut self, bounces: u32) {
+        self.reflection_bounces = bounces;
+    }
+
+    pub fn fog(&self) -> bool {
+        self.fog
+    }
+
+    pub fn set_fog(&mut self, fog: bool) {
+        self.fog = fog;
+    }
+
+    pub fn fog_start(&self) -> f32 {
+        self.fog_start
+    }
+
+    pub fn set_fog_start(&mut self, start: f32) {
+        self.fog_start = start.clamp(0.0, 0.99);
+    }
+
+    pub fn fog_density(&self) -> f32 {
+        self.fog_density
+    }
+
+    pub fn set_fog_density(&mut self, density: f32) {
+        self.fog_density = density.max(0.0);
+    }
+
+    pub fn tonemapper(&self) -> Tonemapper {
+        self.tonemapper
+    }
+
+    pub fn set_tonemapper(&mut self, tonemapper: Tonemapper) {
+        self.tonemapper = tonemapper;
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.exposure = exposure.clamp(-8.0, 8.0);
+    }
+
+    pub fn auto_exposure(&self) -> bool {
+        self.auto_exposure
+    }
+
+    pub fn set_auto_exposure(&mut self, auto_exposure: bool) {
+        self.auto_exposure = auto_exposure;
+    }
+
+    pub fn post_effects(&self) -> &[(PostEffect, bool)] {
+        &self.post_effects
+    }
+
+    // the effects that are on, in the order they are drawn
+    pub fn post_chain(&self) -> Vec<PostEffect> {
+        self.post_effects
+            .iter()
+            .filter(|(_, on)| *on)
+            .map(|(effect, _)| *effect)
+            .collect()
+    }
+
+    pub fn set_post_effect(&mut self, index: usize, on: bool) {
+        if let Some(entry) = self.post_effects.get_mut(index) {
+            entry.1 = on;
+        }
+    }
+
+    // swaps the effect with the one drawn before it
+    pub fn move_post_effect_up(&mut self, index: usize) {
+        if index > 0 && index < self.post_effects.len() {
+            self.post_effects.swap(index - 1, index);
+        }
+    }
+
+    pub fn color_blindness(&self) -> ColorBlindness {
+        self.color_blindness
+    }
+
+    pub fn set_color_blindness(&mut self, color_blindness: ColorBlindness) {
+        self.color_blindness = color_blindness;
+    }
+
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    pub fn set_render_scale(&mut self, scale: f32) {
+        self.render_scale = scale.clamp(0.25, 1.0);
+    }
+
+    pub fn auto_resolution(&self) -> bool {
+        self.auto_resolution
+    }
+
+    pub fn set_auto_resolution(&mut self, auto_resolution: bool) {
+        self.auto_resolution = auto_resolution;
+    }
+
+    pub fn target_frame_time(&self) -> f32 {
+        self.target_frame_time
+    }
+
+    pub fn set_target_frame_time(&mut self, milliseconds: f32) {
+        self.target_frame_time = milliseconds.clamp(1.0, 100.0);
+    }
+
+    pub fn upscale_filter(&self) -> UpscaleFilter {
+        self.upscale_filter
+    }
+
+    pub fn set_upscale_filter(&mut self, filter: UpscaleFilter) {
+        self.upscale_filter = filter;
+    }
+
+    pub fn path_tracing(&self) -> bool {
+        self.path_tracing
+    }
+
+    pub fn set_path_tracing(&mut self, path_tracing: bool) {
+        self.path_tracing = path_tracing;
+    }
+
+    pub fn path_bounces(&self) -> u32 {
+        self.path_bounces
+    }
+
+    pub fn set_path_bounces(&mut self, bounces: u32) {
+        self.path_bounces = bounces.max(1);
+    }
+
+    pub fn automaton_rule(&self) -> &str {
+        &self.automaton_rule
+    }
+
+    pub fn automaton_rate(&self) -> u64 {
+        self.automaton_rate
+    }
+
+    // tree depth of a chunk at the given squared chunk distance
+    pub fn lod_depth(&self, distance_squared: i32) -> u32 {
+        let rings_passed = self
+            .lod_rings
+            .iter()
+            .filter(|r| distance_squared > *r * *r)
+            .count() as u32;
+        FULL_DEPTH - rings_passed
+    }
+}
+
+// ambient occlusion quality, the value is the mode the shader gets
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Occlusion {
+    Off = 0,
+    // from the voxels around each face corner
+    Corners = 1,
+    // a few short rays over the hemisphere
+    Rays = 2,
 }
 
-#[derive(PartialEq, Eq, Hash)]
+// tone curve from the float picture to the screen, the value is what the
+// shader gets
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Tonemapper {
+    Reinhard = 0,
+    Aces = 1,
+}
+
+// fullscreen effect after tonemapping, the value is the pipeline drawing it
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PostEffect {
+    Bloom = 0,
+    Fxaa = 1,
+    // through the color grading table
+    Grading = 2,
+    Vignette = 3,
+    // simulates the kind of color blindness chosen
+    ColorBlind = 4,
+}
+
+// missing cone the color blindness effect simulates, the value is what the
+// shader gets
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ColorBlindness {
+    Protanopia = 0,
+    Deuteranopia = 1,
+    Tritanopia = 2,
+}
+
+// how the rendered picture is stretched over the window, the value is what
+// the shader gets
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UpscaleFilter {
+    Bilinear = 0,
+    // keeps edges between unlike pixels sharp
+    EdgeAware = 1,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Action {
     Forward,
     Backwards,
@@ -53,4 +394,14 @@ pub enum Action {
     Down,
 
     GrabCursor,
+
+    Break,
+    Place,
+    PlaceWater,
+    PlaceLava,
+    SeedCells,
+    FillWater,
+    Explode,
+    SpawnObject,
+    PlaceModel,
 }
@@ -1,8 +1,14 @@
-use std::{array, collections::HashMap};
+use std::{
+    array,
+    collections::{HashMap, HashSet},
+    io,
+};
 
 use bytemuck::{Pod, Zeroable};
 use nalgebra::Vector3;
 
+use crate::core::block::{BlockId, AIR};
+
 pub struct Camera {
     pos: nalgebra::Vector3<f32>,
     dir: nalgebra::Vector3<f32>,
@@ -76,24 +82,373 @@ fn to_arr4(v3: &Vector3<f32>) -> [f32; 4] {
 
 // Cpu side chunk representation
 pub struct Node64 {
-    pub children: [Box<Node>; 64],
+    pub children: [Node; 64],
 }
 
 impl Node64 {
     pub fn new() -> Self {
         Self {
-            children: array::from_fn(|_| Box::new(Node::Empty)),
+            children: array::from_fn(|_| Node::Empty),
+        }
+    }
+
+    pub fn filled(block: BlockId) -> Self {
+        Self {
+            children: array::from_fn(|_| Node::Solid(block)),
         }
     }
 }
 pub enum Node {
     Empty,
-    Branch(Node64),
-    Leaf(u64),
+    Branch(Box<Node64>),
+    // voxel mask, block id of every voxel (AIR where the bit is not set)
+    Leaf(u64, Box<[BlockId; 64]>),
+    // whole subtree filled with one block
+    Solid(BlockId),
+}
+
+// voxels per chunk axis, root -> 3 branch levels -> 4x4x4 leaf
+pub const CHUNK_SIZE: i32 = 256;
+pub const ROOT_SHIFT: u32 = 6;
+// levels below the chunk root, at this depth nodes are single voxels
+pub const FULL_DEPTH: u32 = 4;
+
+const TAG_EMPTY: u8 = 0;
+const TAG_BRANCH: u8 = 1;
+const TAG_LEAF: u8 = 2;
+const TAG_SOLID: u8 = 3;
+
+impl Node {
+    pub fn get(&self, local: (i32, i32, i32)) -> BlockId {
+        let mut node = self;
+        let mut shift = ROOT_SHIFT;
+
+        loop {
+            match node {
+                Node::Empty => return AIR,
+                Node::Solid(block) => return *block,
+                Node::Leaf(_, blocks) => return blocks[child_index(local, 0)],
+                Node::Branch(branch) => {
+                    node = &branch.children[child_index(local, shift)];
+                    shift -= 2;
+                }
+            }
+        }
+    }
+
+    // bits of the leaf holding `local` whose block passes `test`, same bit
+    // order as Leaf. Uniform nodes above leaf level answer for all 64.
+    pub fn leaf_mask(&self, local: (i32, i32, i32), test: impl Fn(BlockId) -> bool) -> u64 {
+        let mut node = self;
+        let mut shift = ROOT_SHIFT;
+
+        loop {
+            match node {
+                Node::Empty => return if test(AIR) { !0 } else { 0 },
+                Node::Solid(block) => return if test(*block) { !0 } else { 0 },
+                Node::Leaf(_, blocks) => {
+                    return blocks
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, block)| test(**block))
+                        .fold(0, |bits, (i, _)| bits | 1 << i);
+                }
+                Node::Branch(branch) => {
+                    node = &branch.children[child_index(local, shift)];
+                    shift -= 2;
+                }
+            }
+        }
+    }
+
+    // returns true if the voxel changed, AIR removes it
+    pub fn set(&mut self, local: (i32, i32, i32), block: BlockId) -> bool {
+        self.set_rec(local, ROOT_SHIFT, block)
+    }
+
+    fn set_rec(&mut self, local: (i32, i32, i32), shift: u32, block: BlockId) -> bool {
+        // expand uniform nodes one level so the voxel can be written
+        match self {
+            Node::Empty if block == AIR => return false,
+            Node::Solid(b) if *b == block => return false,
+            Node::Empty => {
+                *self = if shift == 0 {
+                    Node::Leaf(0, Box::new([AIR; 64]))
+                } else {
+                    Node::Branch(Box::new(Node64::new()))
+                };
+            }
+            Node::Solid(b) => {
+                let b = *b;
+                *self = if shift == 0 {
+                    Node::Leaf(!0, Box::new([b; 64]))
+                } else {
+                    Node::Branch(Box::new(Node64::filled(b)))
+                };
+            }
+            _ => {}
+        }
+
+        let changed = match self {
+            Node::Leaf(mask, blocks) => {
+                let i = child_index(local, shift);
+                let old = blocks[i];
+                blocks[i] = block;
+                if block == AIR {
+                    *mask &= !(1 << i);
+                } else {
+                    *mask |= 1 << i;
+                }
+                old != block
+            }
+            Node::Branch(branch) => {
+                let child = &mut branch.children[child_index(local, shift)];
+                child.set_rec(local, shift - 2, block)
+            }
+            _ => false,
+        };
+
+        self.collapse();
+        changed
+    }
+
+    // turns empty or uniform nodes into Empty / Solid
+    pub fn collapse(&mut self) {
+        let uniform = match self {
+            Node::Leaf(0, _) => Some(Node::Empty),
+            Node::Leaf(mask, blocks) if *mask == !0 && blocks.iter().all(|b| *b == blocks[0]) => {
+                Some(Node::Solid(blocks[0]))
+            }
+            Node::Branch(branch) => match &branch.children[0] {
+                Node::Empty if branch.children.iter().all(|c| matches!(c, Node::Empty)) => {
+                    Some(Node::Empty)
+                }
+                Node::Solid(b)
+                    if branch
+                        .children
+                        .iter()
+                        .all(|c| matches!(c, Node::Solid(o) if o == b)) =>
+                {
+                    Some(Node::Solid(*b))
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+
+        if let Some(node) = uniform {
+            *self = node;
+        }
+    }
+
+    // block seen from above: most common block of the highest non empty layer
+    pub fn representative(&self) -> BlockId {
+        let mut counts = [0u8; 256];
+
+        match self {
+            Node::Empty => return AIR,
+            Node::Solid(block) => return *block,
+            Node::Leaf(mask, blocks) => {
+                for y in (0..4).rev() {
+                    for i in (0..64).filter(|i| i / 4 % 4 == y && mask & (1 << i) != 0) {
+                        counts[blocks[i] as usize] += 1;
+                    }
+                    if counts.iter().any(|c| *c > 0) {
+                        break;
+                    }
+                }
+            }
+            Node::Branch(branch) => {
+                for y in (0..4).rev() {
+                    for i in (0..64).filter(|i| i / 4 % 4 == y) {
+                        let child = &branch.children[i];
+                        if !matches!(child, Node::Empty) {
+                            counts[child.representative() as usize] += 1;
+                        }
+                    }
+                    if counts.iter().any(|c| *c > 0) {
+                        break;
+                    }
+                }
+            }
+        }
+
+        let mut best = 0;
+        for (block, count) in counts.iter().enumerate() {
+            if *count > counts[best] {
+                best = block;
+            }
+        }
+        best as BlockId
+    }
+
+    // cuts the tree `depth` levels below this node, what is left there turns Solid
+    pub fn truncate(&mut self, depth: u32) {
+        if depth == 0 {
+            if !matches!(self, Node::Empty) {
+                *self = Node::Solid(self.representative());
+            }
+            return;
+        }
+
+        if let Node::Branch(branch) = self {
+            for child in branch.children.iter_mut() {
+                child.truncate(depth - 1);
+            }
+        }
+        self.collapse();
+    }
+
+    // Branch: tag, u64 mask of non empty children, children in order
+    // Leaf:   tag, u64 voxel mask, block id of every set voxel
+    // Solid:  tag, block id
+    pub fn write(&self, out: &mut Vec<u8>) {
+        match self {
+            Node::Empty => out.push(TAG_EMPTY),
+            Node::Solid(block) => {
+                out.push(TAG_SOLID);
+                out.push(*block);
+            }
+            Node::Leaf(mask, blocks) => {
+                out.push(TAG_LEAF);
+                out.extend_from_slice(&mask.to_le_bytes());
+                for (i, block) in blocks.iter().enumerate() {
+                    if mask & (1 << i) != 0 {
+                        out.push(*block);
+                    }
+                }
+            }
+            Node::Branch(branch) => {
+                out.push(TAG_BRANCH);
+
+                let mut mask: u64 = 0;
+                for (i, child) in branch.children.iter().enumerate() {
+                    if !matches!(child, Node::Empty) {
+                        mask |= 1 << i;
+                    }
+                }
+                out.extend_from_slice(&mask.to_le_bytes());
+
+                for child in branch.children.iter() {
+                    if !matches!(child, Node::Empty) {
+                        child.write(out);
+                    }
+                }
+            }
+        }
+    }
+
+    // a chunk root
+    pub fn read(bytes: &[u8], cursor: &mut usize) -> io::Result<Node> {
+        Node::read_at(bytes, cursor, 0)
+    }
+
+    // branches only above the leaf level and leaves only at it, so a damaged
+    // chunk cannot nest deeper than FULL_DEPTH
+    fn read_at(bytes: &[u8], cursor: &mut usize, depth: u32) -> io::Result<Node> {
+        let tag = read_u8(bytes, cursor)?;
+
+        match tag {
+            TAG_EMPTY => Ok(Node::Empty),
+            TAG_SOLID => Ok(Node::Solid(read_u8(bytes, cursor)?)),
+            TAG_LEAF => {
+                if depth + 1 != FULL_DEPTH {
+                    return Err(invalid_data("leaf outside the leaf level"));
+                }
+                let mask = read_u64(bytes, cursor)?;
+                let mut blocks = Box::new([AIR; 64]);
+                for (i, block) in blocks.iter_mut().enumerate() {
+                    if mask & (1 << i) != 0 {
+                        *block = read_u8(bytes, cursor)?;
+                    }
+                }
+                Ok(Node::Leaf(mask, blocks))
+            }
+            TAG_BRANCH => {
+                if depth + 1 >= FULL_DEPTH {
+                    return Err(invalid_data("branch below the leaf level"));
+                }
+                let mask = read_u64(bytes, cursor)?;
+                let mut branch = Box::new(Node64::new());
+                for i in 0..64 {
+                    if mask & (1 << i) != 0 {
+                        branch.children[i] = Node::read_at(bytes, cursor, depth + 1)?;
+                    }
+                }
+                Ok(Node::Branch(branch))
+            }
+            _ => Err(invalid_data("unknown node tag")),
+        }
+    }
 }
+
+pub fn child_index(local: (i32, i32, i32), shift: u32) -> usize {
+    let x = (local.0 >> shift) & 3;
+    let y = (local.1 >> shift) & 3;
+    let z = (local.2 >> shift) & 3;
+
+    (x + 4 * y + 16 * z) as usize
+}
+
+fn read_u8(bytes: &[u8], cursor: &mut usize) -> io::Result<u8> {
+    let byte = *bytes
+        .get(*cursor)
+        .ok_or_else(|| invalid_data("unexpected end of chunk"))?;
+    *cursor += 1;
+
+    Ok(byte)
+}
+
+fn read_u64(bytes: &[u8], cursor: &mut usize) -> io::Result<u64> {
+    let end = *cursor + 8;
+    let raw = bytes
+        .get(*cursor..end)
+        .ok_or_else(|| invalid_data("unexpected end of chunk"))?;
+    *cursor = end;
+
+    Ok(u64::from_le_bytes(raw.try_into().unwrap()))
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// world voxel -> (chunk coord, voxel inside chunk)
+pub fn split_voxel(pos: (i32, i32, i32)) -> ((i32, i32, i32), (i32, i32, i32)) {
+    let chunk = (
+        pos.0.div_euclid(CHUNK_SIZE),
+        pos.1.div_euclid(CHUNK_SIZE),
+        pos.2.div_euclid(CHUNK_SIZE),
+    );
+    let local = (
+        pos.0.rem_euclid(CHUNK_SIZE),
+        pos.1.rem_euclid(CHUNK_SIZE),
+        pos.2.rem_euclid(CHUNK_SIZE),
+    );
+    (chunk, local)
+}
+
+// shadow rays start this far off the surface
+const SHADOW_BIAS: f32 = 0.01;
+
+pub struct RayHit {
+    pub voxel: (i32, i32, i32),
+    // face of the voxel the ray entered through
+    pub normal: (i32, i32, i32),
+    // distance along the ray to where it entered the voxel
+    pub t: f32,
+}
+
 pub struct Scene {
     world: HashMap<(i32, i32, i32), Node>,
     world_changed: bool,
+
+    // chunks edited since they were loaded / generated
+    modified: HashSet<(i32, i32, i32)>,
+    // tree depth of chunks stored at lower detail, see FULL_DEPTH
+    depths: HashMap<(i32, i32, i32), u32>,
+    // chunks changed since they were last staged for the gpu
+    dirty: HashSet<(i32, i32, i32)>,
 }
 
 impl Scene {
@@ -103,14 +458,213 @@ impl Scene {
         Self {
             world,
             world_changed: true,
+            modified: HashSet::new(),
+            depths: HashMap::new(),
+            dirty: HashSet::new(),
         }
     }
 
     pub fn add_chunk(&mut self, root: Node, coords: (i32, i32, i32)) {
+        self.add_lod_chunk(root, coords, FULL_DEPTH);
+    }
+
+    // chunk already truncated to `depth`
+    pub fn add_lod_chunk(&mut self, root: Node, coords: (i32, i32, i32), depth: u32) {
         self.world.insert(coords, root);
+        if depth < FULL_DEPTH {
+            self.depths.insert(coords, depth);
+        } else {
+            self.depths.remove(&coords);
+        }
+        self.dirty.insert(coords);
         self.world_changed = true;
     }
 
+    pub fn chunk_depth(&self, coords: (i32, i32, i32)) -> u32 {
+        self.depths.get(&coords).copied().unwrap_or(FULL_DEPTH)
+    }
+
+    // returns the chunk and whether it was edited while loaded
+    pub fn remove_chunk(&mut self, coords: (i32, i32, i32)) -> Option<(Node, bool)> {
+        let root = self.world.remove(&coords)?;
+        self.depths.remove(&coords);
+        self.world_changed = true;
+
+        Some((root, self.modified.remove(&coords)))
+    }
+
+    pub fn contains_chunk(&self, coords: (i32, i32, i32)) -> bool {
+        self.world.contains_key(&coords)
+    }
+
+    // smallest chunk box containing every loaded chunk, end is exclusive
+    pub fn bounds(&self) -> ((i32, i32, i32), (i32, i32, i32)) {
+        let mut start = (i32::MAX, i32::MAX, i32::MAX);
+        let mut end = (i32::MIN, i32::MIN, i32::MIN);
+
+        for c in self.world.keys() {
+            start = (start.0.min(c.0), start.1.min(c.1), start.2.min(c.2));
+            end = (end.0.max(c.0 + 1), end.1.max(c.1 + 1), end.2.max(c.2 + 1));
+        }
+
+        if self.world.is_empty() {
+            return ((0, 0, 0), (1, 1, 1));
+        }
+        (start, end)
+    }
+
+    pub fn chunk_coords(&self) -> Vec<(i32, i32, i32)> {
+        self.world.keys().copied().collect()
+    }
+
+    pub fn mark_modified(&mut self, coords: (i32, i32, i32)) {
+        self.modified.insert(coords);
+    }
+
+    // clears the modified set, caller is responsible for saving
+    pub fn take_modified(&mut self) -> Vec<(i32, i32, i32)> {
+        self.modified.drain().collect()
+    }
+
+    pub fn get_voxel(&self, pos: (i32, i32, i32)) -> BlockId {
+        let (chunk, local) = split_voxel(pos);
+        match self.world.get(&chunk) {
+            Some(root) => root.get(local),
+            None => AIR,
+        }
+    }
+
+    // bitmask of the 4x4x4 leaf holding `pos`, see Node::leaf_mask,
+    // unloaded chunks are air
+    pub fn leaf_mask(&self, pos: (i32, i32, i32), test: impl Fn(BlockId) -> bool) -> u64 {
+        let (chunk, local) = split_voxel(pos);
+        match self.world.get(&chunk) {
+            Some(root) => root.leaf_mask(local, test),
+            None if test(AIR) => !0,
+            None => 0,
+        }
+    }
+
+    // voxels can only be edited inside loaded chunks at full detail
+    pub fn is_editable(&self, pos: (i32, i32, i32)) -> bool {
+        let (chunk, _) = split_voxel(pos);
+        self.world.contains_key(&chunk) && !self.depths.contains_key(&chunk)
+    }
+
+    pub fn set_voxel(&mut self, pos: (i32, i32, i32), block: BlockId) -> bool {
+        if !self.is_editable(pos) {
+            return false;
+        }
+        let (chunk, local) = split_voxel(pos);
+        let root = self.world.get_mut(&chunk).unwrap();
+
+        let changed = root.set(local, block);
+        if changed {
+            self.modified.insert(chunk);
+            self.dirty.insert(chunk);
+            self.world_changed = true;
+        }
+        changed
+    }
+
+    // many edits at once, returns the voxels that changed
+    pub fn set_voxels(&mut self, edits: &[((i32, i32, i32), BlockId)]) -> Vec<(i32, i32, i32)> {
+        edits
+            .iter()
+            .filter(|(pos, block)| self.set_voxel(*pos, *block))
+            .map(|(pos, _)| *pos)
+            .collect()
+    }
+
+    // chunks to restage, removed chunks are dropped by the stager itself
+    pub fn take_dirty(&mut self) -> Vec<(i32, i32, i32)> {
+        self.dirty.drain().collect()
+    }
+
+    // voxel DDA, max_dist in voxels
+    pub fn raycast(&self, origin: [f32; 4], dir: [f32; 4], max_dist: f32) -> Option<RayHit> {
+        let mut voxel = [
+            origin[0].floor() as i32,
+            origin[1].floor() as i32,
+            origin[2].floor() as i32,
+        ];
+        let mut step = [0; 3];
+        let mut t_max = [f32::INFINITY; 3];
+        let mut t_delta = [f32::INFINITY; 3];
+
+        for i in 0..3 {
+            if dir[i] > 0.0 {
+                step[i] = 1;
+                t_delta[i] = 1.0 / dir[i];
+                t_max[i] = (voxel[i] as f32 + 1.0 - origin[i]) * t_delta[i];
+            } else if dir[i] < 0.0 {
+                step[i] = -1;
+                t_delta[i] = -1.0 / dir[i];
+                t_max[i] = (origin[i] - voxel[i] as f32) * t_delta[i];
+            }
+        }
+
+        let mut normal = (0, 0, 0);
+        let mut t = 0.0;
+        loop {
+            let pos = (voxel[0], voxel[1], voxel[2]);
+            if self.get_voxel(pos) != AIR {
+                return Some(RayHit {
+                    voxel: pos,
+                    normal,
+                    t,
+                });
+            }
+
+            let axis = if t_max[0] < t_max[1] && t_max[0] < t_max[2] {
+                0
+            } else if t_max[1] < t_max[2] {
+                1
+            } else {
+                2
+            };
+            if t_max[axis] > max_dist {
+                return None;
+            }
+
+            voxel[axis] += step[axis];
+            t = t_max[axis];
+            t_max[axis] += t_delta[axis];
+
+            normal = match axis {
+                0 => (-step[0], 0, 0),
+                1 => (0, -step[1], 0),
+                _ => (0, 0, -step[2]),
+            };
+        }
+    }
+
+    // shadow ray from where the ray `origin`, `dir` hit towards the light, the
+    // compute shader casts the same one from every pixel
+    pub fn in_shadow(
+        &self,
+        origin: [f32; 4],
+        dir: [f32; 4],
+        hit: &RayHit,
+        light_dir: [f32; 3],
+        max_dist: f32,
+    ) -> bool {
+        // started inside the voxel, there is no face
+        if hit.normal == (0, 0, 0) {
+            return false;
+        }
+        let normal = [hit.normal.0, hit.normal.1, hit.normal.2].map(|n| n as f32);
+        let facing: f32 = (0..3).map(|i| normal[i] * light_dir[i]).sum();
+        if facing <= 0.0 {
+            return true;
+        }
+
+        let start = [0, 1, 2].map(|i| origin[i] + dir[i] * hit.t + normal[i] * SHADOW_BIAS);
+        let light = [light_dir[0], light_dir[1], light_dir[2], 0.0];
+        self.raycast([start[0], start[1], start[2], 0.0], light, max_dist)
+            .is_some()
+    }
+
     pub fn world_changed(&self) -> bool {
         self.world_changed
     }
@@ -124,15 +678,65 @@ impl Scene {
     }
 }
 
-fn fill_hashmap(world: &mut HashMap<(i32, i32, i32), Node>) {
-    let mut start = (-4, -4, -4);
-    let mut end = (4, 4, 4);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::block::STONE;
+
+    // an 8x8 floor with a two voxel blocker above it, seen from straight
+    // above with one ray per pixel, lit diagonally from +x
+    #[test]
+    fn shadowed_pixels() {
+        let mut scene = Scene::new();
+        scene.add_chunk(Node::Empty, (0, 0, 0));
+        for x in 0..8 {
+            for z in 0..8 {
+                scene.set_voxel((x, 0, z), STONE);
+            }
+        }
+        scene.set_voxel((4, 3, 4), STONE);
+        scene.set_voxel((4, 3, 5), STONE);
 
-    for z in start.2..end.2 {
-        for y in start.1..end.1 {
-            for x in start.0..end.0 {
-                world.insert((x, y, z), Node::Empty);
+        let light = [0.5f32.sqrt(), 0.5f32.sqrt(), 0.0];
+        let dir = [0.0, -1.0, 0.0, 0.0];
+        let mut shadowed = Vec::new();
+        for z in 0..8 {
+            for x in 0..8 {
+                let origin = [x as f32 + 0.5, 10.0, z as f32 + 0.5, 0.0];
+                let hit = scene.raycast(origin, dir, 32.0).unwrap();
+                if scene.in_shadow(origin, dir, &hit, light, 16.0) {
+                    shadowed.push((x, z));
+                }
             }
         }
+
+        assert_eq!(shadowed, vec![(1, 4), (2, 4), (1, 5), (2, 5)]);
+    }
+
+    #[test]
+    fn read_checks_nesting() {
+        let mut chunk = Node::Empty;
+        chunk.set((5, 9, 200), STONE);
+        let mut bytes = Vec::new();
+        chunk.write(&mut bytes);
+
+        let mut cursor = 0;
+        let read = Node::read(&bytes, &mut cursor).unwrap();
+        let mut again = Vec::new();
+        read.write(&mut again);
+        assert_eq!((cursor, again), (bytes.len(), bytes));
+
+        // branches with one child each, far deeper than a chunk goes
+        let mut deep = Vec::new();
+        for _ in 0..100_000 {
+            deep.push(TAG_BRANCH);
+            deep.extend_from_slice(&1u64.to_le_bytes());
+        }
+        deep.push(TAG_EMPTY);
+        assert!(Node::read(&deep, &mut 0).is_err());
+
+        let mut leaf_root = vec![TAG_LEAF];
+        leaf_root.extend_from_slice(&0u64.to_le_bytes());
+        assert!(Node::read(&leaf_root, &mut 0).is_err());
     }
 }
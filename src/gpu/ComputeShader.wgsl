@@ -8,25 +8,68 @@ struct Camera {
     screen: vec2<f32>,
 }
 
-struct Ray {
-    dir: vec3<f32>,
-    origin: vec3<f32>,
-    invdir: vec3<f32>,
-    
+struct Environment {
+    // w is how strong the light is, 0..=1
+    sun_dir: vec4<f32>,
+    moon_dir: vec4<f32>,
+    sky_color: vec4<f32>,
+    sun_color: vec4<f32>,
+    // direction surfaces are lit from, w is the ambient share
+    light_dir: vec4<f32>,
+    // x is how far shadow rays go, 0 turns shadows off
+    shadow: vec4<f32>,
+    // x is the ambient occlusion mode, see AO_CORNERS and AO_RAYS
+    occlusion: vec4<u32>,
+    // x is how many mirror and refraction rays follow the first hit
+    bounces: vec4<u32>,
+}
+
+// in voxels along the camera ray, see gpu/types.rs
+struct Fog {
+    start: f32,
+    end: f32,
+    density: f32,
+}
+
+// see core/materials.rs
+struct Material {
+    color: vec3<f32>,
+    roughness: f32,
+    metalness: f32,
+    transparency: f32,
+    ior: f32,
+    emission: f32,
 }
 
 struct Header {
-    base: vec3<i32>,
-    end: vec3<i32>,
     size: u32,
 }
-struct GpuRoot {
-    mask1: u32,
-    mask0: u32,
 
-    offset: u32,
-    size: u32,
+// progressive path tracing, samples already averaged into the accumulation
+struct Accumulation {
+    samples: u32,
+    bounces: u32,
+}
+
+// world -> object transform as rows, w is the translation. Chunks, voxel
+// objects and model instances are all one of these.
+struct GpuObject {
+    m0: vec4<f32>,
+    m1: vec4<f32>,
+    m2: vec4<f32>,
+    size: vec4<f32>,
+    root: vec4<u32>,
 }
+
+// leaf: `count` objects from `first`, inner: children at `first` and
+// `first + 1`, first == 0 without a count is the empty tree
+struct BvhNode {
+    low: vec3<f32>,
+    first: u32,
+    high: vec3<f32>,
+    count: u32,
+}
+
 struct GpuNode {
     mask_h: u32,
     mask_l: u32,
@@ -39,340 +82,716 @@ struct GpuNode {
 var<uniform> header: Header;
 @group(0) @binding(1)
 var<storage, read> nodes: array<GpuNode>;
+// 32 per leaf, one light byte per voxel then one block id byte per voxel
+@group(0) @binding(2)
+var<storage, read> bricks: array<u32>;
+@group(0) @binding(3)
+var<storage, read> objects: array<GpuObject>;
+@group(0) @binding(4)
+var<storage, read> bvh: array<BvhNode>;
+@group(0) @binding(5)
+var<storage, read> materials: array<Material>;
 
 @group(1) @binding(0)
-var output_texture: texture_storage_2d<rgba8unorm, write>;
+var output_texture: texture_storage_2d<rgba16float, write>;
 
 @group(2) @binding(0)
 var<uniform> cam: Camera;
+@group(2) @binding(1)
+var<uniform> env: Environment;
+@group(2) @binding(2)
+var<uniform> fog: Fog;
+
+@group(3) @binding(0)
+var accumulation: texture_storage_2d<rgba32float, read_write>;
+@group(3) @binding(1)
+var<uniform> accum: Accumulation;
 
 @compute @workgroup_size(8, 8)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    let size = textureDimensions(output_texture);
+    // the render size, the texture is as big as the window
+    let size = vec2<u32>(cam.screen);
     if (global_id.x >= size.x || global_id.y >= size.y) {
         return;
     }
 
-//    let color = vec4<f32>(f32(global_id.x) / f32(size.x), f32(global_id.y) / f32(size.y), 0.5, 1.0);
-//    textureStore(output_texture, vec2<i32>(global_id.xy), color);
-
     let PI: f32 = 3.14159265359;
 
     // compute primary ray through pixel
     let aspectRatio = cam.screen.x / cam.screen.y;
-    let pX = (2.0 * ((f32(global_id.x) + 0.5) / cam.screen.x - 1.0)) * tan((cam.fov / 2.0 * PI / 180.0)) * aspectRatio;
+    let pX = (2.0 * ((f32(global_id.x) + 0.5) / cam.screen.x) - 1.0) * tan((cam.fov / 2.0 * PI / 180.0)) * aspectRatio;
     let pY = (1.0 - 2.0 * ((f32(global_id.y) + 0.5) / cam.screen.y)) * tan(cam.fov / 2.0 * PI / 180.0);
-    let primary_ray = normalize(vec3<f32> (pX, pY, -1.0));
 
-    // world ray
-    let ray = normalize(primary_ray.x * cam.right + primary_ray.y * cam.up + primary_ray.z * cam.dir);
+    // world ray, the camera looks along cam.dir
+    var ray = normalize(pX * cam.right + pY * cam.up + cam.dir);
+    var origin = cam.origin;
+
+    // Mirrors and transparent voxels continue the ray up to env.bounces.x
+    // times. Only the stronger of reflection and refraction is followed, the
+    // other share is taken as the shaded surface.
+    let primary = ray;
+    var primary_t = 0.0;
+    var color = vec3<f32>(0.0);
+    var weight = vec3<f32>(1.0);
+    var inside = 0u;
+    for (var bounce = 0u; bounce <= env.bounces.x; bounce++) {
+        medium = inside;
+        let hit = trace(origin, ray, cam.far);
+        medium = 0u;
+        if (!hit.hit) {
+            color += weight * sky(ray);
+            break;
+        }
+        if (bounce == 0u) {
+            primary_t = hit.t;
+        }
 
-//    let dist: f32 = dda_iter(ray); // distance returned by DDA / dda_iter(ray);
-//    let b: f32 = 1.0 / (1.0 + dist * dist); // closer = brighter, farther = darker
+        let position = origin + ray * hit.t;
+        if (inside != 0u && hit.color == 0u) {
+            // leaving the transparent voxels, bent back unless it reflects inside
+            let out = refract(ray, hit.normal, materials[inside].ior);
+            if (all(hit.normal == vec3<f32>(0.0))) {
+                origin = position;
+                inside = 0u;
+            } else if (all(out == vec3<f32>(0.0))) {
+                ray = reflect(ray, hit.normal);
+                origin = position + hit.normal * SHADOW_BIAS;
+            } else {
+                ray = out;
+                origin = position - hit.normal * SHADOW_BIAS;
+                inside = 0u;
+            }
+            continue;
+        }
+        inside = 0u;
+
+        let material = materials[hit.color];
+        let surface = mix(shade(hit, position), material.color, material.emission);
+
+        let cos_i = max(-dot(ray, hit.normal), 0.0);
+        let fresnel = schlick(cos_i, material.ior) * (1.0 - material.roughness);
+        let mirror = material.metalness * (1.0 - material.roughness) + material.transparency * fresnel;
+        let through = material.transparency * (1.0 - fresnel);
+        let diffuse = max(1.0 - mirror - through, 0.0);
+
+        let follow = max(mirror, through);
+        if (bounce == env.bounces.x || follow == 0.0 || all(hit.normal == vec3<f32>(0.0))) {
+            color += weight * surface;
+            break;
+        }
+        color += weight * surface * (diffuse + min(mirror, through));
 
-    let end = dda_iter(ray);
-    let dist = distance(end, cam.origin);
-    let b: f32 = 1.0 / (1.0 + dist * dist); // closer = brighter, farther = darker
+        if (mirror >= through) {
+            weight *= mirror * mix(vec3<f32>(1.0), material.color, material.metalness);
+            ray = reflect(ray, hit.normal);
+            origin = position + hit.normal * SHADOW_BIAS;
+        } else {
+            weight *= through * mix(vec3<f32>(1.0), material.color, 1.0 - material.transparency);
+            ray = refract(ray, hit.normal, 1.0 / material.ior);
+            origin = position - hit.normal * SHADOW_BIAS;
+            inside = hit.color;
+        }
+    }
+    color = mix(color, sky_gradient(primary), fog_amount(primary_t));
 
-    textureStore(output_texture, vec2<i32>(global_id.xy), vec4<f32>(b, b, b, 1.0));
+    textureStore(output_texture, vec2<i32>(global_id.xy), vec4<f32>(color, 1.0));
 }
 
-struct StackFrame {
-    origin: vec3<f32>,
-    map_check: vec3<i32>,
-    ray_length: vec3<f32>,
-    
-    low: vec3<i32>,
-    high: vec3<i32>,
-
-    node_offset: u32,
-    depth: u32,
-    steps: u32,
-    
-    step_to_boundary: bool,
-    resumed: bool,
-}
-
-struct Stack {
-    frame: array<StackFrame, 8>,
-    sp: u32,
-}
-
-// Initialize stack
-fn stack_init(stack: ptr<function, Stack>) {
-    (*stack).sp = 0u;
-    // optional: clear frames
-    for(var i: u32 = 0u; i < 8u; i = i + 1u) {
-        (*stack).frame[i].resumed = false;
+// One path per pixel and frame, averaged with the samples before it. Every
+// bounce takes direct light from env.light_dir through a shadow ray and
+// continues in a cosine weighted direction, emissive voxels are the other
+// light sources.
+@compute @workgroup_size(8, 8)
+fn path_trace(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    // the render size, the texture is as big as the window
+    let size = vec2<u32>(cam.screen);
+    if (global_id.x >= size.x || global_id.y >= size.y) {
+        return;
+    }
+
+    let PI: f32 = 3.14159265359;
+    rng_state = hash(global_id.x + global_id.y * size.x + accum.samples * size.x * size.y);
+
+    // jittered inside the pixel so the samples also antialias
+    let jitter = vec2<f32>(random(), random());
+    let aspectRatio = cam.screen.x / cam.screen.y;
+    let pX = (2.0 * ((f32(global_id.x) + jitter.x) / cam.screen.x) - 1.0) * tan((cam.fov / 2.0 * PI / 180.0)) * aspectRatio;
+    let pY = (1.0 - 2.0 * ((f32(global_id.y) + jitter.y) / cam.screen.y)) * tan(cam.fov / 2.0 * PI / 180.0);
+
+    var origin = cam.origin;
+    var ray = normalize(pX * cam.right + pY * cam.up + cam.dir);
+    var max_dist = cam.far;
+    let primary = ray;
+    var primary_t = 0.0;
+
+    let sky_light = env.sun_color.rgb * env.sun_dir.w + vec3<f32>(0.6, 0.7, 1.0) * env.moon_dir.w;
+    var radiance = vec3<f32>(0.0);
+    var throughput = vec3<f32>(1.0);
+    for (var bounce = 0u; bounce <= accum.bounces; bounce++) {
+        let hit = trace(origin, ray, max_dist);
+        if (!hit.hit) {
+            // the sun and moon discs only for rays from the camera, later
+            // bounces already got them as direct light
+            radiance += throughput * select(sky_gradient(ray), sky(ray), bounce == 0u);
+            break;
+        }
+        if (bounce == 0u) {
+            primary_t = hit.t;
+        }
+
+        let material = materials[hit.color];
+        let albedo = material.color;
+        if (material.emission > 0.0) {
+            radiance += throughput * albedo * material.emission * EMISSIVE_GLOW;
+            break;
+        }
+        if (all(hit.normal == vec3<f32>(0.0))) {
+            // started inside a voxel
+            break;
+        }
+
+        let position = origin + ray * hit.t + hit.normal * SHADOW_BIAS;
+        throughput *= albedo;
+
+        let lambert = max(dot(hit.normal, env.light_dir.xyz), 0.0);
+        if (lambert > 0.0 && !trace(position, env.light_dir.xyz, max(env.shadow.x, cam.far)).hit) {
+            radiance += throughput * sky_light * lambert;
+        }
+
+        origin = position;
+        ray = cosine_sample(hit.normal);
+        max_dist = cam.far;
     }
+
+    let coords = vec2<i32>(global_id.xy);
+    let previous = textureLoad(accumulation, coords).rgb;
+    radiance = mix(radiance, sky_gradient(primary), fog_amount(primary_t));
+    let samples = f32(accum.samples);
+    let average = (previous * samples + radiance) / (samples + 1.0);
+
+    textureStore(accumulation, coords, vec4<f32>(average, 1.0));
+    textureStore(output_texture, coords, vec4<f32>(average, 1.0));
+}
+
+// how bright fully emissive voxels are as a light source
+const EMISSIVE_GLOW: f32 = 4.0;
+
+var<private> rng_state: u32;
+
+fn hash(seed: u32) -> u32 {
+    var x = seed * 747796405u + 2891336453u;
+    x = ((x >> ((x >> 28u) + 4u)) ^ x) * 277803737u;
+    return (x >> 22u) ^ x;
 }
 
-// Push a frame
-fn stack_push(stack: ptr<function, Stack>, frame: StackFrame) -> bool {
-    if ((*stack).sp >= 8u) {
-        return false; // stack overflow
+// uniform in 0..1, PCG
+fn random() -> f32 {
+    rng_state = hash(rng_state);
+    return f32(rng_state) / 4294967295.0;
+}
+
+// direction over the hemisphere around normal, more of them near the normal
+fn cosine_sample(normal: vec3<f32>) -> vec3<f32> {
+    let up = select(vec3<f32>(0.0, 1.0, 0.0), vec3<f32>(1.0, 0.0, 0.0), abs(normal.y) > 0.9);
+    let tangent = normalize(cross(up, normal));
+    let bitangent = cross(normal, tangent);
+
+    let r = sqrt(random());
+    let angle = random() * 6.28318530718;
+    let z = sqrt(max(1.0 - r * r, 0.0));
+    return normalize(tangent * r * cos(angle) + bitangent * r * sin(angle) + normal * z);
+}
+
+// Lambert from env.light_dir plus an ambient share, both scaled by how much
+// sky the flood filled light says reaches the voxel. Light levels are
+// 0..=15, each level darker by a fixed factor, sunlight follows the time of day.
+fn shade(hit: Hit, position: vec3<f32>) -> vec3<f32> {
+    let sun = pow(0.8, f32(15u - (hit.light >> 4u)));
+    let block = pow(0.8, f32(15u - (hit.light & 15u)));
+
+    let sky_light = env.sun_color.rgb * env.sun_dir.w + vec3<f32>(0.6, 0.7, 1.0) * env.moon_dir.w;
+    let ambient = env.light_dir.w;
+    var lambert = max(dot(hit.normal, env.light_dir.xyz), 0.0);
+    if (lambert > 0.0 && shadowed(position, hit.normal)) {
+        lambert = 0.0;
     }
-    (*stack).frame[(*stack).sp] = frame;
-    (*stack).sp = (*stack).sp + 1u;
-    return true;
+    let ao = occlusion(position, hit.normal);
+    let direct = sky_light * sun * (ambient * ao + (1.0 - ambient) * lambert);
+
+    return block_material(hit.color) * max(direct, vec3<f32>(block * ao));
 }
 
-// Peek top frame
-fn stack_pop(stack: ptr<function, Stack>) {
-    (*stack).sp = (*stack).sp - 1u;
+// shadow rays start this far off the surface
+const SHADOW_BIAS: f32 = 0.01;
+
+// hard shadow, the same trace towards the light, see Scene::in_shadow
+fn shadowed(position: vec3<f32>, normal: vec3<f32>) -> bool {
+    if (env.shadow.x <= 0.0) {
+        return false;
+    }
+    return trace(position + normal * SHADOW_BIAS, env.light_dir.xyz, env.shadow.x).hit;
 }
 
-fn stack_peek(stack: ptr<function, Stack>) -> StackFrame {
-    return (*stack).frame[(*stack).sp - 1u];
+const AO_CORNERS: u32 = 1u;
+const AO_RAYS: u32 = 2u;
+// how dark a fully closed in spot gets
+const AO_STRENGTH: f32 = 0.6;
+const AO_SAMPLES: u32 = 6u;
+const AO_RAY_LENGTH: f32 = 4.0;
+
+// 1 in the open, lower where voxels close in on the surface
+fn occlusion(position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    if (all(normal == vec3<f32>(0.0))) {
+        return 1.0;
+    }
+    switch (env.occlusion.x) {
+        case AO_CORNERS: { return corner_occlusion(position, normal); }
+        case AO_RAYS: { return ray_occlusion(position, normal); }
+        default: { return 1.0; }
+    }
 }
 
-fn stack_update(stack: ptr<function, Stack>, frame: StackFrame) {
-    (*stack).frame[(*stack).sp - 1u] = frame;
+// The two edge and one corner neighbors in front of every face corner give
+// that corner a value, blended across the face.
+fn corner_occlusion(position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    let n = abs(normal);
+    // turned objects are off the grid
+    if (max(n.x, max(n.y, n.z)) < 0.99) {
+        return 1.0;
+    }
+
+    // the two axes along the face
+    var u = vec3<i32>(0, 1, 0);
+    var w = vec3<i32>(0, 0, 1);
+    if (n.y > 0.5) {
+        u = vec3<i32>(1, 0, 0);
+    } else if (n.z > 0.5) {
+        u = vec3<i32>(1, 0, 0);
+        w = vec3<i32>(0, 1, 0);
+    }
+
+    let front = vec3<i32>(floor(position + normal * 0.5));
+    let u0 = solid_at(front - u);
+    let u1 = solid_at(front + u);
+    let w0 = solid_at(front - w);
+    let w1 = solid_at(front + w);
+
+    let a00 = corner_value(u0, w0, solid_at(front - u - w));
+    let a10 = corner_value(u1, w0, solid_at(front + u - w));
+    let a01 = corner_value(u0, w1, solid_at(front - u + w));
+    let a11 = corner_value(u1, w1, solid_at(front + u + w));
+
+    let f = fract(position);
+    let fu = dot(f, vec3<f32>(u));
+    let fw = dot(f, vec3<f32>(w));
+    let ao = mix(mix(a00, a10, fu), mix(a01, a11, fu), fw);
+    return 1.0 - AO_STRENGTH * (1.0 - ao);
 }
 
-// Pop top frame
-fn stack_full(stack: ptr<function, Stack>) -> bool {
-    if ((*stack).sp == 0u) {
-        return false; // stack underflow
+// both edges taken close the corner even if its diagonal is open
+fn corner_value(side1: bool, side2: bool, corner: bool) -> f32 {
+    if (side1 && side2) {
+        return 0.0;
     }
-    return true;
+    return (3.0 - f32(side1) - f32(side2) - f32(corner)) / 3.0;
 }
 
-const SUBDIVISION: u32 = 4u;
-const REGION_SIZE: u32 = 256u;
-const SIZE_XYZ: vec3<i32> = vec3<i32>(i32(SUBDIVISION), i32(SUBDIVISION), i32(SUBDIVISION));
-const MAX_STEP_COUNT = (3u * SUBDIVISION - 2u) * 8u;
-const MAX_DEPTH = 3u;
+// share of a few short rays around the normal that get out
+fn ray_occlusion(position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    let up = select(vec3<f32>(0.0, 1.0, 0.0), vec3<f32>(1.0, 0.0, 0.0), abs(normal.y) > 0.9);
+    let tangent = normalize(cross(up, normal));
+    let bitangent = cross(normal, tangent);
+
+    var open = 0.0;
+    for (var i = 0u; i < AO_SAMPLES; i++) {
+        let angle = f32(i) * 6.28318530718 / f32(AO_SAMPLES);
+        // 45 degrees off the normal
+        let dir = normalize(normal + tangent * cos(angle) + bitangent * sin(angle));
+        if (!trace(position + normal * SHADOW_BIAS, dir, AO_RAY_LENGTH).hit) {
+            open += 1.0;
+        }
+    }
+    return 1.0 - AO_STRENGTH * (1.0 - open / f32(AO_SAMPLES));
+}
 
-fn dda_iter(ray_dir: vec3<f32>) -> vec3<f32> {
-    let origin = cam.origin;
-    let step: vec3<i32> = vec3<i32>(sign(ray_dir));
+const SUN_SIZE: f32 = 0.9995;
+const MOON_SIZE: f32 = 0.9997;
 
-    let ray_dir_offset = ray_dir * 0.0000001;
+// the gradient with the sun and moon drawn in
+fn sky(ray: vec3<f32>) -> vec3<f32> {
+    if (dot(ray, env.sun_dir.xyz) > SUN_SIZE) {
+        return env.sun_color.rgb;
+    }
+    if (dot(ray, env.moon_dir.xyz) > MOON_SIZE) {
+        return vec3<f32>(0.8, 0.85, 0.9);
+    }
+    return sky_gradient(ray);
+}
 
-    let ray_unit_step: vec3<f32> = vec3<f32>(
-        sqrt(1.0 + (ray_dir.y * ray_dir.y + ray_dir.z * ray_dir.z) / (ray_dir.x * ray_dir.x)),
-        sqrt(1.0 + (ray_dir.x * ray_dir.x + ray_dir.z * ray_dir.z) / (ray_dir.y * ray_dir.y)),
-        sqrt(1.0 + (ray_dir.x * ray_dir.x + ray_dir.y * ray_dir.y) / (ray_dir.z * ray_dir.z)),
-    );
+// scattering per unit of air, Rayleigh for red, green and blue, Mie grey
+const RAYLEIGH: vec3<f32> = vec3<f32>(0.0058, 0.0135, 0.0331);
+const MIE: f32 = 0.004;
+// how much Mie scattering leans forward, towards the light
+const MIE_G: f32 = 0.76;
+// air straight up, the horizon sees about 20 times as much
+const AIR_DEPTH: f32 = 10.0;
+const SUN_INTENSITY: f32 = 20.0;
+// the moon lights the air this much weaker than the sun
+const MOON_SCATTER: f32 = 0.05;
+
+// Sky light from single scattering in air of even density. Below the
+// horizon the air just darkens, the ground is drawn by the voxels.
+fn sky_gradient(ray: vec3<f32>) -> vec3<f32> {
+    let view = normalize(vec3<f32>(ray.x, max(ray.y, 0.0), ray.z));
+
+    var color = scattering(view, env.sun_dir.xyz) * env.sun_dir.w;
+    color += scattering(view, env.moon_dir.xyz) * env.moon_dir.w * MOON_SCATTER;
+    // the clock's sky color takes over at night
+    color += env.sky_color.rgb * (1.0 - env.sun_dir.w);
+
+    return color * (1.0 - 0.5 * clamp(-ray.y * 5.0, 0.0, 1.0));
+}
 
-    var curr: GpuNode;
-    // -------------------------------- //
+// light scattered towards the eye along `view` from a light in direction `light`
+fn scattering(view: vec3<f32>, light: vec3<f32>) -> vec3<f32> {
+    let mu = dot(view, light);
+    let beta = RAYLEIGH + MIE;
 
-    // Stack set up
-    var stack: Stack = Stack();
-    stack_init(&stack);
+    // air the ray looks through, and air the light crossed to get there
+    let view_depth = AIR_DEPTH / (view.y + 0.05);
+    let light_depth = AIR_DEPTH / (max(light.y, 0.0) + 0.05);
+    let sunlight = exp(-beta * light_depth);
 
-    // push base frame
-    let base_frame = StackFrame(
-        origin,                     // origin
-        vec3<i32>(0, 0, 0),         // map_check
-        vec3<f32>(0.0, 0.0, 0.0),   // ray_length
+    let rayleigh_phase = 3.0 / (16.0 * 3.14159265359) * (1.0 + mu * mu);
+    let g2 = MIE_G * MIE_G;
+    let mie_phase = (1.0 - g2) / (4.0 * 3.14159265359 * pow(1.0 + g2 - 2.0 * MIE_G * mu, 1.5));
 
-        header.base,    // low
-        header.end,     // high
+    let scattered = (RAYLEIGH * rayleigh_phase + MIE * mie_phase) / beta;
+    return sunlight * scattered * (1.0 - exp(-beta * view_depth)) * SUN_INTENSITY;
+}
 
-        init_region_offset(origin), // offset to node
-        0u,      // depth
-        0u,     // steps done
+// how much of what lies t along the camera ray is hidden by fog, reaching 1
+// at the far end so chunks fade in instead of popping up
+fn fog_amount(t: f32) -> f32 {
+    if (fog.end <= fog.start || t <= 0.0) {
+        return 0.0;
+    }
+    let haze = 1.0 - exp(-fog.density * t);
+    return clamp(max(haze, smoothstep(fog.start, fog.end, t)), 0.0, 1.0);
+}
 
-        false,  // step to boundary
-        false,  // resumed
-    );
-    stack_push(&stack, base_frame);
+fn block_material(block: u32) -> vec3<f32> {
+    return materials[block].color;
+}
 
-    var map_check: vec3<i32> = vec3<i32>(0);
-    var ray_length: vec3<f32> = vec3<f32>(0.0);
+// share of light reflected off a surface between air and ior
+fn schlick(cos_i: f32, ior: f32) -> f32 {
+    let r0 = pow((1.0 - ior) / (1.0 + ior), 2.0);
+    return r0 + (1.0 - r0) * pow(1.0 - cos_i, 5.0);
+}
 
-    while(stack_full(&stack)) {
-        var frame = stack_peek(&stack);
+// Transparent voxels the ray is travelling through, 0 outside of them. While
+// set, traces stop at the first voxel that is not the same medium, air
+// included, so the ray can bend again where it leaves.
+var<private> medium: u32;
+
+// glass and water of every level count as one medium
+fn same_medium(a: u32, b: u32) -> bool {
+    let first = materials[a];
+    let second = materials[b];
+    return first.transparency > 0.0
+        && first.transparency == second.transparency
+        && first.ior == second.ior
+        && all(first.color == second.color);
+}
 
-        // local variables for layer in voxel grid:
-        let origin: vec3<f32> = frame.origin;
+const CHUNK_SIZE: i32 = 256;
+// leaf level, children of a leaf are single voxels
+const MAX_DEPTH: u32 = 3u;
+const MAX_STEPS: u32 = 512u;
+// nudge into the next cell when stepping over a boundary
+const EPSILON: f32 = 0.001;
+// color flag: node is filled all the way down, also ends chunks cut at a lower LOD
+const SOLID_FLAG: u32 = 0x80000000u;
+// base flag of solid nodes that carry their own light, see cpu_side_svo.rs
+const LIGHT_SET: u32 = 0x100u;
+const FULL_SUN: u32 = 0xF0u;
+
+struct Hit {
+    hit: bool,
+    t: f32,
+    // face the ray entered through
+    normal: vec3<f32>,
+    color: u32,
+    // sunlight in the high 4 bits, block light in the low 4
+    light: u32,
+}
 
-        let low: vec3<i32> = frame.low;
-        let high: vec3<i32> = frame.high;
-    
-        let node_offset: u32 = frame.node_offset;
-        let depth: u32 = frame.depth;
-        var steps: u32 = frame.steps;
+// deepest node around a voxel, or the empty cell it lies in
+struct Cell {
+    solid: bool,
+    color: u32,
+    light: u32,
+    // size of the empty cell
+    size: i32,
+}
 
-        let step_to_boundary: bool = frame.step_to_boundary;
-        var resumed: bool = frame.resumed;
+struct Step {
+    t: f32,
+    normal: vec3<f32>,
+}
 
-        let cell_size: f32 = f32(REGION_SIZE) / f32(pow_u32(SUBDIVISION, depth));
-        var distance = 0.0;
-        let node: GpuNode = get_region(node_offset);
+const BVH_STACK: u32 = 32u;
 
-        // initialize ray at layer
-        if (!resumed) {
-            resumed = true;
+// Walks the hierarchy nearest child first and traces every object whose box
+// the ray reaches before the closest hit so far.
+fn trace(origin: vec3<f32>, dir: vec3<f32>, max_dist: f32) -> Hit {
+    var result = Hit(false, max_dist, vec3<f32>(0.0), 0u, FULL_SUN);
 
-            let origin_grid = (origin + ray_dir_offset) / cell_size;
-            map_check = vec3<i32>(floor(origin_grid));
+    let safe_dir = select(dir, vec3<f32>(1e-8), abs(dir) < vec3<f32>(1e-8));
+    let inv_dir = 1.0 / safe_dir;
 
-            let delta = origin_grid - vec3<f32>(map_check);
-            ray_length = init_ray_length(ray_dir, ray_unit_step, delta);
+    var stack: array<u32, BVH_STACK>;
+    var top = 1u;
+    stack[0] = 0u;
 
-            if (step_to_boundary) {
-                distance = step(&map_check, &ray_length, step, ray_unit_step);
-            }
-        } else {
-            map_check = frame.map_check;
-            ray_length = frame.ray_length;
+    while (top > 0u) {
+        top -= 1u;
+        let node = bvh[stack[top]];
+        if (box_entry(node.low, node.high, origin, inv_dir) >= result.t) {
+            continue;
+        }
 
-            distance = step(&map_check, &ray_length, step, ray_unit_step);
-            if (outside_bounds(map_check, low, high)) {
-                stack_pop(&stack);
-                continue;
+        if (node.count > 0u) {
+            for (var i = node.first; i < node.first + node.count; i++) {
+                let hit = trace_object(origin, dir, result.t, objects[i]);
+                if (hit.hit) {
+                    result = hit;
+                }
             }
+            continue;
+        }
+        if (node.first == 0u || top + 2u > BVH_STACK) {
+            continue;
         }
 
-        for (var i = steps; i < MAX_STEP_COUNT; i++) {
-            let sub_node_offset = get_sub_region_offset(map_check, node);
-            if (depth == MAX_DEPTH) { return cam.origin; }
+        // the nearer child is popped first
+        let left = node.first;
+        let t_left = box_entry(bvh[left].low, bvh[left].high, origin, inv_dir);
+        let t_right = box_entry(bvh[left + 1u].low, bvh[left + 1u].high, origin, inv_dir);
+        let near_left = t_left <= t_right;
+        stack[top] = select(left, left + 1u, near_left);
+        stack[top + 1u] = select(left + 1u, left, near_left);
+        top += 2u;
+    }
+
+    return result;
+}
 
-            if (sub_node_offset != 0u && depth < MAX_DEPTH) {
-                steps = i + 1u;
-                frame.steps = steps;
-                frame.map_check = map_check;
-                frame.ray_length = ray_length;
-                frame.resumed = resumed;
-                stack_update(&stack, frame);
+// distance along the ray to the box, a huge value if it is missed
+fn box_entry(low: vec3<f32>, high: vec3<f32>, origin: vec3<f32>, inv_dir: vec3<f32>) -> f32 {
+    let t_low = (low - origin) * inv_dir;
+    let t_high = (high - origin) * inv_dir;
+    let t_enter = min(t_low, t_high);
+    let t_leave = max(t_low, t_high);
 
-                let low = map_check * i32(SUBDIVISION);
-                let high = low + SIZE_XYZ;
+    let t = max(max(t_enter.x, t_enter.y), max(t_enter.z, 0.0));
+    let t_exit = min(t_leave.x, min(t_leave.y, t_leave.z));
+    return select(1e30, t, t <= t_exit);
+}
 
-                let new_frame = StackFrame(
-                    origin + (ray_dir - ray_dir_offset) * distance * cell_size,
+// Same walk through one object's tree, with the ray moved into object space.
+// Rotations keep lengths so t is the same in both spaces.
+fn trace_object(origin: vec3<f32>, dir: vec3<f32>, max_dist: f32, object: GpuObject) -> Hit {
+    var result = Hit(false, max_dist, vec3<f32>(0.0), 0u, FULL_SUN);
 
-                    vec3<i32>(0), // mapcheck
-                    vec3<f32>(0.0), // ray_length
+    let local_origin = to_local(object, origin);
+    let local_dir = vec3<f32>(dot(object.m0.xyz, dir), dot(object.m1.xyz, dir), dot(object.m2.xyz, dir));
 
-                    low,
-                    high,
+    let safe_dir = select(local_dir, vec3<f32>(1e-8), abs(local_dir) < vec3<f32>(1e-8));
+    let inv_dir = 1.0 / safe_dir;
 
-                    sub_node_offset,
+    // clip the ray to the object's box
+    let t_low = -local_origin * inv_dir;
+    let t_high = (object.size.xyz - local_origin) * inv_dir;
+    let t_enter = min(t_low, t_high);
+    let t_leave = max(t_low, t_high);
 
-                    depth + 1u,
-                    0u, // steps
+    var t = max(max(t_enter.x, t_enter.y), max(t_enter.z, 0.0));
+    let t_exit = min(min(t_leave.x, t_leave.y), min(t_leave.z, max_dist));
+    if (t >= t_exit) {
+        return result;
+    }
 
-                    distance != 0.0, // step to boundary
-                    false,
-                );
+    var normal = vec3<f32>(0.0);
+    if (t > 0.0) {
+        normal = entry_normal(t_enter, local_dir);
+    }
 
-                stack_push(&stack, new_frame);
-                break;
-            }
+    let size = vec3<i32>(object.size.xyz);
+    for (var i = 0u; i < MAX_STEPS; i++) {
+        if (t >= t_exit) {
+            break;
+        }
 
-            if (depth == MAX_DEPTH && sub_node_offset != 0u) {
-                // return point of collision
-                return origin + ray_dir * distance * cell_size;
-            }
+        let voxel = vec3<i32>(floor(local_origin + local_dir * (t + EPSILON)));
+        if (any(voxel < vec3<i32>(0)) || any(voxel >= size)) {
+            break;
+        }
 
-            distance = step(&map_check, &ray_length, step, ray_unit_step);
-            if (outside_bounds(map_check, low, high)) {
-                stack_pop(&stack);
-                break;
-            }
+        let cell = descend(object.root.x, voxel);
+        let in_medium = cell.solid && same_medium(cell.color, medium);
+        if (select(cell.solid, !in_medium, medium != 0u)) {
+            // the transform is a rotation, its transpose takes the normal back
+            let world_normal = object.m0.xyz * normal.x + object.m1.xyz * normal.y + object.m2.xyz * normal.z;
+            return Hit(true, t, world_normal, select(0u, cell.color, cell.solid), cell.light);
         }
+
+        let next = step_cell(local_origin, local_dir, inv_dir, voxel, cell.size, t);
+        t = next.t;
+        normal = next.normal;
     }
-    return vec3<f32>(1000000.0);
-}
 
-fn init_region_offset(origin: vec3<f32>) -> u32 {
-    let coord = vec3<i32>(floor(origin / 256f));
-    let offset = coord - header.base;
-    let per_axis = header.end - header.base;
+    return result;
+}
 
-    let final_offset = u32(offset.x + offset.y * per_axis.x + offset.z * per_axis.x * per_axis.y);
-    return final_offset + 1u; // + 1 because [0] is NULL
+fn to_local(object: GpuObject, point: vec3<f32>) -> vec3<f32> {
+    return vec3<f32>(
+        dot(object.m0.xyz, point) + object.m0.w,
+        dot(object.m1.xyz, point) + object.m1.w,
+        dot(object.m2.xyz, point) + object.m2.w,
+    );
 }
 
-fn get_region(offset: u32) -> GpuNode {
-    return nodes[offset];
+// whether anything traced fills the voxel, searched through the hierarchy
+fn solid_at(voxel: vec3<i32>) -> bool {
+    let point = vec3<f32>(voxel) + 0.5;
+
+    var stack: array<u32, BVH_STACK>;
+    var top = 1u;
+    stack[0] = 0u;
+
+    while (top > 0u) {
+        top -= 1u;
+        let node = bvh[stack[top]];
+        if (any(point < node.low) || any(point > node.high)) {
+            continue;
+        }
+
+        if (node.count > 0u) {
+            for (var i = node.first; i < node.first + node.count; i++) {
+                let object = objects[i];
+                let local = to_local(object, point);
+                if (any(local < vec3<f32>(0.0)) || any(local >= object.size.xyz)) {
+                    continue;
+                }
+                if (descend(object.root.x, vec3<i32>(floor(local))).solid) {
+                    return true;
+                }
+            }
+            continue;
+        }
+        if (node.first == 0u || top + 2u > BVH_STACK) {
+            continue;
+        }
+
+        stack[top] = node.first;
+        stack[top + 1u] = node.first + 1u;
+        top += 2u;
+    }
+    return false;
 }
 
-fn get_sub_region_offset(map_check: vec3<i32>, node: GpuNode) -> u32 {
-    let mask = SUBDIVISION - 1u;
-    let coord  = vec3<u32>(map_check) & vec3<u32>(mask);
-    // let offset = coord.x + coord.y * SUBDIVISION + coord.z * SUBDIVISION * SUBDIVISION;
-    let shift = coord.x + coord.y * SUBDIVISION + coord.z * SUBDIVISION * SUBDIVISION;
+fn descend(root: u32, voxel: vec3<i32>) -> Cell {
+    var index = root;
+    var size = CHUNK_SIZE;
+    var depth = 0u;
+
+    loop {
+        let node = nodes[index];
+        if ((node.color & SOLID_FLAG) != 0u) {
+            return Cell(true, node.color & ~SOLID_FLAG, solid_light(node), size);
+        }
+        if ((node.mask_l | node.mask_h) == 0u) {
+            return Cell(false, 0u, 0u, size);
+        }
 
-    var pointer = 0u;
-    if (shift < 32u) {
-        if ((node.mask_l & (1u << shift)) != 0u) {
-            let o1 = countOneBits(node.mask_l >> shift);
-            let offset = o1 - 1u;
+        let child = size / 4;
+        let shift = 6u - 2u * depth;
+        let local = (voxel >> vec3<u32>(shift)) & vec3<i32>(3);
+        let bit = u32(local.x + local.y * 4 + local.z * 16);
 
-            pointer = node.base + offset;
+        if (!has_child(node, bit)) {
+            return Cell(false, 0u, 0u, child);
         }
-    } else {
-        let shift = shift - 32u;
-        if ((node.mask_h & (1u << shift)) != 0u) {
-            let o1 = countOneBits(node.mask_l);
-            let o2 = countOneBits(node.mask_h >> shift);
-            let offset = o1 + o2 - 1u;
-
-            pointer = node.base + offset;
+        if (depth == MAX_DEPTH) {
+            return Cell(true, leaf_block(node, bit), leaf_light(node, bit), 1);
         }
+
+        index = node.base + child_offset(node, bit);
+        if (index >= header.size) {
+            return Cell(false, 0u, 0u, child);
+        }
+        size = child;
+        depth += 1u;
     }
+    return Cell(false, 0u, 0u, size);
+}
+
+// step to the far side of the empty cell around `voxel`
+fn step_cell(origin: vec3<f32>, dir: vec3<f32>, inv_dir: vec3<f32>, voxel: vec3<i32>, cell: i32, t: f32) -> Step {
+    let cell_low = vec3<f32>(voxel & vec3<i32>(~(cell - 1)));
+    let bound = select(cell_low, cell_low + f32(cell), dir > vec3<f32>(0.0));
+    let t_cell = (bound - origin) * inv_dir;
 
-    if (pointer >= header.size) { return 0u; }
-    return pointer;
-}
-
-fn step(
-    map_check: ptr<function, vec3<i32>>,
-    ray_length: ptr<function, vec3<f32>>,
-    step: vec3<i32>,
-    ray_unit_step: vec3<f32>,
-) -> f32 {
-    var distance: f32 = 0.0;
-
-    if ((*ray_length).x < (*ray_length).y && (*ray_length).x < (*ray_length).z) {
-        (*map_check).x += step.x;
-        distance = (*ray_length).x;
-        (*ray_length).x += ray_unit_step.x;
-    } else if ((*ray_length).y < (*ray_length).z) {
-        (*map_check).y += step.y;
-        distance = (*ray_length).y;
-        (*ray_length).y += ray_unit_step.y;
-    } else {
-        (*map_check).z += step.z;
-        distance = (*ray_length).z;
-        (*ray_length).z += ray_unit_step.z;
+    if (t_cell.x <= t_cell.y && t_cell.x <= t_cell.z) {
+        return Step(max(t_cell.x, t), vec3<f32>(-sign(dir.x), 0.0, 0.0));
+    } else if (t_cell.y <= t_cell.z) {
+        return Step(max(t_cell.y, t), vec3<f32>(0.0, -sign(dir.y), 0.0));
     }
+    return Step(max(t_cell.z, t), vec3<f32>(0.0, 0.0, -sign(dir.z)));
+}
 
-    return distance;
+fn entry_normal(t_enter: vec3<f32>, dir: vec3<f32>) -> vec3<f32> {
+    if (t_enter.x >= t_enter.y && t_enter.x >= t_enter.z) {
+        return vec3<f32>(-sign(dir.x), 0.0, 0.0);
+    } else if (t_enter.y >= t_enter.z) {
+        return vec3<f32>(0.0, -sign(dir.y), 0.0);
+    }
+    return vec3<f32>(0.0, 0.0, -sign(dir.z));
 }
 
-fn outside_bounds(map_check: vec3<i32>, low: vec3<i32>, high: vec3<i32>) -> bool {
-    return any(map_check < low) || any(map_check >= high);
+fn solid_light(node: GpuNode) -> u32 {
+    if ((node.base & LIGHT_SET) != 0u) {
+        return node.base & 0xFFu;
+    }
+    return FULL_SUN;
 }
 
-fn init_ray_length(ray_dir: vec3<f32>, ray_unit_step: vec3<f32>, delta: vec3<f32>) -> vec3<f32> {
-    let ray_length = select(
-        (1.0 - delta) * ray_unit_step, // when ray_dir >= 0
-        delta * ray_unit_step,         // when ray_dir < 0
-        ray_dir < vec3<f32>(0.0),       // condition
-    );
+// leaf base is its brick, see cpu_side_svo.rs
+fn leaf_light(node: GpuNode, bit: u32) -> u32 {
+    return brick_byte(node.base * 32u + bit / 4u, bit);
+}
 
-    return ray_length;
+fn leaf_block(node: GpuNode, bit: u32) -> u32 {
+    return brick_byte(node.base * 32u + 16u + bit / 4u, bit);
 }
 
-fn pow_u32(base: u32, exp: u32) -> u32 {
-    var result: u32 = 1u;
-    var e: u32 = exp;
-    var b: u32 = base;
+fn brick_byte(word: u32, bit: u32) -> u32 {
+    return (bricks[word] >> ((bit % 4u) * 8u)) & 0xFFu;
+}
 
-    // Fast exponentiation (O(log n))
-    while (e > 0u) {
-        if ((e & 1u) != 0u) {
-            result = result * b;
-        }
-        e = e >> 1u;   // divide exponent by 2
-        b = b * b;     // square base
+fn has_child(node: GpuNode, bit: u32) -> bool {
+    if (bit < 32u) {
+        return (node.mask_l & (1u << bit)) != 0u;
     }
-    return result;
-}
\ No newline at end of file
+    return (node.mask_h & (1u << (bit - 32u))) != 0u;
+}
+
+// children are stored in bit order, count the ones before `bit`
+fn child_offset(node: GpuNode, bit: u32) -> u32 {
+    if (bit < 32u) {
+        return countOneBits(node.mask_l & ((1u << bit) - 1u));
+    }
+    return countOneBits(node.mask_l) + countOneBits(node.mask_h & ((1u << (bit - 32u)) - 1u));
+}
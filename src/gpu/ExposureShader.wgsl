@@ -0,0 +1,89 @@
+// Auto exposure. `histogram` sorts every pixel of the shared texture into
+// bins of log2 luminance, `average` turns the bins into the scene luminance
+// the fullscreen pass exposes for and clears them for the next frame.
+
+// luminance the picture is adapting to, kept between frames
+struct Exposure {
+    luminance: f32,
+}
+
+// see gpu/types.rs
+struct Upscale {
+    size: vec2<u32>,
+    mode: u32,
+}
+
+@group(0) @binding(0)
+var shared_texture: texture_storage_2d<rgba16float, read>;
+@group(0) @binding(1)
+var<storage, read_write> bins: array<atomic<u32>, 256>;
+@group(0) @binding(2)
+var<storage, read_write> state: Exposure;
+@group(0) @binding(3)
+var<uniform> upscale: Upscale;
+
+// log2 luminance covered by the bins, bin 0 holds everything darker
+const MIN_LOG: f32 = -10.0;
+const LOG_RANGE: f32 = 14.0;
+// share of the way to the new luminance taken every frame
+const ADAPT_RATE: f32 = 0.05;
+
+var<workgroup> local_bins: array<atomic<u32>, 256>;
+
+@compute @workgroup_size(16, 16)
+fn histogram(@builtin(global_invocation_id) global_id: vec3<u32>, @builtin(local_invocation_index) index: u32) {
+    atomicStore(&local_bins[index], 0u);
+    workgroupBarrier();
+
+    // only the rendered part of the texture
+    let size = upscale.size;
+    if (global_id.x < size.x && global_id.y < size.y) {
+        let color = textureLoad(shared_texture, vec2<i32>(global_id.xy)).rgb;
+        atomicAdd(&local_bins[bin(luminance(color))], 1u);
+    }
+    workgroupBarrier();
+
+    atomicAdd(&bins[index], atomicLoad(&local_bins[index]));
+}
+
+var<workgroup> weights: array<f32, 256>;
+
+@compute @workgroup_size(256)
+fn average(@builtin(local_invocation_index) index: u32) {
+    // the black bin does not count, a dark cave should not blow up the sky
+    let count = atomicExchange(&bins[index], 0u);
+    weights[index] = select(f32(count), 0.0, index == 0u);
+    workgroupBarrier();
+
+    if (index == 0u) {
+        var total = 0.0;
+        var weighted = 0.0;
+        for (var i = 1u; i < 256u; i++) {
+            total += weights[i];
+            weighted += weights[i] * f32(i);
+        }
+        if (total == 0.0) {
+            return;
+        }
+
+        let log_luminance = (weighted / total - 1.0) / 254.0 * LOG_RANGE + MIN_LOG;
+        let target_luminance = exp2(log_luminance);
+        state.luminance = select(
+            mix(state.luminance, target_luminance, ADAPT_RATE),
+            target_luminance,
+            state.luminance <= 0.0
+        );
+    }
+}
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+fn bin(luminance: f32) -> u32 {
+    if (luminance < exp2(MIN_LOG)) {
+        return 0u;
+    }
+    let position = clamp((log2(luminance) - MIN_LOG) / LOG_RANGE, 0.0, 1.0);
+    return u32(position * 254.0 + 1.0);
+}
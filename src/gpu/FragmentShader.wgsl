@@ -13,18 +13,116 @@ fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
     return VertexOutput(vec4<f32>(positions[vertex_index], 0.0, 1.0), uv);
 }
 
+// see gpu/types.rs
+struct Tonemap {
+    curve: u32,
+    exposure: f32,
+    auto_exposure: u32,
+}
+
+// written by ExposureShader.wgsl
+struct Exposure {
+    luminance: f32,
+}
+
+// see gpu/types.rs
+struct Upscale {
+    size: vec2<u32>,
+    mode: u32,
+}
+
 @group(0) @binding(0)
-var output_texture: texture_storage_2d<rgba8unorm, read>;
+var<uniform> tonemap: Tonemap;
+@group(0) @binding(1)
+var<storage, read> state: Exposure;
+@group(0) @binding(2)
+var<uniform> upscale: Upscale;
+
+@group(1) @binding(0)
+var output_texture: texture_storage_2d<rgba16float, read>;
+
+const REINHARD: u32 = 0u;
+const ACES: u32 = 1u;
+// scene luminance auto exposure maps to
+const AUTO_KEY: f32 = 0.3;
+
+const BILINEAR: u32 = 0u;
+const EDGE_AWARE: u32 = 1u;
+// how much the edge aware filter ignores samples unlike the nearest one
+const EDGE_SHARPNESS: f32 = 8.0;
 
 @fragment
 fn fs_main(@builtin(position) pos: vec4<f32>) -> @location(0) vec4<f32> {
-    let coords = vec2<i32>(i32(pos.x), i32(pos.y));
-    let sample = textureLoad(output_texture, coords);
-    let color = vec3<f32>(sample.x, sample.y, sample.z);
+    var color = upscaled(pos.xy) * tonemap.exposure;
+
+    if (tonemap.auto_exposure != 0u && state.luminance > 0.0) {
+        color *= AUTO_KEY / state.luminance;
+    }
+
+    switch (tonemap.curve) {
+        case ACES: { color = aces(color); }
+        default: { color = reinhard(color); }
+    }
 
     return vec4<f32>(linear_to_srgb(color), 1.0);
 }
 
+// the compute pass renders into the top left render size of the texture,
+// stretched over the frame between the four nearest rendered pixels
+fn upscaled(frame_pos: vec2<f32>) -> vec3<f32> {
+    let frame = vec2<f32>(textureDimensions(output_texture));
+    let size = max(vec2<f32>(upscale.size), vec2<f32>(1.0));
+    let last = vec2<i32>(size) - 1;
+
+    // position among the rendered pixel centers
+    let p = frame_pos * size / frame - 0.5;
+    let corner = vec2<i32>(floor(p));
+    let f = p - floor(p);
+
+    let c00 = load(corner, last);
+    let c10 = load(corner + vec2<i32>(1, 0), last);
+    let c01 = load(corner + vec2<i32>(0, 1), last);
+    let c11 = load(corner + vec2<i32>(1, 1), last);
+    var weights = vec4<f32>((1.0 - f.x) * (1.0 - f.y), f.x * (1.0 - f.y), (1.0 - f.x) * f.y, f.x * f.y);
+
+    // samples across an edge from the nearest one count less, so edges stay
+    // sharp instead of smearing
+    if (upscale.mode == EDGE_AWARE) {
+        let brightness = vec4<f32>(log_luminance(c00), log_luminance(c10), log_luminance(c01), log_luminance(c11));
+        let right = f.x >= 0.5;
+        let nearest = select(
+            select(brightness.x, brightness.y, right),
+            select(brightness.z, brightness.w, right),
+            f.y >= 0.5
+        );
+        weights /= 1.0 + EDGE_SHARPNESS * abs(brightness - nearest);
+    }
+
+    let total = weights.x + weights.y + weights.z + weights.w;
+    return (c00 * weights.x + c10 * weights.y + c01 * weights.z + c11 * weights.w) / total;
+}
+
+fn load(coords: vec2<i32>, last: vec2<i32>) -> vec3<f32> {
+    return textureLoad(output_texture, clamp(coords, vec2<i32>(0), last)).rgb;
+}
+
+fn log_luminance(c: vec3<f32>) -> f32 {
+    return log2(1.0 + dot(c, vec3<f32>(0.2126, 0.7152, 0.0722)));
+}
+
+fn reinhard(c: vec3<f32>) -> vec3<f32> {
+    return c / (1.0 + c);
+}
+
+// Narkowicz's fit of the ACES filmic curve
+fn aces(c: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((c * (a * c + b)) / (c * (2.43 * c + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
 fn linear_to_srgb(c: vec3<f32>) -> vec3<f32> {
     return select(
         12.92 * c,
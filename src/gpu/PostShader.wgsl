@@ -0,0 +1,181 @@
+// Post effects, one fragment entry point each, drawn with the fullscreen
+// vertex shader of FragmentShader.wgsl. Every pass reads the tonemapped
+// picture the pass before it left and writes the next one, the order is
+// chosen in the settings, see core/settings.rs.
+
+// see gpu/types.rs
+struct Post {
+    color_blindness: u32,
+}
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+@group(0) @binding(1)
+var linear_sampler: sampler;
+@group(0) @binding(2)
+var lut: texture_3d<f32>;
+@group(0) @binding(3)
+var<uniform> post: Post;
+
+const PI: f32 = 3.14159265;
+
+// brightness above which a pixel glows, and how much of the glow is added
+const BLOOM_THRESHOLD: f32 = 0.7;
+const BLOOM_STRENGTH: f32 = 0.6;
+// rings of eight taps, BLOOM_SPACING pixels apart
+const BLOOM_RINGS: i32 = 4;
+const BLOOM_SPACING: f32 = 3.0;
+
+const FXAA_SPAN: f32 = 8.0;
+const FXAA_REDUCE_MUL: f32 = 1.0 / 8.0;
+const FXAA_REDUCE_MIN: f32 = 1.0 / 128.0;
+
+// darkening at the corners, from VIGNETTE_START of the way out
+const VIGNETTE: f32 = 0.5;
+const VIGNETTE_START: f32 = 0.4;
+
+const PROTANOPIA: u32 = 0u;
+const DEUTERANOPIA: u32 = 1u;
+const TRITANOPIA: u32 = 2u;
+
+@fragment
+fn bloom(@builtin(position) pos: vec4<f32>) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source));
+    let uv = pos.xy * texel;
+    let color = fetch(uv);
+
+    var glow = vec3<f32>(0.0);
+    var total = 0.0;
+    for (var ring = 1; ring <= BLOOM_RINGS; ring++) {
+        let weight = exp(-f32(ring * ring) / 8.0);
+        // every ring turned half a step so the taps do not line up
+        for (var i = 0; i < 8; i++) {
+            let angle = (f32(i) + 0.5 * f32(ring)) * PI / 4.0;
+            let offset = vec2<f32>(cos(angle), sin(angle)) * f32(ring) * BLOOM_SPACING;
+            glow += bright(fetch(uv + offset * texel)) * weight;
+            total += weight;
+        }
+    }
+
+    return vec4<f32>(color + glow / total * BLOOM_STRENGTH, 1.0);
+}
+
+// Lottes' FXAA, blurs along the edge found from the luma of the corners
+@fragment
+fn fxaa(@builtin(position) pos: vec4<f32>) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source));
+    let uv = pos.xy * texel;
+
+    let color = fetch(uv);
+    let nw = luma(fetch(uv + vec2<f32>(-1.0, -1.0) * texel));
+    let ne = luma(fetch(uv + vec2<f32>(1.0, -1.0) * texel));
+    let sw = luma(fetch(uv + vec2<f32>(-1.0, 1.0) * texel));
+    let se = luma(fetch(uv + vec2<f32>(1.0, 1.0) * texel));
+    let m = luma(color);
+
+    let luma_min = min(m, min(min(nw, ne), min(sw, se)));
+    let luma_max = max(m, max(max(nw, ne), max(sw, se)));
+
+    var dir = vec2<f32>(-((nw + ne) - (sw + se)), (nw + sw) - (ne + se));
+    let reduce = max((nw + ne + sw + se) * 0.25 * FXAA_REDUCE_MUL, FXAA_REDUCE_MIN);
+    let scale = 1.0 / (min(abs(dir.x), abs(dir.y)) + reduce);
+    dir = clamp(dir * scale, vec2<f32>(-FXAA_SPAN), vec2<f32>(FXAA_SPAN)) * texel;
+
+    let a = 0.5 * (fetch(uv + dir * (1.0 / 3.0 - 0.5)) + fetch(uv + dir * (2.0 / 3.0 - 0.5)));
+    let b = a * 0.5 + 0.25 * (fetch(uv - dir * 0.5) + fetch(uv + dir * 0.5));
+
+    // the wider blur reached past the edge, keep the narrow one
+    let luma_b = luma(b);
+    if (luma_b < luma_min || luma_b > luma_max) {
+        return vec4<f32>(a, 1.0);
+    }
+    return vec4<f32>(b, 1.0);
+}
+
+// color grading through the lookup table, see gpu/resources.rs
+@fragment
+fn grade(@builtin(position) pos: vec4<f32>) -> @location(0) vec4<f32> {
+    let size = f32(textureDimensions(lut).x);
+    let color = clamp(fetch(pos.xy / vec2<f32>(textureDimensions(source))), vec3<f32>(0.0), vec3<f32>(1.0));
+    // texel centers, the first and last entry are at the ends of 0..=1
+    let coords = color * ((size - 1.0) / size) + 0.5 / size;
+    return vec4<f32>(textureSampleLevel(lut, linear_sampler, coords, 0.0).rgb, 1.0);
+}
+
+@fragment
+fn vignette(@builtin(position) pos: vec4<f32>) -> @location(0) vec4<f32> {
+    let uv = pos.xy / vec2<f32>(textureDimensions(source));
+    let distance = length(uv - 0.5) * sqrt(2.0);
+    let darkening = 1.0 - VIGNETTE * smoothstep(VIGNETTE_START, 1.0, distance);
+    return vec4<f32>(fetch(uv) * darkening, 1.0);
+}
+
+// how the picture looks without one kind of cone, Machado et al. 2009 at full
+// severity, applied to linear color
+@fragment
+fn color_blind(@builtin(position) pos: vec4<f32>) -> @location(0) vec4<f32> {
+    let color = srgb_to_linear(fetch(pos.xy / vec2<f32>(textureDimensions(source))));
+
+    // rows of the simulation matrix, a row vector times it is the matrix
+    // times the column
+    var simulation: mat3x3<f32>;
+    switch (post.color_blindness) {
+        case PROTANOPIA: {
+            simulation = mat3x3<f32>(
+                vec3<f32>(0.152286, 1.052583, -0.204868),
+                vec3<f32>(0.114503, 0.786281, 0.099216),
+                vec3<f32>(-0.003882, -0.048116, 1.051998),
+            );
+        }
+        case DEUTERANOPIA: {
+            simulation = mat3x3<f32>(
+                vec3<f32>(0.367322, 0.860646, -0.227968),
+                vec3<f32>(0.280085, 0.672501, 0.047413),
+                vec3<f32>(-0.011820, 0.042940, 0.968881),
+            );
+        }
+        case TRITANOPIA: {
+            simulation = mat3x3<f32>(
+                vec3<f32>(1.255528, -0.076749, -0.178779),
+                vec3<f32>(-0.078411, 0.930809, 0.147602),
+                vec3<f32>(0.004733, 0.691367, 0.303900),
+            );
+        }
+        default: {
+            return vec4<f32>(linear_to_srgb(color), 1.0);
+        }
+    }
+
+    let simulated = clamp(color * simulation, vec3<f32>(0.0), vec3<f32>(1.0));
+    return vec4<f32>(linear_to_srgb(simulated), 1.0);
+}
+
+fn fetch(uv: vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(source, linear_sampler, uv, 0.0).rgb;
+}
+
+fn luma(c: vec3<f32>) -> f32 {
+    return dot(c, vec3<f32>(0.299, 0.587, 0.114));
+}
+
+// the part of the color above the bloom threshold
+fn bright(c: vec3<f32>) -> vec3<f32> {
+    let l = luma(c);
+    return c * max(l - BLOOM_THRESHOLD, 0.0) / max(l, 0.0001);
+}
+
+fn srgb_to_linear(c: vec3<f32>) -> vec3<f32> {
+    return select(
+        c / 12.92,
+        pow((c + 0.055) / 1.055, vec3<f32>(2.4)),
+        c > vec3<f32>(0.04045)
+    );
+}
+
+fn linear_to_srgb(c: vec3<f32>) -> vec3<f32> {
+    return select(
+        12.92 * c,
+        1.055 * pow(c, vec3<f32>(1.0 / 2.4)) - 0.055,
+        c > vec3<f32>(0.0031308)
+    );
+}
@@ -5,17 +5,25 @@ use wgpu::{
     BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, BufferBinding,
     BufferBindingType, ColorTargetState, ColorWrites, ComputePipeline, ComputePipelineDescriptor,
     FragmentState, MultisampleState, PipelineCompilationOptions, PipelineLayoutDescriptor,
-    PrimitiveState, RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource,
-    ShaderStages, StorageTextureAccess, SurfaceConfiguration, TextureFormat, VertexState,
+    PrimitiveState, RenderPipeline, RenderPipelineDescriptor, SamplerBindingType,
+    ShaderModuleDescriptor, ShaderSource, ShaderStages, StorageTextureAccess, SurfaceConfiguration,
+    TextureFormat, TextureSampleType, TextureViewDimension, VertexState,
 };
 
 pub struct Pipelines {
     shared_set: SharedSet,
     uniform_set: UniformSet,
     compute_set: ComputeSet,
+    path_set: PathSet,
+    exposure_set: ExposureSet,
     render_set: RenderSet,
+    post_set: PostSet,
 }
 
+// entry points of PostShader.wgsl, indexed by the effect, see PostEffect in
+// core/settings.rs
+const POST_EFFECTS: [&str; 5] = ["bloom", "fxaa", "grade", "vignette", "color_blind"];
+
 pub struct SharedSet {
     layout_compute: BindGroupLayout,
     pub group_compute: BindGroup,
@@ -29,18 +37,38 @@ struct UniformSet {
 }
 struct ComputeSet {
     pipeline: wgpu::ComputePipeline,
-    p_layout: wgpu::PipelineLayout,
 
     bind_group: wgpu::BindGroup,
     bg_layout: wgpu::BindGroupLayout,
 }
 
+// path tracing entry of the compute shader, shares the compute bind group and
+// adds the accumulation texture and its uniform
+struct PathSet {
+    pipeline: wgpu::ComputePipeline,
+
+    bind_group: wgpu::BindGroup,
+}
+
+// auto exposure, a luminance histogram of the shared texture and its average
+struct ExposureSet {
+    histogram: wgpu::ComputePipeline,
+    average: wgpu::ComputePipeline,
+
+    bind_group: wgpu::BindGroup,
+}
+
 struct RenderSet {
     pipeline: wgpu::RenderPipeline,
-    p_layout: wgpu::PipelineLayout,
-    // at this point only using shared resources in here
-    // bind_group: wgpu::BindGroup,
-    // bg_layout: wgpu::BindGroupLayout,
+    // tone curve, exposure and upscaling, the shared texture comes after it
+    bind_group: wgpu::BindGroup,
+}
+
+// one fullscreen pipeline per post effect, and a bind group reading either of
+// the two post textures
+struct PostSet {
+    pipelines: Vec<wgpu::RenderPipeline>,
+    bind_groups: [wgpu::BindGroup; 2],
 }
 
 impl Pipelines {
@@ -52,15 +80,28 @@ impl Pipelines {
         let shared_set = create_shared_set(device, resources);
         let uniform_set = create_uniform_set(device, resources);
         let compute_set = create_compute_pipeline(device, resources, &shared_set, &uniform_set);
+        let path_set =
+            create_path_pipeline(device, resources, &shared_set, &uniform_set, &compute_set);
+        let exposure_set = create_exposure_pipelines(device, resources);
         let render_set = create_render_pipeline(device, resources, surface_conf, &shared_set);
+        let post_set = create_post_pipelines(device, resources, surface_conf);
 
         Self {
             shared_set,
             uniform_set,
             compute_set,
+            path_set,
+            exposure_set,
             render_set,
+            post_set,
         }
     }
+    // node or light buffer was recreated, bind the new ones
+    pub fn rebind_world_buffer(&mut self, device: &wgpu::Device, resources: &Resources) {
+        self.compute_set.bind_group =
+            create_compute_bind_group(device, resources, &self.compute_set.bg_layout);
+    }
+
     pub fn get_compute_pipeline(&self) -> &ComputePipeline {
         &self.compute_set.pipeline
     }
@@ -68,16 +109,39 @@ impl Pipelines {
         &self.compute_set.bind_group
     }
 
+    pub fn get_path_pipeline(&self) -> &ComputePipeline {
+        &self.path_set.pipeline
+    }
+    pub fn get_path_bind_group(&self) -> &BindGroup {
+        &self.path_set.bind_group
+    }
+
+    pub fn get_histogram_pipeline(&self) -> &ComputePipeline {
+        &self.exposure_set.histogram
+    }
+    pub fn get_average_pipeline(&self) -> &ComputePipeline {
+        &self.exposure_set.average
+    }
+    pub fn get_exposure_bind_group(&self) -> &BindGroup {
+        &self.exposure_set.bind_group
+    }
+
     pub fn get_render_pipeline(&self) -> &RenderPipeline {
         &self.render_set.pipeline
     }
 
-    // we do not have render only group
-    /*
     pub fn get_render_bind_group(&self) -> &BindGroup {
         &self.render_set.bind_group
     }
-     */
+
+    pub fn get_post_pipeline(&self, effect: u32) -> &RenderPipeline {
+        &self.post_set.pipelines[effect as usize]
+    }
+
+    // reads the post texture with the given index
+    pub fn get_post_bind_group(&self, source: usize) -> &BindGroup {
+        &self.post_set.bind_groups[source]
+    }
 
     pub fn get_shared_bind_group(&self) -> &SharedSet {
         &self.shared_set
@@ -96,7 +160,7 @@ fn create_shared_set(device: &wgpu::Device, resources: &Resources) -> SharedSet
             visibility: ShaderStages::COMPUTE,
             ty: BindingType::StorageTexture {
                 access: StorageTextureAccess::WriteOnly,
-                format: TextureFormat::Rgba8Unorm,
+                format: TextureFormat::Rgba16Float,
                 view_dimension: wgpu::TextureViewDimension::D2,
             },
             count: None,
@@ -119,7 +183,7 @@ fn create_shared_set(device: &wgpu::Device, resources: &Resources) -> SharedSet
             visibility: ShaderStages::FRAGMENT,
             ty: BindingType::StorageTexture {
                 access: StorageTextureAccess::ReadOnly,
-                format: TextureFormat::Rgba8Unorm,
+                format: TextureFormat::Rgba16Float,
                 view_dimension: wgpu::TextureViewDimension::D2,
             },
             count: None,
@@ -146,25 +210,60 @@ fn create_shared_set(device: &wgpu::Device, resources: &Resources) -> SharedSet
 fn create_uniform_set(device: &wgpu::Device, resources: &Resources) -> UniformSet {
     let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
         label: Some("Uniform layout"),
-        entries: &[BindGroupLayoutEntry {
-            binding: 0,
-            visibility: ShaderStages::COMPUTE,
-            ty: BindingType::Buffer {
-                ty: BufferBindingType::Uniform,
-                has_dynamic_offset: false,
-                min_binding_size: None,
+        entries: &[
+            // camera
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
             },
-            count: None,
-        }],
+            // sun, moon and sky
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // distance fog
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
     });
 
     let group = device.create_bind_group(&BindGroupDescriptor {
         label: Some("Uniform group"),
         layout: &layout,
-        entries: &[BindGroupEntry {
-            binding: 0,
-            resource: resources.view_port().as_entire_binding(),
-        }],
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: resources.view_port().as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: resources.environment().as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: resources.fog().as_entire_binding(),
+            },
+        ],
     });
 
     UniformSet { layout, group }
@@ -200,14 +299,87 @@ fn create_compute_pipeline(
                 },
                 count: None,
             },
+            // light and block id bricks of the leaves (reads)
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // chunk, object and instance transforms (reads)
+            BindGroupLayoutEntry {
+                binding: 3,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // hierarchy over the transformed trees (reads)
+            BindGroupLayoutEntry {
+                binding: 4,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // material per block id (reads)
+            BindGroupLayoutEntry {
+                binding: 5,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
         ],
     });
 
+    let bind_group = create_compute_bind_group(device, resources, &bg_layout);
+
+    let p_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("Compute pipeline layout"),
+        bind_group_layouts: &[&bg_layout, &shared_set.layout_compute, &uniform_set.layout],
+        push_constant_ranges: &[],
+    });
+
+    let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+        label: Some("Compute pipeline"),
+        layout: Some(&p_layout),
+        module: &device.create_shader_module(include_wgsl!("ComputeShader.wgsl")),
+        entry_point: Some("main"),
+        compilation_options: PipelineCompilationOptions::default(),
+        cache: None,
+    });
+
+    ComputeSet {
+        pipeline,
+        bind_group,
+        bg_layout,
+    }
+}
+
+fn create_compute_bind_group(
+    device: &wgpu::Device,
+    resources: &Resources,
+    bg_layout: &BindGroupLayout,
+) -> BindGroup {
     let (header, nodes) = resources.get_world_buffer();
 
-    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+    device.create_bind_group(&BindGroupDescriptor {
         label: Some("Compute bind group"),
-        layout: &bg_layout,
+        layout: bg_layout,
         entries: &[
             BindGroupEntry {
                 binding: 0,
@@ -217,29 +389,191 @@ fn create_compute_pipeline(
                 binding: 1,
                 resource: nodes.as_entire_binding(),
             },
+            BindGroupEntry {
+                binding: 2,
+                resource: resources.get_brick_buffer().as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: resources.get_object_buffer().as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 4,
+                resource: resources.get_bvh_buffer().as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 5,
+                resource: resources.get_material_buffer().as_entire_binding(),
+            },
+        ],
+    })
+}
+
+fn create_path_pipeline(
+    device: &wgpu::Device,
+    resources: &Resources,
+    shared_set: &SharedSet,
+    uniform_set: &UniformSet,
+    compute_set: &ComputeSet,
+) -> PathSet {
+    let bg_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("Path bind group layout"),
+        entries: &[
+            // running average of the samples
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::ReadWrite,
+                    format: TextureFormat::Rgba32Float,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            // sample count and bounces
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    });
+
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+        label: Some("Path bind group"),
+        layout: &bg_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(resources.get_accumulation_view()),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: resources.accumulation().as_entire_binding(),
+            },
         ],
     });
 
     let p_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
-        label: Some("Compute pipeline layout"),
-        bind_group_layouts: &[&bg_layout, &shared_set.layout_compute, &uniform_set.layout],
+        label: Some("Path pipeline layout"),
+        bind_group_layouts: &[
+            &compute_set.bg_layout,
+            &shared_set.layout_compute,
+            &uniform_set.layout,
+            &bg_layout,
+        ],
         push_constant_ranges: &[],
     });
 
     let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
-        label: Some("Compute pipeline"),
+        label: Some("Path pipeline"),
         layout: Some(&p_layout),
         module: &device.create_shader_module(include_wgsl!("ComputeShader.wgsl")),
-        entry_point: Some("main"),
+        entry_point: Some("path_trace"),
         compilation_options: PipelineCompilationOptions::default(),
         cache: None,
     });
 
-    ComputeSet {
+    PathSet {
         pipeline,
-        p_layout,
         bind_group,
-        bg_layout,
+    }
+}
+
+fn create_exposure_pipelines(device: &wgpu::Device, resources: &Resources) -> ExposureSet {
+    let storage = |binding, read_only| BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::Buffer {
+            ty: BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+
+    let bg_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("Exposure bind group layout"),
+        entries: &[
+            // the picture before tonemapping
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::ReadOnly,
+                    format: TextureFormat::Rgba16Float,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            // histogram bins
+            storage(1, false),
+            // adapted luminance
+            storage(2, false),
+            // rendered size
+            BindGroupLayoutEntry {
+                binding: 3,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    });
+
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+        label: Some("Exposure bind group"),
+        layout: &bg_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(resources.get_shared_texture_view()),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: resources.histogram().as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: resources.exposure().as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: resources.upscale().as_entire_binding(),
+            },
+        ],
+    });
+
+    let p_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("Exposure pipeline layout"),
+        bind_group_layouts: &[&bg_layout],
+        push_constant_ranges: &[],
+    });
+
+    let module = device.create_shader_module(include_wgsl!("ExposureShader.wgsl"));
+    let pipeline = |entry_point| {
+        device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Exposure pipeline"),
+            layout: Some(&p_layout),
+            module: &module,
+            entry_point: Some(entry_point),
+            compilation_options: PipelineCompilationOptions::default(),
+            cache: None,
+        })
+    };
+
+    ExposureSet {
+        histogram: pipeline("histogram"),
+        average: pipeline("average"),
+        bind_group,
     }
 }
 
@@ -249,18 +583,39 @@ fn create_render_pipeline(
     surface_config: &SurfaceConfiguration,
     shared_set: &SharedSet,
 ) -> RenderSet {
-    // not needed as we use only the shared texture here
-    /*
-    let bg_laout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+    let bg_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
         label: Some("Render bind group layout"),
         entries: &[
+            // tone curve and exposure
             BindGroupLayoutEntry {
                 binding: 0,
                 visibility: ShaderStages::FRAGMENT,
-                ty: BindingType::StorageTexture {
-                    access: StorageTextureAccess::ReadOnly,
-                    format: TextureFormat::Rgba8Unorm,
-                    view_dimension: wgpu::TextureViewDimension::D2
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // luminance auto exposure adapted to
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // rendered size and upscaling filter
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
                 },
                 count: None,
             },
@@ -269,17 +624,26 @@ fn create_render_pipeline(
 
     let bind_group = device.create_bind_group(&BindGroupDescriptor {
         label: Some("Render bind group"),
-        layout: &bg_laout,
-        entries: &[BindGroupEntry {
-            binding: 0,
-            resource: BindingResource::TextureView(resources.get_output_view()),
-        }],
+        layout: &bg_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: resources.tonemap().as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: resources.exposure().as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: resources.upscale().as_entire_binding(),
+            },
+        ],
     });
-     */
 
     let render_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
         label: Some("Render pipeline layout"),
-        bind_group_layouts: &[&shared_set.layout_render],
+        bind_group_layouts: &[&bg_layout, &shared_set.layout_render],
         push_constant_ranges: &[],
     });
 
@@ -324,8 +688,127 @@ fn create_render_pipeline(
 
     RenderSet {
         pipeline: render_pipeline,
-        p_layout: render_pipeline_layout,
-        // bind_group: bind_group,
-        // bg_layout: bg_laout,
+        bind_group,
+    }
+}
+
+fn create_post_pipelines(
+    device: &wgpu::Device,
+    resources: &Resources,
+    surface_config: &SurfaceConfiguration,
+) -> PostSet {
+    let bg_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("Post bind group layout"),
+        entries: &[
+            // picture left by the pass before
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                count: None,
+            },
+            // color grading table
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D3,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            // effect options
+            BindGroupLayoutEntry {
+                binding: 3,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    });
+
+    let bind_groups = [0, 1].map(|source| {
+        device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Post bind group"),
+            layout: &bg_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(resources.get_post_view(source)),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(resources.get_linear_sampler()),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(resources.get_lut_view()),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: resources.post().as_entire_binding(),
+                },
+            ],
+        })
+    });
+
+    let p_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("Post pipeline layout"),
+        bind_group_layouts: &[&bg_layout],
+        push_constant_ranges: &[],
+    });
+
+    // the fullscreen triangles of the tonemapping pass
+    let vertex_module = device.create_shader_module(include_wgsl!("FragmentShader.wgsl"));
+    let module = device.create_shader_module(include_wgsl!("PostShader.wgsl"));
+    let pipelines = POST_EFFECTS
+        .iter()
+        .map(|entry_point| {
+            device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some("Post pipeline"),
+                layout: Some(&p_layout),
+                vertex: VertexState {
+                    module: &vertex_module,
+                    entry_point: Some("vs_main"),
+                    compilation_options: PipelineCompilationOptions::default(),
+                    buffers: &[],
+                },
+                primitive: PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: MultisampleState::default(),
+                fragment: Some(FragmentState {
+                    module: &module,
+                    entry_point: Some(entry_point),
+                    compilation_options: PipelineCompilationOptions::default(),
+                    targets: &[Some(ColorTargetState {
+                        format: surface_config.format,
+                        blend: None,
+                        write_mask: ColorWrites::ALL,
+                    })],
+                }),
+                multiview: None,
+                cache: None,
+            })
+        })
+        .collect();
+
+    PostSet {
+        pipelines,
+        bind_groups,
     }
 }
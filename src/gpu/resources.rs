@@ -1,21 +1,51 @@
+use wgpu::util::{DeviceExt, TextureDataOrder};
 use wgpu::wgt::SamplerDescriptor;
 use wgpu::{
-    Buffer, BufferDescriptor, BufferUsages, Extent3d, TextureDescriptor, TextureFormat,
-    TextureUsages, TextureView, TextureViewDescriptor,
+    AddressMode, Buffer, BufferDescriptor, BufferUsages, Extent3d, FilterMode, Sampler,
+    TextureDescriptor, TextureFormat, TextureUsages, TextureView, TextureViewDescriptor,
 };
 
 use crate::core::cpu_side_svo::Stager;
-use crate::gpu::types::{self, GpuNode, ViewPort};
+use crate::gpu::types::{
+    self, Accumulation, Environment, Fog, GpuBvhNode, GpuMaterial, GpuNode, GpuObject, Post,
+    Tonemap, Upscale, ViewPort,
+};
+
+// entries along each side of the color grading table
+const LUT_SIZE: u32 = 16;
+
 pub struct Resources {
     shared_texture: wgpu::Texture,
     shared_texture_view: wgpu::TextureView,
 
+    // running average of the path traced samples, the view keeps the texture
+    accumulation_view: wgpu::TextureView,
+    accumulation: wgpu::Buffer,
+
+    // tone curve uniform, luminance histogram bins and the adapted luminance
+    tonemap: wgpu::Buffer,
+    // rendered size inside the shared texture
+    upscale: wgpu::Buffer,
+    histogram: wgpu::Buffer,
+    exposure: wgpu::Buffer,
+
+    // the post effects pass the picture back and forth between two textures
+    // in the frame's format, sampled with the linear sampler
+    post_views: [wgpu::TextureView; 2],
+    linear_sampler: wgpu::Sampler,
+    lut_view: wgpu::TextureView,
+    post: wgpu::Buffer,
+
     scene: types::GpuScene,
     uniform: types::Uniforms,
 }
 
 impl Resources {
-    pub fn new(device: &wgpu::Device, surface_config: &wgpu::SurfaceConfiguration) -> Resources {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        surface_config: &wgpu::SurfaceConfiguration,
+    ) -> Resources {
         let (width, height) = (surface_config.width, surface_config.height);
         let shared_texture = device.create_texture(&TextureDescriptor {
             label: Some("Output texture"),
@@ -27,13 +57,119 @@ impl Resources {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: TextureFormat::Rgba8Unorm,
+            format: TextureFormat::Rgba16Float,
             usage: TextureUsages::STORAGE_BINDING,
             view_formats: &[],
         });
 
         let shared_texture_view = shared_texture.create_view(&TextureViewDescriptor::default());
 
+        let accumulation_texture = device.create_texture(&TextureDescriptor {
+            label: Some("Accumulation texture"),
+            size: Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: TextureFormat::Rgba32Float,
+            usage: TextureUsages::STORAGE_BINDING,
+            view_formats: &[],
+        });
+        let accumulation_view = accumulation_texture.create_view(&TextureViewDescriptor::default());
+
+        let accumulation = device.create_buffer(&BufferDescriptor {
+            label: Some("Accumulation"),
+            size: size_of::<Accumulation>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let tonemap = device.create_buffer(&BufferDescriptor {
+            label: Some("Tonemap"),
+            size: size_of::<Tonemap>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let upscale = device.create_buffer(&BufferDescriptor {
+            label: Some("Upscale"),
+            size: size_of::<Upscale>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let histogram = device.create_buffer(&BufferDescriptor {
+            label: Some("Luminance histogram"),
+            size: 256 * size_of::<u32>() as u64,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let exposure = device.create_buffer(&BufferDescriptor {
+            label: Some("Exposure"),
+            size: 16,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let post_views = ["Post texture A", "Post texture B"].map(|label| {
+            device
+                .create_texture(&TextureDescriptor {
+                    label: Some(label),
+                    size: Extent3d {
+                        width,
+                        height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: surface_config.format,
+                    usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                })
+                .create_view(&TextureViewDescriptor::default())
+        });
+
+        let linear_sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Linear sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let lut_view = device
+            .create_texture_with_data(
+                queue,
+                &TextureDescriptor {
+                    label: Some("Color grading table"),
+                    size: Extent3d {
+                        width: LUT_SIZE,
+                        height: LUT_SIZE,
+                        depth_or_array_layers: LUT_SIZE,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D3,
+                    format: TextureFormat::Rgba8Unorm,
+                    usage: TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                },
+                TextureDataOrder::LayerMajor,
+                &grading_lut(),
+            )
+            .create_view(&TextureViewDescriptor::default());
+
+        let post = device.create_buffer(&BufferDescriptor {
+            label: Some("Post"),
+            size: size_of::<Post>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
         // Sparse voxel octree
         let scene = types::GpuScene::new(device, [8, 8, 8]);
 
@@ -44,6 +180,19 @@ impl Resources {
             shared_texture,
             shared_texture_view,
 
+            accumulation_view,
+            accumulation,
+
+            tonemap,
+            upscale,
+            histogram,
+            exposure,
+
+            post_views,
+            linear_sampler,
+            lut_view,
+            post,
+
             scene,
             uniform,
         }
@@ -51,18 +200,76 @@ impl Resources {
     pub fn get_shared_texture_view(&self) -> &TextureView {
         &self.shared_texture_view
     }
+    pub fn get_accumulation_view(&self) -> &TextureView {
+        &self.accumulation_view
+    }
+    pub fn accumulation(&self) -> &Buffer {
+        &self.accumulation
+    }
+    pub fn tonemap(&self) -> &Buffer {
+        &self.tonemap
+    }
+    pub fn upscale(&self) -> &Buffer {
+        &self.upscale
+    }
+    pub fn histogram(&self) -> &Buffer {
+        &self.histogram
+    }
+    pub fn exposure(&self) -> &Buffer {
+        &self.exposure
+    }
+    pub fn get_post_view(&self, index: usize) -> &TextureView {
+        &self.post_views[index]
+    }
+    pub fn get_linear_sampler(&self) -> &Sampler {
+        &self.linear_sampler
+    }
+    pub fn get_lut_view(&self) -> &TextureView {
+        &self.lut_view
+    }
+    pub fn post(&self) -> &Buffer {
+        &self.post
+    }
     pub fn get_world_buffer(&self) -> (&Buffer, &Buffer) {
         self.scene.get_buffers()
     }
+    pub fn get_brick_buffer(&self) -> &Buffer {
+        self.scene.get_brick_buffer()
+    }
+    pub fn get_object_buffer(&self) -> &Buffer {
+        self.scene.get_object_buffer()
+    }
+    pub fn get_bvh_buffer(&self) -> &Buffer {
+        self.scene.get_bvh_buffer()
+    }
+    pub fn get_material_buffer(&self) -> &Buffer {
+        self.scene.get_material_buffer()
+    }
 
-    pub fn replace_world_buffer(&self, queue: &wgpu::Queue, data: &Stager) {
-        let (header, nodes) = self.get_world_buffer();
-
+    // returns true if the node or brick buffer had to be recreated
+    pub fn replace_world_buffer(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        data: &Stager,
+    ) -> bool {
         let length = bytemuck::cast_slice::<GpuNode, u8>(&data.gpu_nodes).len();
+        let brick_length = bytemuck::cast_slice::<u32, u8>(&data.gpu_bricks).len();
+        let recreated = self
+            .scene
+            .reserve(device, length as u64, brick_length as u64);
+
+        let (header, nodes) = self.get_world_buffer();
         queue.write_buffer(nodes, 0, bytemuck::cast_slice(&data.gpu_nodes));
+        queue.write_buffer(
+            self.get_brick_buffer(),
+            0,
+            bytemuck::cast_slice(&data.gpu_bricks),
+        );
         queue.write_buffer(header, 0, bytemuck::bytes_of(&data.header));
 
         queue.submit([]);
+        recreated
     }
 
     pub fn view_port(&self) -> &Buffer {
@@ -72,4 +279,96 @@ impl Resources {
     pub fn update_view_port(&self, queue: &wgpu::Queue, data: &ViewPort) {
         queue.write_buffer(self.view_port(), 0, bytemuck::bytes_of(data));
     }
+
+    pub fn environment(&self) -> &Buffer {
+        self.uniform.environment()
+    }
+
+    pub fn update_environment(&self, queue: &wgpu::Queue, data: &Environment) {
+        queue.write_buffer(self.environment(), 0, bytemuck::bytes_of(data));
+    }
+
+    pub fn fog(&self) -> &Buffer {
+        self.uniform.fog()
+    }
+
+    pub fn update_fog(&self, queue: &wgpu::Queue, data: &Fog) {
+        queue.write_buffer(self.fog(), 0, bytemuck::bytes_of(data));
+    }
+
+    pub fn update_tonemap(&self, queue: &wgpu::Queue, data: &Tonemap) {
+        queue.write_buffer(self.tonemap(), 0, bytemuck::bytes_of(data));
+    }
+
+    pub fn update_upscale(&self, queue: &wgpu::Queue, data: &Upscale) {
+        queue.write_buffer(self.upscale(), 0, bytemuck::bytes_of(data));
+    }
+
+    pub fn update_post(&self, queue: &wgpu::Queue, data: &Post) {
+        queue.write_buffer(self.post(), 0, bytemuck::bytes_of(data));
+    }
+
+    pub fn update_accumulation(&self, queue: &wgpu::Queue, data: &Accumulation) {
+        queue.write_buffer(self.accumulation(), 0, bytemuck::bytes_of(data));
+    }
+
+    pub fn update_materials(&self, queue: &wgpu::Queue, data: &[GpuMaterial]) {
+        queue.write_buffer(self.get_material_buffer(), 0, bytemuck::cast_slice(data));
+    }
+
+    // rewrites single objects, the buffers keep their size
+    pub fn move_objects(
+        &self,
+        queue: &wgpu::Queue,
+        moved: &[(usize, GpuObject)],
+        bvh: &[GpuBvhNode],
+    ) {
+        for (slot, object) in moved {
+            let offset = (slot * size_of::<GpuObject>()) as u64;
+            queue.write_buffer(self.get_object_buffer(), offset, bytemuck::bytes_of(object));
+        }
+        queue.write_buffer(self.get_bvh_buffer(), 0, bytemuck::cast_slice(bvh));
+    }
+
+    // returns true if the object or hierarchy buffer had to be recreated
+    pub fn update_objects(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        data: &[GpuObject],
+        bvh: &[GpuBvhNode],
+    ) -> bool {
+        let bytes = bytemuck::cast_slice::<GpuObject, u8>(data);
+        let bvh_bytes = bytemuck::cast_slice::<GpuBvhNode, u8>(bvh);
+        let recreated =
+            self.scene
+                .reserve_objects(device, bytes.len() as u64, bvh_bytes.len() as u64);
+        queue.write_buffer(self.get_object_buffer(), 0, bytes);
+        queue.write_buffer(self.get_bvh_buffer(), 0, bvh_bytes);
+        recreated
+    }
+}
+
+// a warm film look, a softer S curve with cool shadows and warm highlights.
+// Red runs along x, green along y and blue along the layers.
+fn grading_lut() -> Vec<u8> {
+    let step = 1.0 / (LUT_SIZE - 1) as f32;
+    let mut data = Vec::with_capacity((LUT_SIZE * LUT_SIZE * LUT_SIZE * 4) as usize);
+    for b in 0..LUT_SIZE {
+        for g in 0..LUT_SIZE {
+            for r in 0..LUT_SIZE {
+                let color = [r, g, b].map(|c| c as f32 * step);
+                let luma = 0.2126 * color[0] + 0.7152 * color[1] + 0.0722 * color[2];
+                let tint = [0.05, 0.01, -0.05].map(|t| t * (luma - 0.5) * 2.0);
+
+                for (c, t) in color.iter().zip(tint) {
+                    let curved = c * c * (3.0 - 2.0 * c);
+                    let graded = c + (curved - c) * 0.4 + t;
+                    data.push((graded.clamp(0.0, 1.0) * 255.0).round() as u8);
+                }
+                data.push(255);
+            }
+        }
+    }
+    data
 }
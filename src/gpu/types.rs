@@ -26,7 +26,20 @@ pub struct GpuNode {
     pub color_index: u32,
 }
 
+// color_index flag: node is filled all the way down, mask and base are unused.
+// Chunks cut at a lower LOD end in these nodes.
+pub const SOLID_FLAG: u32 = 1 << 31;
+
 impl GpuNode {
+    pub fn set_solid(color_index: u32) -> Self {
+        Self {
+            mask_h: !0,
+            mask_l: !0,
+            base: 0,
+            color_index: color_index | SOLID_FLAG,
+        }
+    }
+
     pub fn set_leaf(mask: u64, color_index: u32) -> Self {
         Self {
             mask_h: (mask >> 32) as u32,
@@ -40,14 +53,145 @@ impl GpuNode {
 #[repr(C)]
 #[derive(Default, Clone, Copy, Pod, Zeroable)]
 pub struct GpuSceneHeader {
-    pub start: [i32; 4],
-    pub end: [i32; 4],
     pub size: u32,
+    // uniform structs are padded to 16 bytes
+    pub _padding: [u32; 3],
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy, Pod, Zeroable)]
+pub struct GpuObject {
+    // rows of the world -> object space transform, w is the translation
+    pub m0: [f32; 4],
+    pub m1: [f32; 4],
+    pub m2: [f32; 4],
+    // voxels per axis, w is unused
+    pub size: [f32; 4],
+    // x is the root node index, the rest is padding
+    pub root: [u32; 4],
+}
+
+// Node of the hierarchy over all GpuObjects. A leaf holds `count` objects
+// from `first` on, an inner node has its two children at `first` and
+// `first + 1`. `first == 0` with no count is the empty tree.
+#[repr(C)]
+#[derive(Default, Clone, Copy, Pod, Zeroable)]
+pub struct GpuBvhNode {
+    pub low: [f32; 3],
+    pub first: u32,
+    pub high: [f32; 3],
+    pub count: u32,
+}
+
+use crate::core::instances::{Instance, Model};
+use crate::core::objects::VoxelObject;
+use crate::core::types::CHUNK_SIZE;
+use nalgebra::{UnitQuaternion, Vector3};
+impl GpuObject {
+    pub fn new(object: &VoxelObject, root: u32) -> Self {
+        let (rotation, position) = (object.rotation, object.position);
+        Self::transformed(rotation, position, object.center, object.size, root)
+    }
+
+    // chunks sit in the world unturned
+    pub fn chunk(coords: (i32, i32, i32), root: u32) -> Self {
+        let origin = Vector3::new(coords.0, coords.1, coords.2).cast::<f32>() * CHUNK_SIZE as f32;
+        let size = (CHUNK_SIZE, CHUNK_SIZE, CHUNK_SIZE);
+        Self::transformed(
+            UnitQuaternion::identity(),
+            origin,
+            Vector3::zeros(),
+            size,
+            root,
+        )
+    }
+
+    // `root` is the shared root of the instance's model
+    pub fn instance(instance: &Instance, model: &Model, root: u32) -> Self {
+        let (rotation, position) = (instance.rotation, instance.position);
+        Self::transformed(rotation, position, model.center(), model.size, root)
+    }
+
+    fn transformed(
+        rotation: UnitQuaternion<f32>,
+        position: Vector3<f32>,
+        center: Vector3<f32>,
+        (x, y, z): (i32, i32, i32),
+        root: u32,
+    ) -> Self {
+        // local = R^-1 * (world - position) + center
+        let inverse = rotation.inverse().to_rotation_matrix();
+        let m = inverse.matrix();
+        let t = center - m * position;
+
+        Self {
+            m0: [m[(0, 0)], m[(0, 1)], m[(0, 2)], t.x],
+            m1: [m[(1, 0)], m[(1, 1)], m[(1, 2)], t.y],
+            m2: [m[(2, 0)], m[(2, 1)], m[(2, 2)], t.z],
+            size: [x as f32, y as f32, z as f32, 0.0],
+            root: [root, 0, 0, 0],
+        }
+    }
+
+    // world space box around the object, corners of its own box turned back
+    pub fn bounds(&self) -> ([f32; 3], [f32; 3]) {
+        let rows = [self.m0, self.m1, self.m2];
+        let mut low = [f32::MAX; 3];
+        let mut high = [f32::MIN; 3];
+
+        for corner in 0..8 {
+            let local = [0, 1, 2].map(|i| {
+                let size = if corner & (1 << i) != 0 {
+                    self.size[i]
+                } else {
+                    0.0
+                };
+                size - rows[i][3]
+            });
+            // the rotation rows are orthonormal, world = R^T * (local - t)
+            for axis in 0..3 {
+                let world: f32 = (0..3).map(|i| rows[i][axis] * local[i]).sum();
+                low[axis] = low[axis].min(world);
+                high[axis] = high[axis].max(world);
+            }
+        }
+        (low, high)
+    }
+}
+
+// core::materials::Material as the shader reads it, one per block id
+#[repr(C)]
+#[derive(Default, Clone, Copy, Pod, Zeroable)]
+pub struct GpuMaterial {
+    color: [f32; 3],
+    roughness: f32,
+    metalness: f32,
+    transparency: f32,
+    ior: f32,
+    emission: f32,
+}
+
+use crate::core::materials::Material;
+impl GpuMaterial {
+    pub fn new(material: &Material) -> Self {
+        Self {
+            color: material.color,
+            roughness: material.roughness,
+            metalness: material.metalness,
+            transparency: material.transparency,
+            ior: material.ior,
+            emission: material.emission,
+        }
+    }
 }
 
 pub struct GpuScene {
     header: wgpu::Buffer,
-    nodes: wgpu::Buffer, // <GpuNode>
+    nodes: wgpu::Buffer,     // <GpuNode>
+    bricks: wgpu::Buffer,    // 32 u32 per leaf, see cpu_side_svo.rs
+    objects: wgpu::Buffer,   // <GpuObject>
+    bvh: wgpu::Buffer,       // <GpuBvhNode>
+    materials: wgpu::Buffer, // <GpuMaterial>, indexed by block id
 }
 
 impl GpuScene {
@@ -59,19 +203,79 @@ impl GpuScene {
             mapped_at_creation: false,
         });
 
-        let nodes = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Nodes"),
-            size: 131_072,
-            usage: BufferUsages::COPY_DST | BufferUsages::STORAGE | BufferUsages::COPY_SRC,
-            mapped_at_creation: false,
-        });
+        let nodes = create_storage_buffer(device, "Nodes", 131_072);
+        let bricks = create_storage_buffer(device, "Bricks", 65_536);
+        let objects = create_storage_buffer(device, "Objects", 65_536);
+        let bvh = create_storage_buffer(device, "Bvh", 65_536);
+        let materials =
+            create_storage_buffer(device, "Materials", 256 * size_of::<GpuMaterial>() as u64);
+
+        Self {
+            header,
+            nodes,
+            bricks,
+            objects,
+            bvh,
+            materials,
+        }
+    }
+
+    // grows the node and brick buffers, returns true if one was recreated
+    pub fn reserve(&mut self, device: &wgpu::Device, node_bytes: u64, brick_bytes: u64) -> bool {
+        let mut recreated = false;
+        if node_bytes > self.nodes.size() {
+            self.nodes = create_storage_buffer(device, "Nodes", node_bytes.next_power_of_two());
+            recreated = true;
+        }
+        if brick_bytes > self.bricks.size() {
+            self.bricks = create_storage_buffer(device, "Bricks", brick_bytes.next_power_of_two());
+            recreated = true;
+        }
+        recreated
+    }
 
-        Self { header, nodes }
+    // grows the object and hierarchy buffers, returns true if one was recreated
+    pub fn reserve_objects(&mut self, device: &wgpu::Device, bytes: u64, bvh_bytes: u64) -> bool {
+        let mut recreated = false;
+        if bytes > self.objects.size() {
+            self.objects = create_storage_buffer(device, "Objects", bytes.next_power_of_two());
+            recreated = true;
+        }
+        if bvh_bytes > self.bvh.size() {
+            self.bvh = create_storage_buffer(device, "Bvh", bvh_bytes.next_power_of_two());
+            recreated = true;
+        }
+        recreated
     }
 
     pub fn get_buffers(&self) -> (&Buffer, &Buffer) {
         (&self.header, &self.nodes)
     }
+
+    pub fn get_brick_buffer(&self) -> &Buffer {
+        &self.bricks
+    }
+
+    pub fn get_object_buffer(&self) -> &Buffer {
+        &self.objects
+    }
+
+    pub fn get_bvh_buffer(&self) -> &Buffer {
+        &self.bvh
+    }
+
+    pub fn get_material_buffer(&self) -> &Buffer {
+        &self.materials
+    }
+}
+
+fn create_storage_buffer(device: &wgpu::Device, label: &str, size: u64) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size,
+        usage: BufferUsages::COPY_DST | BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    })
 }
 
 #[repr(C)]
@@ -92,7 +296,7 @@ pub struct ViewPort {
 use crate::core::types::Camera;
 use winit::dpi::PhysicalSize;
 impl ViewPort {
-    pub fn new(cam: &Camera, size: PhysicalSize<u32>, fov: f64) -> Self {
+    pub fn new(cam: &Camera, size: PhysicalSize<u32>, fov: f64, far: f32) -> Self {
         let (pos, dir, up, right) = cam.get_raw();
 
         Self {
@@ -100,16 +304,176 @@ impl ViewPort {
             dir,
             up,
             right,
-            far: 64.0,
+            far,
             fov: fov as f32,
             screen_x: size.width as f32,
             screen_y: size.height as f32,
         }
     }
+
+    // the same camera rendering at a different resolution
+    pub fn with_screen(mut self, (width, height): (u32, u32)) -> Self {
+        self.screen_x = width as f32;
+        self.screen_y = height as f32;
+        self
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+pub struct Environment {
+    // w is how strong the light from it is, 0..=1
+    sun_dir: [f32; 4],
+    moon_dir: [f32; 4],
+    // w is unused
+    sky_color: [f32; 4],
+    sun_color: [f32; 4],
+    // direction surfaces are lit from, w is the ambient share 0..=1
+    light_dir: [f32; 4],
+    // x is how far shadow rays go, 0 turns shadows off
+    shadow: [f32; 4],
+    // x is the ambient occlusion mode, 0 off, 1 face corners, 2 rays
+    occlusion: [u32; 4],
+    // x is how many mirror and refraction rays follow the first hit
+    bounces: [u32; 4],
+}
+
+use crate::core::clock::Clock;
+impl Environment {
+    pub fn new(
+        clock: &Clock,
+        light_dir: [f32; 3],
+        ambient: f32,
+        shadow_distance: f32,
+        occlusion: u32,
+        bounces: u32,
+    ) -> Self {
+        let [sx, sy, sz] = clock.sun_direction();
+        let [mx, my, mz] = clock.moon_direction();
+        let [r, g, b] = clock.sky_color();
+        let [sr, sg, sb] = clock.sun_color();
+
+        Self {
+            sun_dir: [sx, sy, sz, clock.daylight()],
+            moon_dir: [mx, my, mz, clock.moonlight()],
+            sky_color: [r, g, b, 0.0],
+            sun_color: [sr, sg, sb, 0.0],
+            light_dir: [light_dir[0], light_dir[1], light_dir[2], ambient],
+            shadow: [shadow_distance, 0.0, 0.0, 0.0],
+            occlusion: [occlusion, 0, 0, 0],
+            bounces: [bounces, 0, 0, 0],
+        }
+    }
+}
+
+// distance fog towards the sky, in voxels along the camera ray
+#[repr(C)]
+#[derive(Default, Clone, Copy, Pod, Zeroable)]
+pub struct Fog {
+    // fully fogged at end, end <= start turns the fog off
+    start: f32,
+    end: f32,
+    // haze thickening with distance before start
+    density: f32,
+    _padding: f32,
+}
+
+impl Fog {
+    // `start` as a share of `far`, None is no fog
+    pub fn new(far: f32, start: Option<f32>, density: f32) -> Self {
+        match start {
+            Some(start) => Self {
+                start: far * start,
+                end: far,
+                density,
+                ..Default::default()
+            },
+            None => Self::default(),
+        }
+    }
+}
+
+// exposure and tone curve of the fullscreen pass
+#[repr(C)]
+#[derive(Default, Clone, Copy, Pod, Zeroable)]
+pub struct Tonemap {
+    // 0 Reinhard, 1 ACES
+    curve: u32,
+    // linear scale, on top of auto exposure if that is on
+    exposure: f32,
+    auto_exposure: u32,
+    _padding: u32,
+}
+
+impl Tonemap {
+    // `exposure` in stops
+    pub fn new(curve: u32, exposure: f32, auto_exposure: bool) -> Self {
+        Self {
+            curve,
+            exposure: exposure.exp2(),
+            auto_exposure: auto_exposure as u32,
+            ..Default::default()
+        }
+    }
+
+    pub fn auto_exposure(&self) -> bool {
+        self.auto_exposure != 0
+    }
+}
+
+// options of the post effects, see PostShader.wgsl
+#[repr(C)]
+#[derive(Default, Clone, Copy, Pod, Zeroable)]
+pub struct Post {
+    // 0 protanopia, 1 deuteranopia, 2 tritanopia
+    color_blindness: u32,
+    _padding: [u32; 3],
+}
+
+impl Post {
+    pub fn new(color_blindness: u32) -> Self {
+        Self {
+            color_blindness,
+            ..Default::default()
+        }
+    }
+}
+
+// size the compute pass renders at and how the fullscreen pass scales it up
+// to the frame, see FragmentShader.wgsl
+#[repr(C)]
+#[derive(Default, Clone, Copy, Pod, Zeroable)]
+pub struct Upscale {
+    size: [u32; 2],
+    // filter, 0 bilinear, 1 edge aware
+    mode: u32,
+    _padding: u32,
+}
+
+impl Upscale {
+    pub fn new((width, height): (u32, u32), mode: u32) -> Self {
+        Self {
+            size: [width, height],
+            mode,
+            ..Default::default()
+        }
+    }
+}
+
+// progressive path tracing state, written every traced frame
+#[repr(C)]
+#[derive(Default, Clone, Copy, Pod, Zeroable)]
+pub struct Accumulation {
+    // samples already averaged in the accumulation texture, 0 starts over
+    pub samples: u32,
+    pub bounces: u32,
+    pub _padding: [u32; 2],
 }
 
 pub struct Uniforms {
     view_port: wgpu::Buffer,
+    environment: wgpu::Buffer,
+    fog: wgpu::Buffer,
 }
 
 impl Uniforms {
@@ -121,10 +485,36 @@ impl Uniforms {
             mapped_at_creation: false,
         });
 
-        Self { view_port: camera }
+        let environment = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Uniform environment"),
+            size: size_of::<Environment>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let fog = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Uniform fog"),
+            size: size_of::<Fog>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            view_port: camera,
+            environment,
+            fog,
+        }
     }
 
     pub fn view_port(&self) -> &wgpu::Buffer {
         &self.view_port
     }
+
+    pub fn environment(&self) -> &wgpu::Buffer {
+        &self.environment
+    }
+
+    pub fn fog(&self) -> &wgpu::Buffer {
+        &self.fog
+    }
 }
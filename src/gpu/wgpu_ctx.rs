@@ -11,25 +11,60 @@ use winit::window::Window;
 
 use wgpu::{
     CommandEncoder, CommandEncoderDescriptor, ComputePassDescriptor, MemoryHints::Performance,
-    Operations, RenderPassColorAttachment, RenderPassDescriptor, SurfaceTexture,
-    TextureViewDescriptor,
+    Operations, RenderPass, RenderPassColorAttachment, RenderPassDescriptor, SurfaceTexture,
+    TextureView, TextureViewDescriptor,
 };
 
 use crate::app::egui::Egui;
 use crate::core::cpu_side_svo::Stager;
-use crate::gpu::types::ViewPort;
+use crate::gpu::types::{
+    Accumulation, Environment, Fog, GpuBvhNode, GpuMaterial, GpuObject, Post, Tonemap, Upscale,
+    ViewPort,
+};
 use crate::gpu::{pipelines::Pipelines, resources::Resources, types::GpuNode};
+
+// lowest share of the window's width and height the compute pass renders
+const MIN_RENDER_SCALE: f32 = 0.25;
+
 pub struct WgpuCtx<'window> {
     surface: wgpu::Surface<'window>,
     surface_config: wgpu::SurfaceConfiguration,
-    adapter: wgpu::Adapter,
     device: wgpu::Device,
     queue: wgpu::Queue,
 
     pipelines: Pipelines,
     resources: Resources,
+
+    // bounces while path tracing, None draws the shaded compute pass
+    path_bounces: Option<u32>,
+    // samples in the accumulation texture, any new camera, scene, light or
+    // fog clears it
+    samples: u32,
+    // last uploaded, to tell when they really changed
+    environment: Environment,
+    fog: Fog,
+    tonemap: Tonemap,
+    post: Post,
+    // measure the picture's luminance every frame
+    auto_exposure: bool,
+    // post effects drawn after tonemapping, in order
+    post_effects: Vec<u32>,
+    // share of the window's width and height the compute pass renders
+    render_scale: f32,
+    // milliseconds the compute passes should take on the GPU, None keeps the
+    // scale fixed
+    target_frame_time: Option<f32>,
+    upscale_filter: u32,
+    // last camera, sent again at the new size when the render scale changes
+    view_port: Option<ViewPort>,
+    // GPU timestamps between the passes, resolved and read back every frame
+    timestamps: wgpu::QuerySet,
+    timestamp_resolve: wgpu::Buffer,
+    timestamp_readback: wgpu::Buffer,
 }
 
+const TIMESTAMPS: u32 = 4;
+
 impl<'window> WgpuCtx<'window> {
     pub fn new(window: Arc<Window>) -> WgpuCtx<'window> {
         pollster::block_on(WgpuCtx::new_async(window))
@@ -71,31 +106,205 @@ impl<'window> WgpuCtx<'window> {
         surface_config.present_mode = wgpu::PresentMode::Fifo;
         surface.configure(&device, &surface_config);
 
-        let resources = Resources::new(&device, &surface_config);
+        let resources = Resources::new(&device, &queue, &surface_config);
         let pipelines = Pipelines::new(&device, &resources, &surface_config);
 
-        Self {
+        let timestamps = device.create_query_set(&QuerySetDescriptor {
+            label: Some("Time query set"),
+            ty: QueryType::Timestamp,
+            count: TIMESTAMPS,
+        });
+        let timestamp_resolve = device.create_buffer(&BufferDescriptor {
+            label: Some("Query Resolve Buffer"),
+            size: size_of::<u64>() as u64 * TIMESTAMPS as u64,
+            usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let timestamp_readback = device.create_buffer(&BufferDescriptor {
+            label: Some("Readback buffer"),
+            size: size_of::<u64>() as u64 * TIMESTAMPS as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let ctx = Self {
             surface,
             surface_config,
-            adapter,
             device,
             queue,
 
             resources,
             pipelines,
-        }
+
+            path_bounces: None,
+            samples: 0,
+            environment: Environment::zeroed(),
+            fog: Fog::default(),
+            tonemap: Tonemap::default(),
+            post: Post::default(),
+            auto_exposure: false,
+            post_effects: Vec::new(),
+            render_scale: 1.0,
+            target_frame_time: None,
+            upscale_filter: 0,
+            view_port: None,
+            timestamps,
+            timestamp_resolve,
+            timestamp_readback,
+        };
+        ctx.update_upscale();
+        ctx
     }
 
     pub fn device(&self) -> &Device {
         &self.device
     }
 
-    pub fn replace_world_buffer(&self, data: &Stager) {
-        self.resources.replace_world_buffer(&self.queue, data);
+    pub fn replace_world_buffer(&mut self, data: &Stager) {
+        self.samples = 0;
+        if self
+            .resources
+            .replace_world_buffer(&self.device, &self.queue, data)
+        {
+            self.pipelines
+                .rebind_world_buffer(&self.device, &self.resources);
+        }
+    }
+
+    pub fn update_view_port(&mut self, data: &ViewPort) {
+        self.samples = 0;
+        self.view_port = Some(*data);
+        let data = data.with_screen(self.render_size());
+        self.resources.update_view_port(&self.queue, &data);
+    }
+
+    pub fn update_environment(&mut self, data: &Environment) {
+        if bytemuck::bytes_of(data) == bytemuck::bytes_of(&self.environment) {
+            return;
+        }
+        self.samples = 0;
+        self.environment = *data;
+        self.resources.update_environment(&self.queue, data);
+    }
+
+    pub fn update_fog(&mut self, data: &Fog) {
+        if bytemuck::bytes_of(data) == bytemuck::bytes_of(&self.fog) {
+            return;
+        }
+        self.samples = 0;
+        self.fog = *data;
+        self.resources.update_fog(&self.queue, data);
+    }
+
+    pub fn update_tonemap(&mut self, data: &Tonemap) {
+        if bytemuck::bytes_of(data) == bytemuck::bytes_of(&self.tonemap) {
+            return;
+        }
+        self.auto_exposure = data.auto_exposure();
+        self.tonemap = *data;
+        self.resources.update_tonemap(&self.queue, data);
+    }
+
+    pub fn update_post(&mut self, data: &Post) {
+        if bytemuck::bytes_of(data) == bytemuck::bytes_of(&self.post) {
+            return;
+        }
+        self.post = *data;
+        self.resources.update_post(&self.queue, data);
+    }
+
+    // pipeline of every effect, see PostEffect in core/settings.rs
+    pub fn set_post_effects(&mut self, effects: &[u32]) {
+        if self.post_effects != effects {
+            self.post_effects = effects.to_vec();
+        }
+    }
+
+    pub fn update_materials(&mut self, data: &[GpuMaterial]) {
+        self.samples = 0;
+        self.resources.update_materials(&self.queue, data);
+    }
+
+    pub fn update_objects(&mut self, data: &[GpuObject], bvh: &[GpuBvhNode]) {
+        self.samples = 0;
+        if self
+            .resources
+            .update_objects(&self.device, &self.queue, data, bvh)
+        {
+            self.pipelines
+                .rebind_world_buffer(&self.device, &self.resources);
+        }
+    }
+
+    // objects that moved, by their position in the object buffer, and the
+    // refitted hierarchy of the same shape
+    pub fn move_objects(&mut self, moved: &[(usize, GpuObject)], bvh: &[GpuBvhNode]) {
+        self.samples = 0;
+        self.resources.move_objects(&self.queue, moved, bvh);
+    }
+
+    pub fn set_path_tracing(&mut self, bounces: Option<u32>) {
+        if self.path_bounces != bounces {
+            self.path_bounces = bounces;
+            self.samples = 0;
+        }
+    }
+
+    // a fixed render scale, or the frame time in milliseconds the scale
+    // adapts to
+    pub fn set_resolution(&mut self, scale: f32, target_frame_time: Option<f32>, filter: u32) {
+        self.target_frame_time = target_frame_time;
+        if target_frame_time.is_none() {
+            self.set_render_scale(scale);
+        }
+        if self.upscale_filter != filter {
+            self.upscale_filter = filter;
+            self.update_upscale();
+        }
+    }
+
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    fn set_render_scale(&mut self, scale: f32) {
+        let scale = scale.clamp(MIN_RENDER_SCALE, 1.0);
+        if self.render_scale == scale {
+            return;
+        }
+        self.render_scale = scale;
+        self.update_upscale();
+        if let Some(view_port) = self.view_port {
+            self.update_view_port(&view_port);
+        }
+    }
+
+    // the time goes with the pixel count, the square of the scale. Close
+    // misses are left alone, every change starts path tracing over.
+    fn adapt_render_scale(&mut self, frame_time: f32) {
+        let Some(target) = self.target_frame_time else {
+            return;
+        };
+        let ratio = target / frame_time.max(0.01);
+        if (0.9..=1.1).contains(&ratio) {
+            return;
+        }
+        let scale = self.render_scale * ratio.sqrt().clamp(0.9, 1.1);
+        // in steps of 1/64 so it settles
+        self.set_render_scale((scale * 64.0).round() / 64.0);
+    }
+
+    fn render_size(&self) -> (u32, u32) {
+        let scaled = |side: u32| ((side as f32 * self.render_scale).round() as u32).clamp(1, side);
+        (
+            scaled(self.surface_config.width),
+            scaled(self.surface_config.height),
+        )
     }
 
-    pub fn update_view_port(&self, data: &ViewPort) {
-        self.resources.update_view_port(&self.queue, data);
+    fn update_upscale(&self) {
+        let upscale = Upscale::new(self.render_size(), self.upscale_filter);
+        self.resources.update_upscale(&self.queue, &upscale);
     }
 
     pub fn draw(&mut self, egui: &mut Egui, output: FullOutput) {
@@ -121,39 +330,31 @@ impl<'window> WgpuCtx<'window> {
             label: Some("Compute encoder"),
         });
 
-        let query_set = self.device.create_query_set(&QuerySetDescriptor {
-            label: Some("Time query set"),
-            ty: QueryType::Timestamp,
-            count: 4,
-        });
-
-        encoder.write_timestamp(&query_set, 0);
-        self.encode_compute_pass(&mut encoder);
+        encoder.write_timestamp(&self.timestamps, 0);
+        match self.path_bounces {
+            Some(bounces) => self.encode_path_pass(&mut encoder, bounces),
+            None => self.encode_compute_pass(&mut encoder),
+        }
+        if self.auto_exposure {
+            self.encode_exposure_pass(&mut encoder);
+        }
 
-        encoder.write_timestamp(&query_set, 1);
+        encoder.write_timestamp(&self.timestamps, 1);
         self.encode_render_pass(&mut encoder, &frame);
 
-        encoder.write_timestamp(&query_set, 2);
+        encoder.write_timestamp(&self.timestamps, 2);
         self.encode_egui_pass(&mut encoder, egui, output, &frame);
 
-        encoder.write_timestamp(&query_set, 3);
+        encoder.write_timestamp(&self.timestamps, 3);
 
-        let query_resolve_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Query Resolve Buffer"),
-            size: size_of::<u64>() as u64 * 4,
-            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
-            mapped_at_creation: false,
-        });
-
-        let readback_buffer = self.device.create_buffer(&BufferDescriptor
-            { label: Some("Readback buffer"),
-            size: size_of::<u64>() as u64 * 4,
-            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
-            mapped_at_creation: false,
-        });
-
-        encoder.resolve_query_set(&query_set, 0..4, &query_resolve_buffer, 0);
-        encoder.copy_buffer_to_buffer(&query_resolve_buffer, 0, &readback_buffer, 0, size_of::<u64>() as u64 * 4);
+        encoder.resolve_query_set(&self.timestamps, 0..TIMESTAMPS, &self.timestamp_resolve, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.timestamp_resolve,
+            0,
+            &self.timestamp_readback,
+            0,
+            self.timestamp_readback.size(),
+        );
 /*
         let (_, nodes) = self.resources.get_world_buffer();
         let read_back_voxel_buffer = self.device.create_buffer(&BufferDescriptor {
@@ -170,18 +371,25 @@ impl<'window> WgpuCtx<'window> {
 
         let _ = self.device.poll(wgpu::MaintainBase::Wait);
 
-        let buffer_slice = readback_buffer.slice(..);
+        let buffer_slice = self.timestamp_readback.slice(..);
         buffer_slice.map_async(wgpu::MapMode::Read, |_| {});
         let _ = self.device.poll(wgpu::MaintainBase::Wait);
 
         let data = buffer_slice.get_mapped_range();
-        let stamps: &[u64] = bytemuck::cast_slice(&data);
+        let stamps: Vec<u64> = bytemuck::cast_slice(&data).to_vec();
+        drop(data);
+        self.timestamp_readback.unmap();
 
         let period = self.queue.get_timestamp_period();
         println!("Compute pass duration: {}", (stamps[1] - stamps[0]) as f64 * period as f64);
         println!("Render pass duration: {}", (stamps[2] - stamps[1]) as f64 * period as f64);
         println!("Gui pass duration: {}", (stamps[3] - stamps[2]) as f64 * period as f64);
 
+        // only the ray cast passes get cheaper at a lower scale, timestamps
+        // are in nanoseconds
+        let compute_time = (stamps[1] - stamps[0]) as f64 * period as f64 / 1_000_000.0;
+        self.adapt_render_scale(compute_time as f32);
+
         /*
         let buffer_slice = read_back_voxel_buffer.slice(..);
         buffer_slice.map_async(wgpu::MapMode::Read, |_| {});
@@ -204,42 +412,88 @@ impl<'window> WgpuCtx<'window> {
             timestamp_writes: None,
         });
 
-        let window_size = (self.surface_config.width, self.surface_config.height);
+        let render_size = self.render_size();
         let shared_set = self.pipelines.get_shared_bind_group();
 
         compute_pass.set_pipeline(self.pipelines.get_compute_pipeline());
         compute_pass.set_bind_group(0, self.pipelines.get_compute_bind_group(), &[]);
         compute_pass.set_bind_group(1, &shared_set.group_compute, &[]);
         compute_pass.set_bind_group(2, self.pipelines.get_uniform_bind_group(), &[]);
-        compute_pass.dispatch_workgroups((window_size.0 + 7) / 8, (window_size.1 + 7) / 8, 1);
+        compute_pass.dispatch_workgroups(render_size.0.div_ceil(8), render_size.1.div_ceil(8), 1);
     }
 
-    fn encode_render_pass(&self, encoder: &mut CommandEncoder, frame: &SurfaceTexture) {
-        let view = frame.texture.create_view(&TextureViewDescriptor::default());
+    // one more sample averaged into the accumulation texture
+    fn encode_path_pass(&mut self, encoder: &mut CommandEncoder, bounces: u32) {
+        self.resources.update_accumulation(
+            &self.queue,
+            &Accumulation {
+                samples: self.samples,
+                bounces,
+                ..Default::default()
+            },
+        );
+        self.samples += 1;
+
+        let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("Path pass"),
+            timestamp_writes: None,
+        });
+
+        let render_size = self.render_size();
         let shared_set = self.pipelines.get_shared_bind_group();
 
-        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
-            label: Some("Render Pass"),
-            color_attachments: &[Some(RenderPassColorAttachment {
-                view: &view,
-                resolve_target: None,
-                ops: Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                    store: wgpu::StoreOp::Store,
-                },
-            })],
-            depth_stencil_attachment: None,
+        compute_pass.set_pipeline(self.pipelines.get_path_pipeline());
+        compute_pass.set_bind_group(0, self.pipelines.get_compute_bind_group(), &[]);
+        compute_pass.set_bind_group(1, &shared_set.group_compute, &[]);
+        compute_pass.set_bind_group(2, self.pipelines.get_uniform_bind_group(), &[]);
+        compute_pass.set_bind_group(3, self.pipelines.get_path_bind_group(), &[]);
+        compute_pass.dispatch_workgroups(render_size.0.div_ceil(8), render_size.1.div_ceil(8), 1);
+    }
+
+    // luminance histogram of the finished picture, then its average
+    fn encode_exposure_pass(&self, encoder: &mut CommandEncoder) {
+        let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("Exposure pass"),
             timestamp_writes: None,
-            occlusion_query_set: None,
         });
 
+        let render_size = self.render_size();
+
+        compute_pass.set_bind_group(0, self.pipelines.get_exposure_bind_group(), &[]);
+        compute_pass.set_pipeline(self.pipelines.get_histogram_pipeline());
+        compute_pass.dispatch_workgroups(render_size.0.div_ceil(16), render_size.1.div_ceil(16), 1);
+        compute_pass.set_pipeline(self.pipelines.get_average_pipeline());
+        compute_pass.dispatch_workgroups(1, 1, 1);
+    }
+
+    // tonemapping, then every post effect reading what the pass before left,
+    // the last pass draws to the frame
+    fn encode_render_pass(&self, encoder: &mut CommandEncoder, frame: &SurfaceTexture) {
+        let view = frame.texture.create_view(&TextureViewDescriptor::default());
+        let shared_set = self.pipelines.get_shared_bind_group();
+        let target = |pass: usize| {
+            if pass == self.post_effects.len() {
+                &view
+            } else {
+                self.resources.get_post_view(pass % 2)
+            }
+        };
+
+        let mut render_pass = begin_fullscreen_pass(encoder, "Render Pass", target(0));
         render_pass.set_pipeline(self.pipelines.get_render_pipeline());
 
-        // do not have only render group, if yes shared index should be 1,
-        //          render_pass.set_bind_group(0, self.pipelines.get_render_bind_group(), &[]);
-        render_pass.set_bind_group(0, &shared_set.group_render, &[]);
+        render_pass.set_bind_group(0, self.pipelines.get_render_bind_group(), &[]);
+        render_pass.set_bind_group(1, &shared_set.group_render, &[]);
 
         render_pass.draw(0..6, 0..1);
+        drop(render_pass);
+
+        for (pass, effect) in self.post_effects.iter().enumerate() {
+            let mut render_pass = begin_fullscreen_pass(encoder, "Post pass", target(pass + 1));
+            render_pass.set_pipeline(self.pipelines.get_post_pipeline(*effect));
+            render_pass.set_bind_group(0, self.pipelines.get_post_bind_group(pass % 2), &[]);
+            render_pass.draw(0..6, 0..1);
+        }
     }
 
     fn encode_egui_pass(
@@ -300,6 +554,27 @@ impl<'window> WgpuCtx<'window> {
     }
 }
 
+fn begin_fullscreen_pass<'a>(
+    encoder: &'a mut CommandEncoder,
+    label: &str,
+    view: &TextureView,
+) -> RenderPass<'a> {
+    encoder.begin_render_pass(&RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        timestamp_writes: None,
+        occlusion_query_set: None,
+    })
+}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, Zeroable, Pod)]
 struct GpuNode2 {
@@ -3,13 +3,19 @@ mod core;
 mod gpu;
 mod util;
 
-use app::App;
+use app::{region, world::World, App};
 use util::timer::Time;
 use winit::event_loop::{ControlFlow, EventLoop};
 
 pub const UPDATE_PER_SECOND: u32 = 60;
 
 fn main() -> Result<(), winit::error::EventLoopError> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() > 1 {
+        run_tool(&args[1..]);
+        return Ok(());
+    }
+
     let mut app: App<'_, Time> = App::new();
 
     let event_loop = EventLoop::new().unwrap();
@@ -17,3 +23,29 @@ fn main() -> Result<(), winit::error::EventLoopError> {
 
     event_loop.run_app(&mut app)
 }
+
+// voxel_engine --verify-regions [world dir]
+// voxel_engine --compact-regions [world dir]
+fn run_tool(args: &[String]) {
+    let world_dir = args.get(1).map_or(core::game::WORLD_DIR, |dir| dir.as_str());
+    let region_dir = World::region_dir(world_dir);
+
+    let result = match args[0].as_str() {
+        "--verify-regions" => region::verify_regions(&region_dir).map(|ok| {
+            if !ok {
+                std::process::exit(1);
+            }
+        }),
+        "--compact-regions" => region::compact_regions(&region_dir),
+        other => {
+            eprintln!("Unknown option {}", other);
+            eprintln!("usage: voxel_engine [--verify-regions | --compact-regions] [world dir]");
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}: {}", region_dir.display(), e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,204 @@
+// Small LZ77 block codec in the spirit of LZ4.
+//
+// sequence: token [lit_len+] literals offset(u16) [match_len+]
+// token high nibble = literal count, low nibble = match length - MIN_MATCH,
+// a nibble of 15 continues in extra bytes (255 = keep adding).
+// The last sequence only carries literals.
+
+const MIN_MATCH: usize = 4;
+const HASH_BITS: u32 = 12;
+const MAX_OFFSET: usize = u16::MAX as usize;
+
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() / 2 + 16);
+    let mut table = vec![usize::MAX; 1 << HASH_BITS];
+
+    let mut anchor = 0;
+    let mut i = 0;
+
+    while i + MIN_MATCH <= input.len() {
+        let h = hash(&input[i..i + MIN_MATCH]);
+        let candidate = table[h];
+        table[h] = i;
+
+        let found = candidate != usize::MAX
+            && i - candidate <= MAX_OFFSET
+            && input[candidate..candidate + MIN_MATCH] == input[i..i + MIN_MATCH];
+
+        if !found {
+            i += 1;
+            continue;
+        }
+
+        let mut len = MIN_MATCH;
+        while i + len < input.len() && input[candidate + len] == input[i + len] {
+            len += 1;
+        }
+
+        write_sequence(&mut out, &input[anchor..i], Some((i - candidate, len)));
+        i += len;
+        anchor = i;
+    }
+
+    write_sequence(&mut out, &input[anchor..], None);
+    out
+}
+
+// no sequence expands a byte into more than this many
+const MAX_RATIO: usize = 256;
+
+// None for input that is damaged or does not decode to exactly expected_len
+pub fn decompress(input: &[u8], expected_len: usize) -> Option<Vec<u8>> {
+    // a damaged length must not allocate more than the input can produce
+    let mut out = Vec::with_capacity(expected_len.min(input.len().saturating_mul(MAX_RATIO)));
+    let mut i = 0;
+
+    loop {
+        let token = *input.get(i)?;
+        i += 1;
+
+        let lit_len = read_length(input, &mut i, (token >> 4) as usize)?;
+        out.extend_from_slice(input.get(i..i.checked_add(lit_len)?)?);
+        i += lit_len;
+
+        if i == input.len() {
+            break;
+        }
+
+        let offset = u16::from_le_bytes([*input.get(i)?, *input.get(i + 1)?]) as usize;
+        i += 2;
+        if offset == 0 || offset > out.len() {
+            return None;
+        }
+
+        let match_len = read_length(input, &mut i, (token & 0x0F) as usize)? + MIN_MATCH;
+        if out.len() + match_len > expected_len {
+            return None;
+        }
+        // matches may overlap the bytes they produce
+        let start = out.len() - offset;
+        for k in 0..match_len {
+            let byte = out[start + k];
+            out.push(byte);
+        }
+    }
+
+    if out.len() != expected_len {
+        return None;
+    }
+    Some(out)
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let v = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    (v.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+}
+
+fn write_sequence(out: &mut Vec<u8>, literals: &[u8], m: Option<(usize, usize)>) {
+    let lit_nibble = literals.len().min(15);
+    let match_extra = m.map_or(0, |(_, len)| len - MIN_MATCH);
+    let match_nibble = match_extra.min(15);
+
+    out.push(((lit_nibble << 4) | match_nibble) as u8);
+    write_length(out, literals.len(), lit_nibble);
+    out.extend_from_slice(literals);
+
+    if let Some((offset, _)) = m {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        write_length(out, match_extra, match_nibble);
+    }
+}
+
+fn write_length(out: &mut Vec<u8>, len: usize, nibble: usize) {
+    if nibble < 15 {
+        return;
+    }
+    let mut rest = len - 15;
+    while rest >= 255 {
+        out.push(255);
+        rest -= 255;
+    }
+    out.push(rest as u8);
+}
+
+fn read_length(input: &[u8], i: &mut usize, nibble: usize) -> Option<usize> {
+    let mut len = nibble;
+    if nibble == 15 {
+        loop {
+            let byte = *input.get(*i)?;
+            *i += 1;
+            len += byte as usize;
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+    Some(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(input: &[u8]) {
+        let packed = compress(input);
+        assert_eq!(decompress(&packed, input.len()).as_deref(), Some(input));
+    }
+
+    #[test]
+    fn empty() {
+        round_trip(&[]);
+    }
+
+    #[test]
+    fn incompressible() {
+        // xorshift bytes, no four of them repeat within reach
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let input: Vec<u8> = (0..10_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        round_trip(&input);
+    }
+
+    #[test]
+    fn long_runs() {
+        let mut input = vec![0u8; 100_000];
+        input.extend(vec![3u8; 300]);
+        input.extend(b"end");
+        round_trip(&input);
+        assert!(compress(&input).len() < 1_000);
+    }
+
+    #[test]
+    fn overlapping_matches() {
+        // period shorter than MIN_MATCH, every match copies bytes it wrote
+        let input: Vec<u8> = b"abc".iter().cycle().take(5_000).copied().collect();
+        round_trip(&input);
+
+        // one literal, a match of offset 1 over 20 bytes, then the closing
+        // sequence without literals, spelled out
+        let packed = [0x1F, b'x', 1, 0, 1, 0x00];
+        assert_eq!(decompress(&packed, 21), Some(vec![b'x'; 21]));
+    }
+
+    #[test]
+    fn rejects_damaged_input() {
+        let input: Vec<u8> = (0..2_000).map(|i| (i % 7) as u8).collect();
+        let packed = compress(&input);
+
+        for len in 0..packed.len() {
+            assert_eq!(decompress(&packed[..len], input.len()), None);
+        }
+        assert_eq!(decompress(&packed, input.len() - 1), None);
+        assert_eq!(decompress(&packed, input.len() + 1), None);
+        // offset past the start of the output
+        assert_eq!(decompress(&[0x10, b'a', 5, 0], 5), None);
+        // a huge claimed length does not allocate it
+        assert_eq!(decompress(&packed, usize::MAX), None);
+    }
+}
@@ -1 +1,5 @@
 pub mod timer;
+
+pub mod checksum;
+pub mod compression;
+pub mod random;
@@ -0,0 +1,94 @@
+// Deterministic hashing, rng and value noise. Everything the world generator
+// places is derived from these, so the same seed always builds the same world.
+
+pub fn splitmix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+pub fn hash(seed: u64, x: i32, y: i32, z: i32) -> u64 {
+    let mut h = splitmix(seed ^ (x as u32 as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    h = splitmix(h ^ (y as u32 as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F));
+    splitmix(h ^ (z as u32 as u64).wrapping_mul(0x1656_67B1_9E37_79F9))
+}
+
+// [0, 1)
+pub fn unit(h: u64) -> f32 {
+    (h >> 40) as f32 / (1u64 << 24) as f32
+}
+
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        splitmix(self.state)
+    }
+
+    // [0, 1)
+    pub fn next_f32(&mut self) -> f32 {
+        unit(self.next_u64())
+    }
+
+    // [low, high)
+    pub fn range(&mut self, low: i32, high: i32) -> i32 {
+        if high <= low {
+            return low;
+        }
+        low + (self.next_u64() % (high - low) as u64) as i32
+    }
+}
+
+// smooth 2D value noise in [0, 1]
+pub fn value_noise(seed: u64, x: f32, z: f32) -> f32 {
+    let x0 = x.floor();
+    let z0 = z.floor();
+    let (ix, iz) = (x0 as i32, z0 as i32);
+
+    let fx = smooth(x - x0);
+    let fz = smooth(z - z0);
+
+    let v00 = unit(hash(seed, ix, 0, iz));
+    let v10 = unit(hash(seed, ix + 1, 0, iz));
+    let v01 = unit(hash(seed, ix, 0, iz + 1));
+    let v11 = unit(hash(seed, ix + 1, 0, iz + 1));
+
+    lerp(lerp(v00, v10, fx), lerp(v01, v11, fx), fz)
+}
+
+// octaves of value noise, normalized to [0, 1]
+pub fn fbm(seed: u64, x: f32, z: f32, octaves: u32) -> f32 {
+    let mut sum = 0.0;
+    let mut total = 0.0;
+    let mut amplitude = 1.0;
+    let mut frequency = 1.0;
+
+    for octave in 0..octaves {
+        sum += value_noise(seed.wrapping_add(octave as u64), x * frequency, z * frequency) * amplitude;
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    sum / total
+}
+
+pub fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn smooth(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}